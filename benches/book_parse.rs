@@ -0,0 +1,146 @@
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use market_aggregator::book_management::{Ask, Bid, Order, traded_instruments::Instrument};
+use market_aggregator::exchange_connectivity::{
+    ExchangeType, fast_parse,
+    levels::{self, RawLevel},
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::hint::black_box;
+
+const LEVELS: usize = 1000;
+
+#[derive(Deserialize)]
+struct TypedResponse {
+    result: TypedBook,
+}
+
+#[derive(Deserialize)]
+struct TypedBook {
+    bids: Vec<RawLevel>,
+    asks: Vec<RawLevel>,
+}
+
+fn deribit_frame() -> String {
+    let side = |start: f64, step: f64| {
+        (0..LEVELS)
+            .map(|i| {
+                format!(
+                    "[{:.1},{:.4}]",
+                    start + step * i as f64,
+                    0.1 + i as f64 / 7.0
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+
+    format!(
+        r#"{{"jsonrpc":"2.0","id":10001,"result":{{"timestamp":1700000000000,"state":"open","bids":[{}],"asks":[{}],"instrument_name":"BTC_USDT"}},"usIn":1,"usOut":2}}"#,
+        side(42000.0, -0.5),
+        side(42000.5, 0.5)
+    )
+}
+
+fn binance_frame() -> String {
+    let side = |start: f64, step: f64| {
+        (0..LEVELS)
+            .map(|i| {
+                format!(
+                    r#"["{:.8}","{:.8}"]"#,
+                    start + step * i as f64,
+                    0.1 + i as f64 / 7.0
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+
+    format!(
+        r#"{{"id":"10001","status":200,"result":{{"lastUpdateId":1027024,"bids":[{}],"asks":[{}]}}}}"#,
+        side(42000.0, -0.01),
+        side(42000.01, 0.01)
+    )
+}
+
+/// The decoding previously done by the connectors: a full `Value` tree
+/// indexed by key, with each level converted by hand.
+fn value_tree<T: Order>(text: &str, key: &str, exchange: ExchangeType) -> Vec<T> {
+    let msg: Value = serde_json::from_str(text).unwrap();
+    msg["result"][key]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|elem| {
+            let pair = elem.as_array()?;
+            let (price, qty) = match (&pair[0], &pair[1]) {
+                (Value::String(p), Value::String(q)) => (p.parse().ok()?, q.parse().ok()?),
+                (p, q) => (p.as_f64()?, q.as_f64()?),
+            };
+            Some(T::new(Instrument::BtcUsdt, exchange, qty, price))
+        })
+        .collect()
+}
+
+fn bench_frame(c: &mut Criterion, name: &str, text: &str, exchange: ExchangeType) {
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Bytes(text.len() as u64));
+
+    group.bench_function("value_tree", |b| {
+        b.iter(|| {
+            let bids = value_tree::<Bid>(black_box(text), "bids", exchange);
+            let asks = value_tree::<Ask>(black_box(text), "asks", exchange);
+            black_box((bids, asks))
+        })
+    });
+
+    group.bench_function("typed_serde", |b| {
+        b.iter(|| {
+            let book: TypedResponse = serde_json::from_str(black_box(text)).unwrap();
+            let bids = levels::into_orders::<Bid>(&book.result.bids, exchange, Instrument::BtcUsdt);
+            let asks = levels::into_orders::<Ask>(&book.result.asks, exchange, Instrument::BtcUsdt);
+            black_box((bids, asks))
+        })
+    });
+
+    group.bench_function("fast_path", |b| {
+        b.iter(|| {
+            black_box(fast_parse::parse_book(
+                black_box(text),
+                exchange,
+                Instrument::BtcUsdt,
+            ))
+        })
+    });
+
+    group.bench_function("fast_path_reused_buffers", |b| {
+        let mut bids = Vec::with_capacity(LEVELS);
+        let mut asks = Vec::with_capacity(LEVELS);
+        b.iter(|| {
+            bids.clear();
+            asks.clear();
+            fast_parse::parse_book_into(
+                black_box(text),
+                exchange,
+                Instrument::BtcUsdt,
+                &mut bids,
+                &mut asks,
+            )
+            .unwrap();
+            black_box((bids.len(), asks.len()))
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_deribit(c: &mut Criterion) {
+    bench_frame(c, "deribit_book", &deribit_frame(), ExchangeType::Deribit);
+}
+
+fn bench_binance(c: &mut Criterion) {
+    bench_frame(c, "binance_book", &binance_frame(), ExchangeType::Binance);
+}
+
+criterion_group!(benches, bench_deribit, bench_binance);
+criterion_main!(benches);
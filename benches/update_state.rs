@@ -0,0 +1,70 @@
+use criterion::{Criterion, criterion_group, criterion_main};
+use market_aggregator::book_management::{
+    AggregatedOrderBook, Ask, Bid, Order, traded_instruments::Instrument,
+};
+use market_aggregator::exchange_connectivity::{
+    BidsAsksResult, ExchangeType,
+    clock::{self, ClockSync},
+};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+const LEVELS: usize = 10;
+
+/// Venues with how long each takes to answer a book request.
+const VENUES: [(ExchangeType, Duration); 2] = [
+    (ExchangeType::Deribit, Duration::from_millis(20)),
+    (ExchangeType::Binance, Duration::from_millis(30)),
+];
+
+/// A fake exchange answering with a fixed book after `latency`.
+async fn fake_exchange(exchange: ExchangeType, latency: Duration) -> BidsAsksResult {
+    tokio::time::sleep(latency).await;
+
+    let bids = (0..LEVELS)
+        .map(|i| Bid::new(Instrument::BtcUsdt, exchange, 1.0, 42000.0 - i as f64))
+        .collect();
+    let asks = (0..LEVELS)
+        .map(|i| Ask::new(Instrument::BtcUsdt, exchange, 1.0, 42001.0 + i as f64))
+        .collect();
+    // Stamped as a venue sending no exchange time would be.
+    Ok((bids, asks, ClockSync::new().stamp(None, clock::now())))
+}
+
+fn bench_update_state(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let book = AggregatedOrderBook::new(Instrument::BtcUsdt, &Vec::new());
+
+    let mut group = c.benchmark_group("update_state");
+    group.sample_size(20);
+
+    // One venue after another, as update_state used to.
+    group.bench_function("sequential", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                for (exchange, latency) in VENUES {
+                    book.update_from([(exchange, fake_exchange(exchange, latency))])
+                        .await
+                        .unwrap();
+                }
+            })
+        })
+    });
+
+    group.bench_function("concurrent", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                book.update_from(
+                    VENUES.map(|(exchange, latency)| (exchange, fake_exchange(exchange, latency))),
+                )
+                .await
+                .unwrap();
+            })
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_update_state);
+criterion_main!(benches);
@@ -0,0 +1,158 @@
+//! The running application as a whole.
+//!
+//! A [`MarketAggregator`] owns the exchange connections, the books built
+//! from them and any background work tied to them (such as GUI
+//! refreshes), so that all of it can be stopped with one
+//! [`shutdown`](MarketAggregator::shutdown).
+use std::sync::Arc;
+
+use crate::book_management::arbitrage::ArbitrageDetector;
+use crate::book_management::{AggregatedOrderBook, traded_instruments::Instrument};
+use crate::exchange_connectivity::pool::PoolConfig;
+use crate::exchange_connectivity::shutdown::{CancellationToken, TaskGroup};
+use crate::exchange_connectivity::{Exchange, ExchangeKeys, ExchangeType};
+
+pub struct MarketAggregator {
+    exchanges: Arc<Vec<Exchange>>,
+    books: Vec<Arc<AggregatedOrderBook>>,
+    arbitrage: Arc<ArbitrageDetector>,
+    tasks: TaskGroup,
+}
+
+impl MarketAggregator {
+    /// Aggregate `instruments` across already connected `exchanges`.
+    pub fn new(exchanges: Vec<Exchange>, instruments: &[Instrument]) -> Self {
+        let exchanges = Arc::new(exchanges);
+        let arbitrage = Arc::new(ArbitrageDetector::new());
+        let books = instruments
+            .iter()
+            .map(|instrument| {
+                let book = AggregatedOrderBook::new(*instrument, &exchanges)
+                    .with_arbitrage_detector(Arc::clone(&arbitrage));
+                Arc::new(book)
+            })
+            .collect();
+
+        MarketAggregator {
+            exchanges,
+            books,
+            arbitrage,
+            tasks: TaskGroup::new(CancellationToken::new()),
+        }
+    }
+
+    /// Connect to every venue in `venues` and aggregate `instruments`
+    /// across them. If any venue fails to connect, those already
+    /// connected are shut down again.
+    pub async fn connect(
+        venues: &[ExchangeType],
+        instruments: &[Instrument],
+        keys: &ExchangeKeys,
+    ) -> Result<Self, String> {
+        let mut exchanges: Vec<Exchange> = Vec::new();
+
+        for venue in venues {
+            match Exchange::connect(*venue, keys).await {
+                Some((exchange, _)) => exchanges.push(exchange),
+                None => {
+                    for exchange in &exchanges {
+                        exchange.shutdown().await;
+                    }
+                    return Err(format!("Failed to connect to {:?}", venue));
+                }
+            }
+        }
+
+        Ok(Self::new(exchanges, instruments))
+    }
+
+    /// Like [`connect`](Self::connect), but with a pool of connections
+    /// per venue as configured by `pool`.
+    pub async fn connect_pools(
+        venues: &[ExchangeType],
+        instruments: &[Instrument],
+        keys: &ExchangeKeys,
+        pool: &PoolConfig,
+    ) -> Result<Self, String> {
+        let mut exchanges: Vec<Exchange> = Vec::new();
+
+        for venue in venues {
+            match Exchange::connect_pool(*venue, keys, pool).await {
+                Some(exchange) => exchanges.push(exchange),
+                None => {
+                    for exchange in &exchanges {
+                        exchange.shutdown().await;
+                    }
+                    return Err(format!("Failed to connect a {:?} pool", venue));
+                }
+            }
+        }
+
+        Ok(Self::new(exchanges, instruments))
+    }
+
+    pub fn exchanges(&self) -> &[Exchange] {
+        &self.exchanges
+    }
+
+    pub fn books(&self) -> &[Arc<AggregatedOrderBook>] {
+        &self.books
+    }
+
+    /// Crossed markets found on any of the books.
+    pub fn arbitrage(&self) -> &ArbitrageDetector {
+        &self.arbitrage
+    }
+
+    /// Tasks stopped on shutdown before the exchanges they read from.
+    pub fn tasks(&self) -> &TaskGroup {
+        &self.tasks
+    }
+
+    /// Stop every task, then close every exchange connection.
+    pub async fn shutdown(&self) {
+        log::info!("Shutting down market aggregator.");
+        self.tasks.shutdown().await;
+
+        for exchange in self.exchanges.iter() {
+            exchange.shutdown().await;
+        }
+        log::info!("Market aggregator shut down.");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::MarketAggregator;
+    use crate::book_management::traded_instruments::Instrument;
+    use crate::exchange_connectivity::Exchange;
+    use crate::exchange_connectivity::mock_server::{MockServer, connect_deribit, deribit_fixture};
+    use crate::gui::MyApp;
+
+    #[tokio::test]
+    async fn shutdown_stops_every_task_and_closes_connections() {
+        let server = MockServer::start(deribit_fixture).await;
+        let deribit = connect_deribit(&server).await;
+        let aggregator = MarketAggregator::new(
+            vec![Exchange::Deribit(Arc::clone(&deribit))],
+            &[Instrument::BtcUsdt, Instrument::EthBtc],
+        );
+
+        let _app = MyApp::new(aggregator.books().iter().cloned(), aggregator.tasks());
+        assert_eq!(aggregator.tasks().len(), 2);
+        server.wait_for("public/get_order_book").await;
+
+        tokio::time::timeout(Duration::from_secs(2), aggregator.shutdown())
+            .await
+            .expect("Shutdown should not hang");
+
+        assert!(aggregator.tasks().is_empty());
+        assert!(aggregator.tasks().token().is_cancelled());
+        assert!(deribit.tasks.is_empty());
+        assert!(deribit.tasks.token().is_cancelled());
+        server.wait_for_close().await;
+    }
+}
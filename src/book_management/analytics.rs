@@ -0,0 +1,313 @@
+//! Metrics computed from the consolidated book on every update.
+//!
+//! Anything implementing [`BookAnalytic`] can be registered on an
+//! [`AggregatedOrderBook`](super::AggregatedOrderBook) with
+//! [`register_analytic`](super::AggregatedOrderBook::register_analytic).
+//! Each is computed when the book is refreshed and its value published
+//! alongside the new levels, so readers always see values that match the
+//! book. The usual microstructure metrics are provided here.
+use super::consolidated::{ConsolidatedBook, ConsolidatedLevel};
+
+const BPS: f64 = 10_000.0;
+
+/// A value derived from the consolidated book.
+pub trait BookAnalytic: Send + Sync {
+    /// Name the value is published under. Must be unique per book.
+    fn name(&self) -> String;
+
+    /// The value for `book`, or `None` if it has no meaning for it, e.g.
+    /// a mid with one side empty.
+    fn compute(&self, book: &ConsolidatedBook) -> Option<f64>;
+}
+
+/// Total quantity and volume-weighted price of up to `levels` levels.
+fn weighted<'a>(levels: impl Iterator<Item = &'a ConsolidatedLevel>) -> Option<(f64, f64)> {
+    let (quantity, notional) = levels.fold((0.0, 0.0), |(quantity, notional), level| {
+        (
+            quantity + level.total_quantity(),
+            notional + level.total_quantity() * level.price(),
+        )
+    });
+    (quantity > 0.0).then(|| (quantity, notional / quantity))
+}
+
+/// Halfway between the best bid and offer.
+pub struct Mid;
+
+impl BookAnalytic for Mid {
+    fn name(&self) -> String {
+        "mid".to_string()
+    }
+
+    fn compute(&self, book: &ConsolidatedBook) -> Option<f64> {
+        book.mid()
+    }
+}
+
+/// The best bid and offer weighted by the quantity on the other side, so
+/// the price leans towards the side more likely to give way.
+pub struct Microprice;
+
+impl BookAnalytic for Microprice {
+    fn name(&self) -> String {
+        "microprice".to_string()
+    }
+
+    fn compute(&self, book: &ConsolidatedBook) -> Option<f64> {
+        let (bid, ask) = (book.best_bid()?, book.best_ask()?);
+        let (bid_quantity, ask_quantity) = (bid.total_quantity(), ask.total_quantity());
+        Some(
+            (bid.price() * ask_quantity + ask.price() * bid_quantity)
+                / (bid_quantity + ask_quantity),
+        )
+    }
+}
+
+/// Halfway between the volume-weighted bid and offer over the top
+/// `levels` levels of each side.
+pub struct WeightedMid {
+    pub levels: usize,
+}
+
+impl BookAnalytic for WeightedMid {
+    fn name(&self) -> String {
+        format!("weighted_mid_{}", self.levels)
+    }
+
+    fn compute(&self, book: &ConsolidatedBook) -> Option<f64> {
+        let (_, bid) = weighted(book.bids().take(self.levels))?;
+        let (_, ask) = weighted(book.asks().take(self.levels))?;
+        Some((bid + ask) / 2.0)
+    }
+}
+
+/// Best offer less best bid, in basis points of the mid.
+pub struct SpreadBps;
+
+impl BookAnalytic for SpreadBps {
+    fn name(&self) -> String {
+        "spread_bps".to_string()
+    }
+
+    fn compute(&self, book: &ConsolidatedBook) -> Option<f64> {
+        let spread = book.best_ask()?.price() - book.best_bid()?.price();
+        Some(spread / book.mid()? * BPS)
+    }
+}
+
+/// Quantity on both sides priced within `bps` basis points of the mid.
+pub struct DepthWithin {
+    pub bps: f64,
+}
+
+impl BookAnalytic for DepthWithin {
+    fn name(&self) -> String {
+        format!("depth_within_{}bps", self.bps)
+    }
+
+    fn compute(&self, book: &ConsolidatedBook) -> Option<f64> {
+        let mid = book.mid()?;
+        let (low, high) = (mid * (1.0 - self.bps / BPS), mid * (1.0 + self.bps / BPS));
+
+        let bids = book.bids().take_while(|level| level.price() >= low);
+        let asks = book.asks().take_while(|level| level.price() <= high);
+        Some(
+            bids.chain(asks)
+                .map(ConsolidatedLevel::total_quantity)
+                .sum(),
+        )
+    }
+}
+
+/// What each side of an [`Imbalance`] adds up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImbalanceMeasure {
+    Quantity,
+    /// Quantity times price, so deeper levels count for less on the bid
+    /// side and more on the ask side.
+    Notional,
+}
+
+/// Which levels an [`Imbalance`] counts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImbalanceDepth {
+    /// The best `n` levels of each side.
+    Levels(usize),
+    /// Levels within this many basis points of the mid.
+    Bps(f64),
+}
+
+/// Bids less asks as a fraction of both: 1 when there are only bids, -1
+/// when there are only asks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Imbalance {
+    pub measure: ImbalanceMeasure,
+    pub depth: ImbalanceDepth,
+    /// If set, a level's weight halves for every this many basis points
+    /// it is from the best price on its side.
+    pub half_life_bps: Option<f64>,
+}
+
+impl Default for Imbalance {
+    /// Quantity over the best ten levels, unweighted.
+    fn default() -> Self {
+        Imbalance::levels(10)
+    }
+}
+
+impl Imbalance {
+    /// Quantity over the best `levels` levels of each side.
+    pub fn levels(levels: usize) -> Self {
+        Imbalance {
+            measure: ImbalanceMeasure::Quantity,
+            depth: ImbalanceDepth::Levels(levels),
+            half_life_bps: None,
+        }
+    }
+
+    /// Quantity within `bps` basis points of the mid.
+    pub fn within_bps(bps: f64) -> Self {
+        Imbalance {
+            depth: ImbalanceDepth::Bps(bps),
+            ..Imbalance::levels(0)
+        }
+    }
+
+    pub fn notional(mut self) -> Self {
+        self.measure = ImbalanceMeasure::Notional;
+        self
+    }
+
+    /// Count levels further from the best price for less.
+    pub fn distance_weighted(mut self, half_life_bps: f64) -> Self {
+        self.half_life_bps = Some(half_life_bps);
+        self
+    }
+
+    /// Weighted total of `levels`, which are best first.
+    fn side<'a>(&self, levels: impl Iterator<Item = &'a ConsolidatedLevel>) -> f64 {
+        let mut best = None;
+        levels
+            .map(|level| {
+                let best = *best.get_or_insert(level.price());
+                let value = match self.measure {
+                    ImbalanceMeasure::Quantity => level.total_quantity(),
+                    ImbalanceMeasure::Notional => level.total_quantity() * level.price(),
+                };
+                let weight = self.half_life_bps.map_or(1.0, |half_life| {
+                    let distance = (level.price() / best - 1.0).abs() * BPS;
+                    0.5_f64.powf(distance / half_life)
+                });
+                value * weight
+            })
+            .sum()
+    }
+}
+
+impl BookAnalytic for Imbalance {
+    fn name(&self) -> String {
+        let measure = match self.measure {
+            ImbalanceMeasure::Quantity => "quantity",
+            ImbalanceMeasure::Notional => "notional",
+        };
+        let depth = match self.depth {
+            ImbalanceDepth::Levels(levels) => format!("{}_levels", levels),
+            ImbalanceDepth::Bps(bps) => format!("{}bps", bps),
+        };
+        let weighting = self.half_life_bps.map_or(String::new(), |half_life| {
+            format!("_half_life_{}bps", half_life)
+        });
+        format!("imbalance_{}_{}{}", measure, depth, weighting)
+    }
+
+    fn compute(&self, book: &ConsolidatedBook) -> Option<f64> {
+        let (bids, asks) = match self.depth {
+            ImbalanceDepth::Levels(levels) => (
+                self.side(book.bids().take(levels)),
+                self.side(book.asks().take(levels)),
+            ),
+            ImbalanceDepth::Bps(bps) => {
+                let mid = book.mid()?;
+                let (low, high) = (mid * (1.0 - bps / BPS), mid * (1.0 + bps / BPS));
+                (
+                    self.side(book.bids().take_while(|level| level.price() >= low)),
+                    self.side(book.asks().take_while(|level| level.price() <= high)),
+                )
+            }
+        };
+
+        let total = bids + asks;
+        (total > 0.0).then(|| (bids - asks) / total)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BookAnalytic, DepthWithin, Imbalance, Microprice, Mid, SpreadBps, WeightedMid};
+    use crate::book_management::consolidated::ConsolidatedBook;
+    use crate::exchange_connectivity::ExchangeType;
+
+    #[test]
+    fn built_in_metrics() {
+        let mut book = ConsolidatedBook::new();
+        book.add_bid(ExchangeType::Deribit, 99.0, 3.0);
+        book.add_bid(ExchangeType::Binance, 98.0, 1.0);
+        book.add_ask(ExchangeType::Deribit, 101.0, 1.0);
+        book.add_ask(ExchangeType::Binance, 103.0, 1.0);
+
+        assert_eq!(Mid.compute(&book), Some(100.0));
+        // Three times as much bid as offered pulls the price up.
+        assert_eq!(Microprice.compute(&book), Some((99.0 + 101.0 * 3.0) / 4.0));
+        assert_eq!(
+            WeightedMid { levels: 2 }.compute(&book),
+            Some((98.75 + 102.0) / 2.0)
+        );
+        assert_eq!(SpreadBps.compute(&book), Some(200.0));
+        assert_eq!(DepthWithin { bps: 150.0 }.compute(&book), Some(4.0));
+
+        let mut one_sided = ConsolidatedBook::new();
+        one_sided.add_bid(ExchangeType::Deribit, 99.0, 1.0);
+        assert_eq!(Mid.compute(&one_sided), None);
+    }
+
+    #[test]
+    fn imbalance_family() {
+        let mut book = ConsolidatedBook::new();
+        book.add_bid(ExchangeType::Deribit, 100.0, 3.0);
+        book.add_bid(ExchangeType::Binance, 90.0, 2.0);
+        book.add_ask(ExchangeType::Deribit, 110.0, 1.0);
+        book.add_ask(ExchangeType::Binance, 120.0, 4.0);
+
+        assert_eq!(Imbalance::levels(1).compute(&book), Some(0.5));
+        assert_eq!(Imbalance::levels(2).compute(&book), Some(0.0));
+        assert_eq!(
+            Imbalance::levels(1).notional().compute(&book),
+            Some((300.0 - 110.0) / 410.0)
+        );
+
+        // 5% either side of the 105 mid takes in the best level of each.
+        assert_eq!(Imbalance::within_bps(500.0).compute(&book), Some(0.5));
+
+        // Binance's asks are 909bps from Deribit's, so weigh about half.
+        let weighted = Imbalance::levels(2).distance_weighted(909.09);
+        let bids = 3.0 + 2.0 * 0.5_f64.powf(1000.0 / 909.09);
+        let asks = 1.0 + 4.0 * 0.5_f64.powf((120.0 / 110.0 - 1.0) * 10_000.0 / 909.09);
+        let expected = (bids - asks) / (bids + asks);
+        assert!((weighted.compute(&book).unwrap() - expected).abs() < 1e-12);
+
+        assert_eq!(
+            Imbalance::within_bps(25.0)
+                .notional()
+                .distance_weighted(5.0)
+                .name(),
+            "imbalance_notional_25bps_half_life_5bps"
+        );
+        assert_eq!(Imbalance::default().name(), "imbalance_quantity_10_levels");
+
+        let mut one_sided = ConsolidatedBook::new();
+        one_sided.add_bid(ExchangeType::Deribit, 99.0, 1.0);
+        assert_eq!(Imbalance::levels(5).compute(&one_sided), Some(1.0));
+        assert_eq!(Imbalance::within_bps(5.0).compute(&one_sided), None);
+        assert_eq!(Imbalance::levels(5).compute(&ConsolidatedBook::new()), None);
+    }
+}
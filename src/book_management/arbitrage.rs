@@ -0,0 +1,390 @@
+//! Crossed markets between venues.
+//!
+//! When one venue's bid is above another's ask, buying on the second and
+//! selling on the first locks in the difference. An [`ArbitrageDetector`]
+//! checks every venue pair each time a book is refreshed, tracks how long
+//! each cross lasts and publishes [`ArbitrageEvent`]s as crosses open,
+//! change and close, along with running statistics per venue pair.
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+
+use super::consolidated::{ConsolidatedBook, ConsolidatedLevel};
+use super::fees::FeeSchedule;
+use super::traded_instruments::Instrument;
+use crate::exchange_connectivity::ExchangeType;
+
+const EVENT_CAPACITY: usize = 1024;
+
+/// Buying `instrument` on one venue to sell it on another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VenuePair {
+    pub instrument: Instrument,
+    pub buy_on: ExchangeType,
+    pub sell_on: ExchangeType,
+}
+
+/// A cross between two venues as of the latest update.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Opportunity {
+    pub pair: VenuePair,
+    /// Best ask on the venue bought on.
+    pub ask: f64,
+    /// Best bid on the venue sold on.
+    pub bid: f64,
+    /// Quantity that can be bought below what it sells for, walking both
+    /// venues' levels.
+    pub size: f64,
+    /// Profit on `size` before fees, in the quote currency.
+    pub gross_edge: f64,
+    /// The part of `size` that still profits after taker fees on both
+    /// venues. Zero if fees eat the whole cross.
+    pub net_size: f64,
+    /// Profit on `net_size` after taker fees on both venues.
+    pub net_edge: f64,
+    /// Local time the cross was first seen.
+    pub opened: Duration,
+    /// How long the cross has lasted so far.
+    pub duration: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ArbitrageEvent {
+    Opened(Opportunity),
+    /// Size or edge of an open cross changed.
+    Updated(Opportunity),
+    /// The cross is gone. Holds its last state and final duration.
+    Closed(Opportunity),
+}
+
+/// Running statistics of one venue pair.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ArbitrageStats {
+    pub opened: u64,
+    pub closed: u64,
+    /// Summed duration of closed crosses.
+    pub total_duration: Duration,
+    pub longest: Duration,
+    pub max_size: f64,
+    pub max_gross_edge: f64,
+    pub max_net_edge: f64,
+}
+
+impl ArbitrageStats {
+    /// Mean duration of closed crosses.
+    pub fn mean_duration(&self) -> Option<Duration> {
+        let closed = u32::try_from(self.closed).ok().filter(|n| *n > 0)?;
+        Some(self.total_duration / closed)
+    }
+
+    fn record(&mut self, opportunity: &Opportunity) {
+        self.longest = self.longest.max(opportunity.duration);
+        self.max_size = self.max_size.max(opportunity.size);
+        self.max_gross_edge = self.max_gross_edge.max(opportunity.gross_edge);
+        self.max_net_edge = self.max_net_edge.max(opportunity.net_edge);
+    }
+}
+
+#[derive(Debug, Default)]
+struct DetectorState {
+    open: BTreeMap<VenuePair, Opportunity>,
+    stats: BTreeMap<VenuePair, ArbitrageStats>,
+}
+
+/// Finds crossed markets across venues. One detector can be shared by
+/// the books of several instruments.
+#[derive(Debug)]
+pub struct ArbitrageDetector {
+    state: Mutex<DetectorState>,
+    events: broadcast::Sender<ArbitrageEvent>,
+}
+
+impl Default for ArbitrageDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ArbitrageDetector {
+    pub fn new() -> Self {
+        ArbitrageDetector {
+            state: Mutex::new(DetectorState::default()),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ArbitrageEvent> {
+        self.events.subscribe()
+    }
+
+    /// Crosses open as of the latest update.
+    pub fn open(&self) -> Vec<Opportunity> {
+        self.state.lock().unwrap().open.values().copied().collect()
+    }
+
+    pub fn stats(&self) -> BTreeMap<VenuePair, ArbitrageStats> {
+        self.state.lock().unwrap().stats.clone()
+    }
+
+    /// Check every venue pair in `book`, the consolidated book of
+    /// `instrument` at local time `now`, for crosses.
+    pub fn observe(
+        &self,
+        instrument: Instrument,
+        book: &ConsolidatedBook,
+        fees: &FeeSchedule,
+        now: Duration,
+    ) {
+        let exchanges = book.exchanges();
+        let mut crossed = BTreeMap::new();
+        for buy_on in &exchanges {
+            for sell_on in exchanges.iter().filter(|sell_on| *sell_on != buy_on) {
+                let pair = VenuePair {
+                    instrument,
+                    buy_on: *buy_on,
+                    sell_on: *sell_on,
+                };
+                if let Some(opportunity) = Self::cross(book, fees, pair, now) {
+                    crossed.insert(pair, opportunity);
+                }
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+
+        let gone = state
+            .open
+            .keys()
+            .filter(|pair| pair.instrument == instrument && !crossed.contains_key(pair))
+            .copied()
+            .collect::<Vec<_>>();
+        for pair in gone {
+            let mut opportunity = state.open.remove(&pair).unwrap();
+            opportunity.duration = now.saturating_sub(opportunity.opened);
+
+            let stats = state.stats.entry(pair).or_default();
+            stats.record(&opportunity);
+            stats.closed += 1;
+            stats.total_duration += opportunity.duration;
+            log::info!("Arbitrage closed: {:?}", opportunity);
+            let _ = self.events.send(ArbitrageEvent::Closed(opportunity));
+        }
+
+        for (pair, mut opportunity) in crossed {
+            let stats = state.stats.entry(pair).or_default();
+            let event = match state.open.get(&pair) {
+                Some(previous) => {
+                    opportunity.opened = previous.opened;
+                    opportunity.duration = now.saturating_sub(previous.opened);
+                    let changed = (
+                        previous.size,
+                        previous.gross_edge,
+                        previous.net_size,
+                        previous.net_edge,
+                    ) != (
+                        opportunity.size,
+                        opportunity.gross_edge,
+                        opportunity.net_size,
+                        opportunity.net_edge,
+                    );
+                    changed.then_some(ArbitrageEvent::Updated(opportunity))
+                }
+                None => {
+                    stats.opened += 1;
+                    log::info!("Arbitrage opened: {:?}", opportunity);
+                    Some(ArbitrageEvent::Opened(opportunity))
+                }
+            };
+
+            stats.record(&opportunity);
+            state.open.insert(pair, opportunity);
+            if let Some(event) = event {
+                let _ = self.events.send(event);
+            }
+        }
+    }
+
+    /// Walk `pair.buy_on`'s asks against `pair.sell_on`'s bids for as long
+    /// as the bid is higher. Fees only ever narrow the edge as the walk goes
+    /// deeper, so the net edge stops at the first level fees make a loss.
+    fn cross(
+        book: &ConsolidatedBook,
+        fees: &FeeSchedule,
+        pair: VenuePair,
+        now: Duration,
+    ) -> Option<Opportunity> {
+        let level = |level: &ConsolidatedLevel| (level.price(), level.total_quantity());
+        let asks = book
+            .venue(pair.buy_on)
+            .asks()
+            .map(level)
+            .collect::<Vec<_>>();
+        let bids = book
+            .venue(pair.sell_on)
+            .bids()
+            .map(level)
+            .collect::<Vec<_>>();
+
+        let (&(ask, _), &(bid, _)) = (asks.first()?, bids.first()?);
+        if bid <= ask {
+            return None;
+        }
+
+        let (mut size, mut gross_edge) = (0.0, 0.0);
+        let (mut net_size, mut net_edge, mut profitable) = (0.0, 0.0, true);
+        let (mut asks, mut bids) = (asks.into_iter(), bids.into_iter());
+        let (mut ask_level, mut bid_level) = (asks.next(), bids.next());
+
+        while let (Some((ask_price, ask_left)), Some((bid_price, bid_left))) =
+            (ask_level, bid_level)
+        {
+            if bid_price <= ask_price {
+                break;
+            }
+
+            let quantity = ask_left.min(bid_left);
+            let all_in_bid = fees.all_in_bid(pair.sell_on, pair.instrument, bid_price);
+            let all_in_ask = fees.all_in_ask(pair.buy_on, pair.instrument, ask_price);
+            size += quantity;
+            gross_edge += (bid_price - ask_price) * quantity;
+            profitable &= all_in_bid > all_in_ask;
+            if profitable {
+                net_size += quantity;
+                net_edge += (all_in_bid - all_in_ask) * quantity;
+            }
+
+            ask_level = match ask_left - quantity {
+                left if left > 0.0 => Some((ask_price, left)),
+                _ => asks.next(),
+            };
+            bid_level = match bid_left - quantity {
+                left if left > 0.0 => Some((bid_price, left)),
+                _ => bids.next(),
+            };
+        }
+
+        Some(Opportunity {
+            pair,
+            ask,
+            bid,
+            size,
+            gross_edge,
+            net_size,
+            net_edge,
+            opened: now,
+            duration: Duration::ZERO,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{ArbitrageDetector, ArbitrageEvent, VenuePair};
+    use crate::book_management::consolidated::ConsolidatedBook;
+    use crate::book_management::fees::{FeeSchedule, FeeTier};
+    use crate::book_management::traded_instruments::Instrument;
+    use crate::exchange_connectivity::ExchangeType;
+
+    #[test]
+    fn crosses_are_sized_tracked_and_closed() {
+        let (deribit, binance) = (ExchangeType::Deribit, ExchangeType::Binance);
+        let fees = FeeSchedule::new().with_venue(binance, [FeeTier::new(0.0, 0.0, 0.001)]);
+        let detector = ArbitrageDetector::new();
+        let mut events = detector.subscribe();
+
+        let mut book = ConsolidatedBook::new();
+        book.add_bid(deribit, 100.5, 1.0);
+        book.add_bid(deribit, 100.2, 1.0);
+        book.add_bid(binance, 99.0, 1.0);
+        book.add_ask(binance, 100.0, 1.5);
+        book.add_ask(binance, 100.3, 5.0);
+        book.add_ask(deribit, 101.0, 1.0);
+
+        let now = Duration::from_secs(10);
+        detector.observe(Instrument::BtcUsdt, &book, &fees, now);
+
+        let Ok(ArbitrageEvent::Opened(opportunity)) = events.try_recv() else {
+            panic!("Expected the cross to open");
+        };
+        let pair = VenuePair {
+            instrument: Instrument::BtcUsdt,
+            buy_on: binance,
+            sell_on: deribit,
+        };
+        assert_eq!(opportunity.pair, pair);
+        assert_eq!((opportunity.ask, opportunity.bid), (100.0, 100.5));
+        // 1 at 100.5 and 0.5 at 100.2 against the 100 ask; 100.3 is too high.
+        assert_eq!(opportunity.size, 1.5);
+        assert!((opportunity.gross_edge - 0.6).abs() < 1e-9);
+        assert_eq!(opportunity.net_size, 1.5);
+        assert!((opportunity.net_edge - (0.6 - 0.15)).abs() < 1e-9);
+        assert!(events.try_recv().is_err());
+
+        // Unchanged on the next update, so nothing is published.
+        detector.observe(
+            Instrument::BtcUsdt,
+            &book,
+            &fees,
+            now + Duration::from_secs(1),
+        );
+        assert!(events.try_recv().is_err());
+        assert_eq!(detector.open()[0].duration, Duration::from_secs(1));
+
+        let mut uncrossed = ConsolidatedBook::new();
+        uncrossed.add_bid(deribit, 99.5, 1.0);
+        uncrossed.add_ask(binance, 100.0, 1.0);
+        detector.observe(
+            Instrument::BtcUsdt,
+            &uncrossed,
+            &fees,
+            now + Duration::from_secs(3),
+        );
+
+        let Ok(ArbitrageEvent::Closed(closed)) = events.try_recv() else {
+            panic!("Expected the cross to close");
+        };
+        assert_eq!(closed.duration, Duration::from_secs(3));
+        assert!(detector.open().is_empty());
+
+        let stats = detector.stats()[&pair];
+        assert_eq!((stats.opened, stats.closed), (1, 1));
+        assert_eq!(stats.mean_duration(), Some(Duration::from_secs(3)));
+        assert_eq!(stats.max_size, 1.5);
+    }
+
+    #[test]
+    fn net_edge_stops_where_fees_make_a_loss() {
+        let (deribit, binance) = (ExchangeType::Deribit, ExchangeType::Binance);
+        let fees = FeeSchedule::new().with_venue(binance, [FeeTier::new(0.0, 0.0, 0.001)]);
+        let detector = ArbitrageDetector::new();
+
+        // Buying at 100 costs 100.1 with fees, so the 100.05 bid is only
+        // crossed before them.
+        let mut book = ConsolidatedBook::new();
+        book.add_bid(deribit, 100.5, 1.0);
+        book.add_bid(deribit, 100.05, 1.0);
+        book.add_ask(binance, 100.0, 2.0);
+        detector.observe(Instrument::BtcUsdt, &book, &fees, Duration::ZERO);
+
+        let opportunity = detector.open()[0];
+        assert_eq!(opportunity.size, 2.0);
+        assert!((opportunity.gross_edge - 0.55).abs() < 1e-9);
+        assert_eq!(opportunity.net_size, 1.0);
+        assert!((opportunity.net_edge - 0.4).abs() < 1e-9);
+
+        // With only the 100.05 bid left the cross is gross but not net.
+        let mut book = ConsolidatedBook::new();
+        book.add_bid(deribit, 100.05, 1.0);
+        book.add_ask(binance, 100.0, 2.0);
+        detector.observe(Instrument::BtcUsdt, &book, &fees, Duration::ZERO);
+
+        let opportunity = detector.open()[0];
+        assert_eq!(opportunity.size, 1.0);
+        assert_eq!((opportunity.net_size, opportunity.net_edge), (0.0, 0.0));
+    }
+}
@@ -0,0 +1,314 @@
+//! Consolidated book levels with a per-venue breakdown.
+//!
+//! Levels are keyed by price alone: quoting the same price on two venues
+//! gives one [`ConsolidatedLevel`] holding both quantities, rather than
+//! two entries which may or may not compare equal. Prices are ordered
+//! with [`f64::total_cmp`] so the ordering is total even for NaN.
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::fees::FeeSchedule;
+use super::traded_instruments::Instrument;
+use super::{Ask, Bid, Order};
+use crate::exchange_connectivity::ExchangeType;
+
+/// A price usable as a map key.
+#[derive(Clone, Copy, Debug)]
+pub struct PriceKey(f64);
+
+impl PriceKey {
+    pub fn new(price: f64) -> Self {
+        // -0.0 and 0.0 are the same price but differ under total_cmp.
+        PriceKey(if price == 0.0 { 0.0 } else { price })
+    }
+
+    pub fn price(self) -> f64 {
+        self.0
+    }
+}
+
+impl PartialEq for PriceKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for PriceKey {}
+
+impl PartialOrd for PriceKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PriceKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Everything resting at one price, split by venue.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConsolidatedLevel {
+    price: f64,
+    quantities: BTreeMap<ExchangeType, f64>,
+}
+
+impl ConsolidatedLevel {
+    fn new(price: f64) -> Self {
+        ConsolidatedLevel {
+            price,
+            quantities: BTreeMap::new(),
+        }
+    }
+
+    pub fn price(&self) -> f64 {
+        self.price
+    }
+
+    /// Quantity across every venue.
+    pub fn total_quantity(&self) -> f64 {
+        self.quantities.values().sum()
+    }
+
+    /// Quantity on `exchange`, or zero if it has none at this price.
+    pub fn quantity(&self, exchange: ExchangeType) -> f64 {
+        self.quantities.get(&exchange).copied().unwrap_or(0.0)
+    }
+
+    /// Venues quoting this price with their quantities.
+    pub fn venues(&self) -> impl Iterator<Item = (ExchangeType, f64)> + '_ {
+        self.quantities
+            .iter()
+            .map(|(exchange, quantity)| (*exchange, *quantity))
+    }
+
+    fn add(&mut self, exchange: ExchangeType, quantity: f64) {
+        *self.quantities.entry(exchange).or_default() += quantity;
+    }
+}
+
+/// Both sides of one instrument's book across venues.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConsolidatedBook {
+    bids: BTreeMap<Reverse<PriceKey>, ConsolidatedLevel>,
+    asks: BTreeMap<PriceKey, ConsolidatedLevel>,
+}
+
+impl ConsolidatedBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `quantity` bid at `price` on `exchange`. Levels with a
+    /// non-finite price or non-positive quantity are ignored.
+    pub fn add_bid(&mut self, exchange: ExchangeType, price: f64, quantity: f64) {
+        if Self::is_valid(price, quantity) {
+            let key = PriceKey::new(price);
+            self.bids
+                .entry(Reverse(key))
+                .or_insert_with(|| ConsolidatedLevel::new(key.price()))
+                .add(exchange, quantity);
+        }
+    }
+
+    /// Add `quantity` offered at `price` on `exchange`. Levels with a
+    /// non-finite price or non-positive quantity are ignored.
+    pub fn add_ask(&mut self, exchange: ExchangeType, price: f64, quantity: f64) {
+        if Self::is_valid(price, quantity) {
+            let key = PriceKey::new(price);
+            self.asks
+                .entry(key)
+                .or_insert_with(|| ConsolidatedLevel::new(key.price()))
+                .add(exchange, quantity);
+        }
+    }
+
+    pub fn extend(&mut self, bids: &[Bid], asks: &[Ask]) {
+        for bid in bids {
+            self.add_bid(bid.exchange(), bid.price(), bid.quantity());
+        }
+        for ask in asks {
+            self.add_ask(ask.exchange(), ask.price(), ask.quantity());
+        }
+    }
+
+    /// Bid levels, best (highest) first.
+    pub fn bids(&self) -> impl Iterator<Item = &ConsolidatedLevel> {
+        self.bids.values()
+    }
+
+    /// Ask levels, best (lowest) first.
+    pub fn asks(&self) -> impl Iterator<Item = &ConsolidatedLevel> {
+        self.asks.values()
+    }
+
+    pub fn best_bid(&self) -> Option<&ConsolidatedLevel> {
+        self.bids().next()
+    }
+
+    pub fn best_ask(&self) -> Option<&ConsolidatedLevel> {
+        self.asks().next()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+
+    /// Halfway between the best bid and offer, if both sides have levels.
+    pub fn mid(&self) -> Option<f64> {
+        Some((self.best_bid()?.price() + self.best_ask()?.price()) / 2.0)
+    }
+
+    /// Add every level of `other` to this book.
+    pub fn merge(&mut self, other: &ConsolidatedBook) {
+        for level in other.bids() {
+            for (exchange, quantity) in level.venues() {
+                self.add_bid(exchange, level.price(), quantity);
+            }
+        }
+        for level in other.asks() {
+            for (exchange, quantity) in level.venues() {
+                self.add_ask(exchange, level.price(), quantity);
+            }
+        }
+    }
+
+    /// Only the levels quoted by `exchange`.
+    pub fn venue(&self, exchange: ExchangeType) -> ConsolidatedBook {
+        let mut book = ConsolidatedBook::new();
+        for level in self.bids() {
+            book.add_bid(exchange, level.price(), level.quantity(exchange));
+        }
+        for level in self.asks() {
+            book.add_ask(exchange, level.price(), level.quantity(exchange));
+        }
+        book
+    }
+
+    /// Venues with at least one level in the book.
+    pub fn exchanges(&self) -> BTreeSet<ExchangeType> {
+        self.bids()
+            .chain(self.asks())
+            .flat_map(|level| level.venues().map(|(exchange, _)| exchange))
+            .collect()
+    }
+
+    /// The book with every price multiplied by `factor`, e.g. to quote it
+    /// in another currency.
+    pub fn scaled(&self, factor: f64) -> ConsolidatedBook {
+        let mut book = ConsolidatedBook::new();
+        for level in self.bids() {
+            for (exchange, quantity) in level.venues() {
+                book.add_bid(exchange, level.price() * factor, quantity);
+            }
+        }
+        for level in self.asks() {
+            for (exchange, quantity) in level.venues() {
+                book.add_ask(exchange, level.price() * factor, quantity);
+            }
+        }
+        book
+    }
+
+    /// Per-venue bids and asks of `instrument`, best first, with venues in
+    /// a fixed order within a level.
+    pub fn orders(&self, instrument: Instrument) -> (Vec<Bid>, Vec<Ask>) {
+        let bids = self
+            .bids()
+            .flat_map(|level| {
+                level.venues().map(move |(exchange, quantity)| {
+                    Bid::new(instrument, exchange, quantity, level.price())
+                })
+            })
+            .collect();
+        let asks = self
+            .asks()
+            .flat_map(|level| {
+                level.venues().map(move |(exchange, quantity)| {
+                    Ask::new(instrument, exchange, quantity, level.price())
+                })
+            })
+            .collect();
+        (bids, asks)
+    }
+
+    /// The book as it looks once taker fees are paid: each venue's bids
+    /// lowered and asks raised by its fee on `instrument`. Venues whose
+    /// prices differ before fees may share a level after, and vice versa.
+    pub fn fee_adjusted(&self, instrument: Instrument, fees: &FeeSchedule) -> ConsolidatedBook {
+        let mut book = ConsolidatedBook::new();
+        for level in self.bids() {
+            for (exchange, quantity) in level.venues() {
+                let price = fees.all_in_bid(exchange, instrument, level.price());
+                book.add_bid(exchange, price, quantity);
+            }
+        }
+        for level in self.asks() {
+            for (exchange, quantity) in level.venues() {
+                let price = fees.all_in_ask(exchange, instrument, level.price());
+                book.add_ask(exchange, price, quantity);
+            }
+        }
+        book
+    }
+
+    fn is_valid(price: f64, quantity: f64) -> bool {
+        price.is_finite() && quantity > 0.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::{ConsolidatedBook, PriceKey};
+    use crate::book_management::traded_instruments::Instrument;
+    use crate::book_management::{Bid, Order};
+    use crate::exchange_connectivity::ExchangeType;
+
+    #[test]
+    fn same_price_on_two_venues_is_one_level() {
+        let mut book = ConsolidatedBook::new();
+        book.add_bid(ExchangeType::Deribit, 100.0, 1.0);
+        book.add_bid(ExchangeType::Binance, 100.0, 1.0);
+        book.add_bid(ExchangeType::Binance, 101.0, 0.5);
+        book.add_ask(ExchangeType::Deribit, 102.0, 2.0);
+        book.add_ask(ExchangeType::Deribit, f64::NAN, 2.0);
+        book.add_ask(ExchangeType::Binance, 103.0, 0.0);
+
+        let bids = book.bids().collect::<Vec<_>>();
+        assert_eq!(bids.len(), 2);
+        assert_eq!(bids[0].price(), 101.0);
+        assert_eq!(bids[1].total_quantity(), 2.0);
+        assert_eq!(bids[1].quantity(ExchangeType::Deribit), 1.0);
+        assert_eq!(bids[1].quantity(ExchangeType::Binance), 1.0);
+
+        assert_eq!(book.asks().count(), 1);
+        assert_eq!(
+            book.best_ask().unwrap().quantity(ExchangeType::Binance),
+            0.0
+        );
+
+        let (bids, asks) = book.orders(Instrument::BtcUsdt);
+        assert_eq!(bids.len(), 3);
+        assert_eq!(asks.len(), 1);
+    }
+
+    #[test]
+    fn orderings_are_total_and_agree_with_equality() {
+        assert_eq!(PriceKey::new(-0.0), PriceKey::new(0.0));
+        assert!(PriceKey::new(f64::NAN) > PriceKey::new(f64::INFINITY));
+
+        let deribit = Bid::new(Instrument::BtcUsdt, ExchangeType::Deribit, 1.0, 100.0);
+        let binance = Bid::new(Instrument::BtcUsdt, ExchangeType::Binance, 1.0, 100.0);
+        assert_ne!(deribit, binance);
+        assert_eq!(
+            BTreeSet::from([deribit.clone(), binance.clone(), deribit]).len(),
+            2
+        );
+
+        let better = Bid::new(Instrument::BtcUsdt, ExchangeType::Binance, 1.0, 101.0);
+        assert!(better < binance);
+    }
+}
@@ -0,0 +1,223 @@
+//! What taking liquidity from a book would cost.
+//!
+//! [`ExecutionAnalytics`] walks one side of a book to find the average
+//! price of filling a given size, how far that is from the best price,
+//! and the reverse: how much can be filled before slippage passes a
+//! limit. Slippage is measured in basis points from the best price on the
+//! side being taken, and is positive when the fill is worse than it.
+use std::collections::BTreeMap;
+
+use super::consolidated::{ConsolidatedBook, ConsolidatedLevel};
+use crate::exchange_connectivity::ExchangeType;
+use crate::exchange_connectivity::orders::Side;
+
+const BPS: f64 = 10_000.0;
+
+/// Filling an order against the book.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fill {
+    pub side: Side,
+    /// Size asked for.
+    pub size: f64,
+    /// Size the book could fill, less than `size` if it runs out.
+    pub filled: f64,
+    pub average_price: f64,
+    /// Best price on the side taken.
+    pub best_price: f64,
+    pub slippage_bps: f64,
+}
+
+/// One point of a slippage curve.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SlippagePoint {
+    pub size: f64,
+    pub average_price: f64,
+    pub slippage_bps: f64,
+}
+
+/// Execution analytics of one book snapshot.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExecutionAnalytics {
+    book: ConsolidatedBook,
+}
+
+impl ExecutionAnalytics {
+    pub fn new(book: ConsolidatedBook) -> Self {
+        ExecutionAnalytics { book }
+    }
+
+    /// The same analytics with only the levels of `exchange`.
+    pub fn venue(&self, exchange: ExchangeType) -> ExecutionAnalytics {
+        ExecutionAnalytics::new(self.book.venue(exchange))
+    }
+
+    /// Analytics of each venue in the book.
+    pub fn venues(&self) -> BTreeMap<ExchangeType, ExecutionAnalytics> {
+        self.book
+            .exchanges()
+            .into_iter()
+            .map(|exchange| (exchange, self.venue(exchange)))
+            .collect()
+    }
+
+    /// Levels a `side` order takes, best first: asks for a buy, bids for
+    /// a sell.
+    fn levels(&self, side: Side) -> Box<dyn Iterator<Item = &ConsolidatedLevel> + '_> {
+        match side {
+            Side::Buy => Box::new(self.book.asks()),
+            Side::Sell => Box::new(self.book.bids()),
+        }
+    }
+
+    fn slippage_bps(side: Side, best_price: f64, average_price: f64) -> f64 {
+        match side {
+            Side::Buy => (average_price / best_price - 1.0) * BPS,
+            Side::Sell => (1.0 - average_price / best_price) * BPS,
+        }
+    }
+
+    /// Average price and slippage of a `side` order for `size`, or `None`
+    /// if that side of the book is empty.
+    pub fn fill(&self, side: Side, size: f64) -> Option<Fill> {
+        let best_price = self.levels(side).next()?.price();
+        let (mut filled, mut notional) = (0.0, 0.0);
+
+        for level in self.levels(side) {
+            let quantity = level.total_quantity().min(size - filled);
+            filled += quantity;
+            notional += quantity * level.price();
+            if filled >= size {
+                break;
+            }
+        }
+
+        let average_price = if filled > 0.0 {
+            notional / filled
+        } else {
+            best_price
+        };
+        Some(Fill {
+            side,
+            size,
+            filled,
+            average_price,
+            best_price,
+            slippage_bps: Self::slippage_bps(side, best_price, average_price),
+        })
+    }
+
+    /// Slippage against size for a `side` order, with a point at the end
+    /// of each level. In between, filling `x` more at a level's price `p`
+    /// after `S` for a notional of `N` gives an average of
+    /// `(N + p·x) / (S + x)`, which is not linear in `x`, so use
+    /// [`fill`](Self::fill) for sizes between points.
+    pub fn slippage_curve(&self, side: Side) -> Vec<SlippagePoint> {
+        let Some(best_price) = self.levels(side).next().map(ConsolidatedLevel::price) else {
+            return Vec::new();
+        };
+        let (mut size, mut notional) = (0.0, 0.0);
+
+        self.levels(side)
+            .map(|level| {
+                size += level.total_quantity();
+                notional += level.total_quantity() * level.price();
+                let average_price = notional / size;
+                SlippagePoint {
+                    size,
+                    average_price,
+                    slippage_bps: Self::slippage_bps(side, best_price, average_price),
+                }
+            })
+            .collect()
+    }
+
+    /// Largest `side` order whose slippage stays within `bps`.
+    pub fn max_size_within(&self, side: Side, bps: f64) -> f64 {
+        let Some(best_price) = self.levels(side).next().map(ConsolidatedLevel::price) else {
+            return 0.0;
+        };
+        let limit = match side {
+            Side::Buy => best_price * (1.0 + bps / BPS),
+            Side::Sell => best_price * (1.0 - bps / BPS),
+        };
+        let (mut size, mut notional) = (0.0, 0.0);
+
+        for level in self.levels(side) {
+            let within = match side {
+                Side::Buy => level.price() <= limit,
+                Side::Sell => level.price() >= limit,
+            };
+            if within {
+                size += level.total_quantity();
+                notional += level.total_quantity() * level.price();
+                continue;
+            }
+
+            // Take just enough of this level to bring the average to the
+            // limit: (notional + price * x) / (size + x) = limit.
+            let partial = (limit * size - notional) / (level.price() - limit);
+            return size + partial.clamp(0.0, level.total_quantity());
+        }
+
+        size
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ExecutionAnalytics;
+    use crate::book_management::consolidated::ConsolidatedBook;
+    use crate::exchange_connectivity::ExchangeType;
+    use crate::exchange_connectivity::orders::Side;
+
+    fn analytics() -> ExecutionAnalytics {
+        let mut book = ConsolidatedBook::new();
+        book.add_ask(ExchangeType::Deribit, 100.0, 1.0);
+        book.add_ask(ExchangeType::Binance, 100.0, 1.0);
+        book.add_ask(ExchangeType::Binance, 101.0, 2.0);
+        book.add_bid(ExchangeType::Deribit, 99.0, 1.0);
+        book.add_bid(ExchangeType::Deribit, 98.0, 1.0);
+        ExecutionAnalytics::new(book)
+    }
+
+    #[test]
+    fn fills_walk_the_book() {
+        let analytics = analytics();
+
+        let fill = analytics.fill(Side::Buy, 3.0).unwrap();
+        assert_eq!(fill.filled, 3.0);
+        assert!((fill.average_price - 301.0 / 3.0).abs() < 1e-9);
+        assert!((fill.slippage_bps - 100.0 / 3.0).abs() < 1e-9);
+
+        let fill = analytics.fill(Side::Sell, 5.0).unwrap();
+        assert_eq!(fill.filled, 2.0);
+        assert_eq!(fill.average_price, 98.5);
+
+        // Binance alone starts at the same price with less depth.
+        let binance = analytics.venue(ExchangeType::Binance);
+        let fill = binance.fill(Side::Buy, 3.0).unwrap();
+        assert!((fill.average_price - 302.0 / 3.0).abs() < 1e-9);
+        assert!(binance.fill(Side::Sell, 1.0).is_none());
+        assert_eq!(analytics.venues().len(), 2);
+    }
+
+    #[test]
+    fn curve_and_max_size_agree() {
+        let analytics = analytics();
+
+        let curve = analytics.slippage_curve(Side::Buy);
+        assert_eq!(curve.len(), 2);
+        assert_eq!((curve[0].size, curve[0].slippage_bps), (2.0, 0.0));
+        assert_eq!(curve[1].size, 4.0);
+        assert!((curve[1].slippage_bps - 50.0).abs() < 1e-9);
+
+        // 2 at 100 plus x at 101 averages 100.25 (25bps) once x is 2/3.
+        let size = analytics.max_size_within(Side::Buy, 25.0);
+        assert!((size - (2.0 + 2.0 / 3.0)).abs() < 1e-9);
+        let fill = analytics.fill(Side::Buy, size).unwrap();
+        assert!((fill.slippage_bps - 25.0).abs() < 1e-9);
+
+        assert_eq!(analytics.max_size_within(Side::Buy, 1000.0), 4.0);
+        assert_eq!(analytics.max_size_within(Side::Sell, 0.0), 1.0);
+    }
+}
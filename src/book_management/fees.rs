@@ -0,0 +1,222 @@
+//! Maker and taker fees by venue and instrument.
+//!
+//! Prices on venues charging different fees aren't directly comparable. A
+//! [`FeeSchedule`] holds each venue's fee tiers so levels can be compared
+//! all-in: a bid is worth its price less the taker fee to whoever sells
+//! into it, and an ask costs its price plus the taker fee.
+use std::collections::BTreeMap;
+
+use super::traded_instruments::Instrument;
+use crate::exchange_connectivity::ExchangeType;
+
+/// Whether a fill added liquidity to the book or took it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+/// Fees charged once traded volume reaches `min_volume`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FeeTier {
+    /// 30-day volume, in the quote currency, from which the tier applies.
+    pub min_volume: f64,
+    /// Fee on resting fills as a fraction of notional. Negative for a rebate.
+    pub maker: f64,
+    /// Fee on aggressive fills as a fraction of notional.
+    pub taker: f64,
+}
+
+impl FeeTier {
+    pub fn new(min_volume: f64, maker: f64, taker: f64) -> Self {
+        FeeTier {
+            min_volume,
+            maker,
+            taker,
+        }
+    }
+
+    pub fn rate(&self, liquidity: Liquidity) -> f64 {
+        match liquidity {
+            Liquidity::Maker => self.maker,
+            Liquidity::Taker => self.taker,
+        }
+    }
+}
+
+/// Fee tiers of each venue, optionally overridden per instrument, along
+/// with the volume deciding which tier applies.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FeeSchedule {
+    venues: BTreeMap<ExchangeType, Vec<FeeTier>>,
+    instruments: BTreeMap<(ExchangeType, Instrument), Vec<FeeTier>>,
+    volumes: BTreeMap<ExchangeType, f64>,
+}
+
+impl FeeSchedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tiers for every instrument on `exchange` without tiers of its own.
+    pub fn with_venue(
+        mut self,
+        exchange: ExchangeType,
+        tiers: impl IntoIterator<Item = FeeTier>,
+    ) -> Self {
+        self.venues.insert(exchange, Self::sorted(tiers));
+        self
+    }
+
+    /// Tiers for `instrument` on `exchange`, in place of the venue's.
+    pub fn with_instrument(
+        mut self,
+        exchange: ExchangeType,
+        instrument: Instrument,
+        tiers: impl IntoIterator<Item = FeeTier>,
+    ) -> Self {
+        self.instruments
+            .insert((exchange, instrument), Self::sorted(tiers));
+        self
+    }
+
+    pub fn with_volume(mut self, exchange: ExchangeType, volume: f64) -> Self {
+        self.set_volume(exchange, volume);
+        self
+    }
+
+    /// Set the 30-day volume traded on `exchange`, which picks its tier.
+    pub fn set_volume(&mut self, exchange: ExchangeType, volume: f64) {
+        self.volumes.insert(exchange, volume);
+    }
+
+    pub fn volume(&self, exchange: ExchangeType) -> f64 {
+        self.volumes.get(&exchange).copied().unwrap_or(0.0)
+    }
+
+    /// The tier `instrument` on `exchange` is charged at, or `None` if the
+    /// schedule has no fees for it. Below the lowest tier's volume the
+    /// lowest tier applies.
+    pub fn tier(&self, exchange: ExchangeType, instrument: Instrument) -> Option<FeeTier> {
+        let tiers = self
+            .instruments
+            .get(&(exchange, instrument))
+            .or_else(|| self.venues.get(&exchange))?;
+        let volume = self.volume(exchange);
+
+        tiers
+            .iter()
+            .rev()
+            .find(|tier| tier.min_volume <= volume)
+            .or(tiers.first())
+            .copied()
+    }
+
+    /// Fee as a fraction of notional, zero if none is configured.
+    pub fn rate(
+        &self,
+        exchange: ExchangeType,
+        instrument: Instrument,
+        liquidity: Liquidity,
+    ) -> f64 {
+        self.tier(exchange, instrument)
+            .map_or(0.0, |tier| tier.rate(liquidity))
+    }
+
+    /// What selling into a bid at `price` yields per unit after taker fees.
+    pub fn all_in_bid(&self, exchange: ExchangeType, instrument: Instrument, price: f64) -> f64 {
+        price * (1.0 - self.rate(exchange, instrument, Liquidity::Taker))
+    }
+
+    /// What lifting an ask at `price` costs per unit after taker fees.
+    pub fn all_in_ask(&self, exchange: ExchangeType, instrument: Instrument, price: f64) -> f64 {
+        price * (1.0 + self.rate(exchange, instrument, Liquidity::Taker))
+    }
+
+    fn sorted(tiers: impl IntoIterator<Item = FeeTier>) -> Vec<FeeTier> {
+        let mut tiers = tiers.into_iter().collect::<Vec<_>>();
+        tiers.sort_by(|a, b| a.min_volume.total_cmp(&b.min_volume));
+        tiers
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FeeSchedule, FeeTier, Liquidity};
+    use crate::book_management::consolidated::ConsolidatedBook;
+    use crate::book_management::traded_instruments::Instrument;
+    use crate::exchange_connectivity::ExchangeType;
+
+    fn schedule() -> FeeSchedule {
+        FeeSchedule::new()
+            .with_venue(
+                ExchangeType::Binance,
+                [
+                    FeeTier::new(1_000_000.0, 0.0009, 0.0009),
+                    FeeTier::new(0.0, 0.001, 0.001),
+                ],
+            )
+            .with_instrument(
+                ExchangeType::Binance,
+                Instrument::BtcUsdt,
+                [FeeTier::new(0.0, 0.0, 0.0)],
+            )
+            .with_venue(ExchangeType::Deribit, [FeeTier::new(0.0, -0.0001, 0.0005)])
+    }
+
+    #[test]
+    fn tiers_follow_volume_and_instrument_overrides() {
+        let mut fees = schedule();
+        let binance = ExchangeType::Binance;
+
+        assert_eq!(
+            fees.rate(binance, Instrument::EthBtc, Liquidity::Taker),
+            0.001
+        );
+        fees.set_volume(binance, 2_000_000.0);
+        assert_eq!(
+            fees.rate(binance, Instrument::EthBtc, Liquidity::Taker),
+            0.0009
+        );
+        assert_eq!(
+            fees.rate(binance, Instrument::BtcUsdt, Liquidity::Taker),
+            0.0
+        );
+
+        let deribit = ExchangeType::Deribit;
+        assert_eq!(
+            fees.rate(deribit, Instrument::EthBtc, Liquidity::Maker),
+            -0.0001
+        );
+        assert!(
+            FeeSchedule::new()
+                .tier(deribit, Instrument::EthBtc)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn fees_reorder_the_all_in_book() {
+        let fees = schedule();
+        let mut book = ConsolidatedBook::new();
+        book.add_bid(ExchangeType::Deribit, 100.0, 1.0);
+        book.add_bid(ExchangeType::Binance, 99.99, 1.0);
+        book.add_ask(ExchangeType::Deribit, 101.0, 1.0);
+        book.add_ask(ExchangeType::Binance, 101.02, 1.0);
+
+        let all_in = book.fee_adjusted(Instrument::BtcUsdt, &fees);
+
+        // Binance charges nothing on BTC_USDT, so it has the better bid all-in.
+        let best_bid = all_in.best_bid().unwrap();
+        assert_eq!(best_bid.price(), 99.99);
+        assert_eq!(best_bid.quantity(ExchangeType::Binance), 1.0);
+        assert_eq!(
+            all_in.bids().nth(1).unwrap().price(),
+            100.0 * (1.0 - 0.0005)
+        );
+
+        let best_ask = all_in.best_ask().unwrap();
+        assert_eq!(best_ask.price(), 101.02);
+        assert_eq!(all_in.asks().count(), 2);
+    }
+}
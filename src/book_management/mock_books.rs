@@ -0,0 +1,53 @@
+//! Canned venue fetches standing in for exchanges in book tests.
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::fees::FeeSchedule;
+use super::traded_instruments::Instrument;
+use super::{AggregatedOrderBook, Ask, Bid, Order};
+use crate::exchange_connectivity::clock::{self, UpdateTimestamps};
+use crate::exchange_connectivity::{BidsAsksResult, ExchangeType};
+
+/// A venue's bids and asks as `(price, quantity)`.
+pub type Levels = (Vec<(f64, f64)>, Vec<(f64, f64)>);
+
+/// A fetch answering with `bids` and `asks`, read off the socket at
+/// `received` and carrying no exchange timestamp.
+pub fn fetched_at(bids: Vec<Bid>, asks: Vec<Ask>, received: Duration) -> BidsAsksResult {
+    Ok((
+        bids,
+        asks,
+        UpdateTimestamps {
+            exchange: None,
+            received,
+            normalised: received,
+        },
+    ))
+}
+
+/// A book of `instrument` charging `fees`, updated once with each venue's
+/// levels as of now.
+pub async fn book_with(
+    instrument: Instrument,
+    venues: Vec<(ExchangeType, Levels)>,
+    fees: FeeSchedule,
+) -> Arc<AggregatedOrderBook> {
+    let book = AggregatedOrderBook::new(instrument, &Vec::new()).with_fees(fees);
+    let received = clock::now();
+    let fetches = venues.into_iter().map(|(exchange, (bids, asks))| {
+        let levels = async move {
+            fetched_at(
+                bids.iter()
+                    .map(|(price, qty)| Bid::new(instrument, exchange, *qty, *price))
+                    .collect(),
+                asks.iter()
+                    .map(|(price, qty)| Ask::new(instrument, exchange, *qty, *price))
+                    .collect(),
+                received,
+            )
+        };
+        (exchange, levels)
+    });
+    book.update_from(fetches).await.unwrap();
+    Arc::new(book)
+}
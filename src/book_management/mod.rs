@@ -1,20 +1,53 @@
+pub mod analytics;
+pub mod arbitrage;
+pub mod consolidated;
+pub mod execution;
+pub mod fees;
+#[cfg(test)]
+pub(crate) mod mock_books;
 pub mod multibook;
+pub mod normalise;
+pub mod order_flow;
+pub mod snapshot;
+pub mod synthetic;
 pub mod traded_instruments;
-
-use std::{fmt::Write, time::Duration};
+pub mod triangular;
+pub mod venue_book;
+
+use analytics::{BookAnalytic, Imbalance};
+use arbitrage::ArbitrageDetector;
+use arc_swap::ArcSwap;
+use consolidated::{ConsolidatedBook, ConsolidatedLevel, PriceKey};
+use execution::ExecutionAnalytics;
+use fees::FeeSchedule;
+use futures_util::future::join_all;
+use order_flow::OrderFlow;
+use snapshot::BookSnapshot;
+use std::{future::Future, time::Duration};
 use tokio::sync::Mutex;
 use traded_instruments::Instrument;
+use venue_book::{SubBook, VenueErrors, VenueFreshness};
 
-use crate::exchange_connectivity::{ConnectedExchangeForBook, Exchange, ExchangeType};
+use crate::exchange_connectivity::clock::{self, FeedLatency, UpdateTimestamps};
+use crate::exchange_connectivity::{BidsAsksResult, Exchange, ExchangeType};
 
-use std::{cmp::Ordering, collections::BTreeSet, sync::Arc};
+use std::{cmp::Ordering, collections::BTreeMap, sync::Arc};
+
+/// Age after which a venue's levels are left out of the consolidated book.
+const STALE_AFTER: Duration = Duration::from_secs(5);
 
 pub struct AggregatedOrderBook {
     instrument: Instrument,
     subscriptions: Vec<Exchange>,
-    last_msg: Arc<Mutex<Duration>>,
-    bids: Arc<Mutex<BTreeSet<Bid>>>,
-    asks: Arc<Mutex<BTreeSet<Ask>>>,
+    stale_after: Duration,
+    fees: FeeSchedule,
+    arbitrage: Option<Arc<ArbitrageDetector>>,
+    order_flow: Option<Arc<OrderFlow>>,
+    analytics: std::sync::Mutex<Vec<Arc<dyn BookAnalytic>>>,
+    /// Held by writers while they build the next snapshot, so updates
+    /// apply one after another. Readers never take it.
+    updating: Mutex<()>,
+    snapshot: ArcSwap<BookSnapshot>,
 }
 
 impl PartialEq for AggregatedOrderBook {
@@ -31,110 +64,239 @@ impl AggregatedOrderBook {
         }
 
         AggregatedOrderBook {
-            instrument: instrument,
+            instrument,
             subscriptions: new_subs,
-            last_msg: Arc::new(Mutex::new(Duration::from_secs(0))),
-            bids: Arc::new(Mutex::new(BTreeSet::new())),
-            asks: Arc::new(Mutex::new(BTreeSet::new())),
+            stale_after: STALE_AFTER,
+            fees: FeeSchedule::new(),
+            arbitrage: None,
+            order_flow: None,
+            analytics: std::sync::Mutex::new(Vec::new()),
+            updating: Mutex::new(()),
+            snapshot: ArcSwap::from_pointee(BookSnapshot::empty(instrument)),
         }
     }
 
-    pub async fn update_state(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let mut bids = self.bids.lock().await;
-        let mut asks = self.asks.lock().await;
-        *bids = BTreeSet::new();
-        *asks = BTreeSet::new();
+    /// Leave a venue out of the consolidated book once its levels are
+    /// older than `stale_after`.
+    pub fn with_stale_after(mut self, stale_after: Duration) -> Self {
+        self.stale_after = stale_after;
+        self
+    }
 
-        for _sub in &self.subscriptions {
-            match _sub {
-                Exchange::Binance(binance) => {
-                    let binance = Arc::clone(binance);
+    /// Fees used for the [`all_in`](Self::all_in) view of the book.
+    pub fn with_fees(mut self, fees: FeeSchedule) -> Self {
+        self.fees = fees;
+        self
+    }
 
-                    let (new_bids, new_asks, time) =
-                        binance.pull_bids_asks(10, self.instrument).await?;
+    /// Check for crossed venues with `detector` on every update.
+    pub fn with_arbitrage_detector(mut self, detector: Arc<ArbitrageDetector>) -> Self {
+        self.arbitrage = Some(detector);
+        self
+    }
 
-                    for bid in new_bids {
-                        bids.insert(bid);
-                    }
+    /// Track order flow imbalance of the consolidated book and each
+    /// venue's sub-book with `order_flow` on every update.
+    pub fn with_order_flow(mut self, order_flow: Arc<OrderFlow>) -> Self {
+        self.order_flow = Some(order_flow);
+        self
+    }
 
-                    for ask in new_asks {
-                        asks.insert(ask);
-                    }
+    /// Compute `analytic` on every update from now on. Replaces any
+    /// analytic already registered under the same name.
+    pub fn register_analytic(&self, analytic: impl BookAnalytic + 'static) {
+        let mut analytics = self.analytics.lock().unwrap();
+        analytics.retain(|registered| registered.name() != analytic.name());
+        analytics.push(Arc::new(analytic));
+    }
 
-                    *self.last_msg.lock().await = time;
-                }
-                Exchange::Deribit(deribit) => {
-                    let deribit = Arc::clone(deribit);
+    pub fn instrument(&self) -> Instrument {
+        self.instrument
+    }
 
-                    let (new_bids, new_asks, time) =
-                        deribit.pull_bids_asks(10, self.instrument).await?;
+    pub fn fees(&self) -> &FeeSchedule {
+        &self.fees
+    }
 
-                    for bid in new_bids {
-                        bids.insert(bid);
-                    }
+    /// The book as of the latest update. Never waits, even while an
+    /// update is being applied, and everything read from one snapshot
+    /// comes from the same update.
+    pub fn snapshot(&self) -> Arc<BookSnapshot> {
+        self.snapshot.load_full()
+    }
 
-                    for ask in new_asks {
-                        asks.insert(ask);
-                    }
+    /// Copy of the bids and asks on every venue, best first.
+    pub fn levels(&self) -> (Vec<Bid>, Vec<Ask>) {
+        self.snapshot().levels()
+    }
+
+    /// Copy of the book with each price level's per-venue breakdown.
+    pub fn consolidated(&self) -> ConsolidatedBook {
+        self.snapshot().book().clone()
+    }
+
+    /// Fill prices and slippage of the current book, consolidated or per
+    /// venue.
+    pub fn execution(&self) -> ExecutionAnalytics {
+        ExecutionAnalytics::new(self.consolidated())
+    }
+
+    /// The book with every level priced after taker fees, which is what
+    /// taking it would actually cost or yield on each venue.
+    pub fn all_in(&self) -> ConsolidatedBook {
+        self.snapshot()
+            .book()
+            .fee_adjusted(self.instrument, &self.fees)
+    }
+
+    /// Best all-in bid and offer.
+    pub fn all_in_bbo(&self) -> (Option<ConsolidatedLevel>, Option<ConsolidatedLevel>) {
+        let book = self.all_in();
+        (book.best_bid().cloned(), book.best_ask().cloned())
+    }
 
-                    *self.last_msg.lock().await = time;
+    /// Refresh every venue's sub-book, then rebuild the consolidated book
+    /// from the venues that are fresh.
+    ///
+    /// A venue that fails keeps its last levels but is left out of the
+    /// consolidated book until it next refreshes. Every venue is tried
+    /// either way, and the failures are returned together.
+    pub async fn update_state(&self) -> Result<(), VenueErrors> {
+        self.update_from(self.subscriptions.iter().map(|subscription| {
+            (
+                subscription.exchange_type(),
+                subscription.pull_bids_asks(10, self.instrument),
+            )
+        }))
+        .await
+    }
+
+    /// Like [`update_state`](Self::update_state), but with the levels of
+    /// each venue read from the paired future rather than a subscription.
+    ///
+    /// Every future is awaited concurrently, so a refresh takes as long as
+    /// the slowest venue. The new state is built without holding the lock
+    /// and swapped in at once; readers see either the old state or the new
+    /// one in full. If two refreshes overlap, the one finishing last wins.
+    pub async fn update_from<F>(
+        &self,
+        fetches: impl IntoIterator<Item = (ExchangeType, F)>,
+    ) -> Result<(), VenueErrors>
+    where
+        F: Future<Output = BidsAsksResult>,
+    {
+        let (exchanges, fetches): (Vec<_>, Vec<_>) = fetches.into_iter().unzip();
+        let results = join_all(fetches).await;
+
+        let mut errors = VenueErrors::default();
+        let _updating = self.updating.lock().await;
+        let previous = self.snapshot();
+        let mut sub_books = previous.sub_books().clone();
+
+        for (exchange, result) in exchanges.into_iter().zip(results) {
+            let sub_book = sub_books.entry(exchange).or_default();
+            match result {
+                Ok((bids, asks, times)) => sub_book.record_success(bids, asks, times),
+                Err(err) => {
+                    log::warn!(
+                        "Failed to refresh {} on {:?}: {}",
+                        self.instrument,
+                        exchange,
+                        err
+                    );
+                    sub_book.record_failure(err.to_string());
+                    errors.insert(exchange, err.to_string());
                 }
             }
         }
 
-        Ok(())
-    }
-
-    pub async fn pretty_print(&self) -> Result<String, Box<dyn std::error::Error>> {
-        let imbalance = self.imbalance().await;
-        let bids = self.bids.lock().await;
-        let asks = self.asks.lock().await;
-
-        let mut output = String::new();
-        write!(output, "Instrument: {}\n\n", self.instrument)?;
-        write!(output, "Bid/Ask Imbalance: {:?}\n\n", imbalance)?;
-        write!(
-            output,
-            "{:<40} {:<40}\n",
-            "Bids (Exchange - Price - Qty)", "Asks (Exchange - Price - Qty)"
-        )?;
-        write!(output, "{:-<80}\n", "")?;
-
-        let max_rows = std::cmp::max(bids.len(), asks.len());
-        let mut bids_iter = bids.iter();
-        let mut asks_iter = asks.iter();
-
-        for _ in 0..max_rows {
-            let bid = bids_iter.next().map_or("".to_string(), |b| {
-                format!("{:#?} - {:.6} - {:.6}", b.exchange, b.price, b.quantity)
-            });
-            let ask = asks_iter.next().map_or("".to_string(), |a| {
-                format!("{:#?} - {:.6} - {:.6}", a.exchange, a.price, a.quantity)
-            });
-
-            write!(output, "{:<40} {:<40}\n", bid, ask)?;
+        let now = clock::now();
+        let book = venue_book::consolidate(&sub_books, now, self.stale_after);
+        let analytics = self
+            .analytics
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|analytic| Some((analytic.name(), analytic.compute(&book)?)))
+            .collect();
+        let snapshot = Arc::new(previous.next(sub_books, book, analytics, now));
+        self.snapshot.store(Arc::clone(&snapshot));
+
+        if let Some(detector) = &self.arbitrage {
+            detector.observe(self.instrument, snapshot.book(), &self.fees, now);
         }
 
-        Ok(output)
+        if let Some(order_flow) = &self.order_flow {
+            order_flow.observe(None, snapshot.book(), now);
+            for (exchange, sub_book) in snapshot.sub_books() {
+                if sub_book.updated().is_some() {
+                    let mut book = ConsolidatedBook::new();
+                    book.extend(sub_book.bids(), sub_book.asks());
+                    order_flow.observe(Some(*exchange), &book, now);
+                }
+            }
+        }
+
+        errors.into_result()
     }
 
-    pub async fn imbalance(&self) -> f64 {
-        let mut bid_total_qty: f64 = 0.0;
-        let mut ask_total_qty: f64 = 0.0;
+    /// Value of each registered analytic as of the last update, leaving
+    /// out any with no value for that book.
+    pub fn analytics(&self) -> BTreeMap<String, f64> {
+        self.snapshot().analytics().clone()
+    }
 
-        for bid in &*self.bids.lock().await {
-            bid_total_qty += bid.price;
-        }
+    pub fn analytic(&self, name: &str) -> Option<f64> {
+        self.snapshot().analytic(name)
+    }
 
-        for ask in &*self.asks.lock().await {
-            ask_total_qty += ask.price;
-        }
+    /// Copy of each venue's sub-book.
+    pub fn sub_books(&self) -> BTreeMap<ExchangeType, SubBook> {
+        self.snapshot().sub_books().clone()
+    }
+
+    /// Age after which a venue is left out of the consolidated book.
+    pub fn stale_after(&self) -> Duration {
+        self.stale_after
+    }
+
+    /// Freshness of each venue's sub-book right now.
+    pub fn venue_freshness(&self) -> BTreeMap<ExchangeType, VenueFreshness> {
+        self.snapshot()
+            .venue_freshness(clock::now(), self.stale_after)
+    }
 
-        bid_total_qty / ask_total_qty
+    pub fn pretty_print(&self) -> Result<String, Box<dyn std::error::Error>> {
+        self.snapshot().pretty_print()
     }
 
-    pub async fn last_time(&self) -> Duration {
-        *self.last_msg.lock().await
+    /// `imbalance` of the consolidated book, or `None` if it has no
+    /// levels to count.
+    pub fn imbalance(&self, imbalance: &Imbalance) -> Option<f64> {
+        self.snapshot().imbalance(imbalance)
+    }
+
+    /// `imbalance` of each venue's levels in the consolidated book.
+    pub fn venue_imbalances(&self, imbalance: &Imbalance) -> BTreeMap<ExchangeType, f64> {
+        self.snapshot().venue_imbalances(imbalance)
+    }
+
+    /// Most recent normalised update time across all venues.
+    pub fn last_time(&self) -> Duration {
+        self.snapshot().last_time()
+    }
+
+    /// Timestamps of the last update received from each venue.
+    pub fn venue_timestamps(&self) -> BTreeMap<ExchangeType, UpdateTimestamps> {
+        self.snapshot().venue_timestamps()
+    }
+
+    /// One-way feed latency of each subscribed venue that has been measured.
+    pub fn feed_latency(&self) -> BTreeMap<ExchangeType, FeedLatency> {
+        self.subscriptions
+            .iter()
+            .filter_map(|sub| Some((sub.exchange_type(), sub.feed_latency()?)))
+            .collect()
     }
 }
 
@@ -146,7 +308,7 @@ pub trait Order {
     fn exchange(&self) -> ExchangeType;
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Bid {
     instrument: Instrument,
     exchange: ExchangeType,
@@ -156,52 +318,36 @@ pub struct Bid {
 
 impl PartialEq for Bid {
     fn eq(&self, other: &Self) -> bool {
-        self.price == other.price
+        self.cmp(other) == Ordering::Equal
     }
 }
 
 impl Eq for Bid {}
 
 impl PartialOrd for Bid {
-    fn ge(&self, other: &Self) -> bool {
-        self.price >= other.price
-    }
-
-    fn gt(&self, other: &Self) -> bool {
-        self.price > other.price
-    }
-
-    fn lt(&self, other: &Self) -> bool {
-        self.price < other.price
-    }
-
-    fn le(&self, other: &Self) -> bool {
-        self.price <= other.price
-    }
-
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        match self.price - other.price {
-            x if x > 0.0 => Some(Ordering::Less),
-            x if x == 0.0 => self.quantity.partial_cmp(&other.quantity),
-            x if x < 0.0 => Some(Ordering::Greater),
-            _ => None,
-        }
+        Some(self.cmp(other))
     }
 }
 
+/// Best (highest) price first, then by venue and quantity.
 impl Ord for Bid {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap_or(Ordering::Equal)
+        PriceKey::new(other.price)
+            .cmp(&PriceKey::new(self.price))
+            .then(self.exchange.cmp(&other.exchange))
+            .then(self.quantity.total_cmp(&other.quantity))
+            .then(self.instrument.cmp(&other.instrument))
     }
 }
 
 impl Order for Bid {
     fn new(instrument: Instrument, exchange: ExchangeType, quantity: f64, price: f64) -> Self {
         Bid {
-            instrument: instrument,
-            exchange: exchange,
-            quantity: quantity,
-            price: price,
+            instrument,
+            exchange,
+            quantity,
+            price,
         }
     }
 
@@ -222,7 +368,7 @@ impl Order for Bid {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Ask {
     quantity: f64,
     price: f64,
@@ -232,52 +378,36 @@ pub struct Ask {
 
 impl PartialEq for Ask {
     fn eq(&self, other: &Self) -> bool {
-        self.price == other.price
+        self.cmp(other) == Ordering::Equal
     }
 }
 
 impl Eq for Ask {}
 
 impl PartialOrd for Ask {
-    fn ge(&self, other: &Self) -> bool {
-        self.price <= other.price
-    }
-
-    fn gt(&self, other: &Self) -> bool {
-        self.price < other.price
-    }
-
-    fn lt(&self, other: &Self) -> bool {
-        self.price > other.price
-    }
-
-    fn le(&self, other: &Self) -> bool {
-        self.price >= other.price
-    }
-
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        match self.price - other.price {
-            x if x < 0.0 => Some(Ordering::Less),
-            x if x == 0.0 => self.quantity.partial_cmp(&other.quantity),
-            x if x > 0.0 => Some(Ordering::Greater),
-            _ => None,
-        }
+        Some(self.cmp(other))
     }
 }
 
+/// Best (lowest) price first, then by venue and quantity.
 impl Ord for Ask {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap_or(Ordering::Equal)
+        PriceKey::new(self.price)
+            .cmp(&PriceKey::new(other.price))
+            .then(self.exchange.cmp(&other.exchange))
+            .then(self.quantity.total_cmp(&other.quantity))
+            .then(self.instrument.cmp(&other.instrument))
     }
 }
 
 impl Order for Ask {
     fn new(instrument: Instrument, exchange: ExchangeType, quantity: f64, price: f64) -> Self {
         Ask {
-            instrument: instrument,
-            exchange: exchange,
-            quantity: quantity,
-            price: price,
+            instrument,
+            exchange,
+            quantity,
+            price,
         }
     }
 
@@ -300,9 +430,23 @@ impl Order for Ask {
 
 #[cfg(test)]
 mod test {
-    use crate::book_management::AggregatedOrderBook;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use serde_json::Value;
+    use tokio::time::Instant;
+
+    use crate::book_management::analytics::{BookAnalytic, Imbalance, Mid};
+    use crate::book_management::consolidated::ConsolidatedBook;
+    use crate::book_management::mock_books::fetched_at;
+    use crate::book_management::order_flow::OrderFlow;
     use crate::book_management::traded_instruments::Instrument;
+    use crate::book_management::venue_book::VenueFreshness;
+    use crate::book_management::{AggregatedOrderBook, Ask, Bid, Order};
     use crate::exchange_connectivity::ExchangeKeys;
+    use crate::exchange_connectivity::clock;
+    use crate::exchange_connectivity::mock_server::{MockServer, connect_deribit, deribit_fixture};
     use crate::exchange_connectivity::{Exchange, ExchangeType};
 
     #[tokio::test]
@@ -320,8 +464,235 @@ mod test {
         let book =
             AggregatedOrderBook::new(Instrument::BtcUsdt, &vec![binance.clone(), deribit.clone()]);
 
-        if let Err(err) = book.pretty_print().await {
+        if let Err(err) = book.pretty_print() {
             panic!("Unexpected error when printing: {}", err);
         }
     }
+
+    #[tokio::test]
+    async fn failing_venue_keeps_its_levels_and_reports_the_error() {
+        let failing = Arc::new(AtomicBool::new(false));
+        let server_failing = Arc::clone(&failing);
+        let server = MockServer::start(move |request: &Value| match request["method"].as_str() {
+            Some("public/get_order_book") if server_failing.load(Ordering::Relaxed) => Vec::new(),
+            _ => deribit_fixture(request),
+        })
+        .await;
+        let deribit = connect_deribit(&server).await;
+        let book = AggregatedOrderBook::new(Instrument::BtcUsdt, &vec![Exchange::Deribit(deribit)]);
+
+        book.update_state().await.unwrap();
+        assert_eq!(book.consolidated().bids().count(), 2);
+
+        failing.store(true, Ordering::Relaxed);
+        let errors = book.update_state().await.unwrap_err();
+        assert!(errors.get(ExchangeType::Deribit).is_some());
+
+        let sub_books = book.sub_books();
+        assert_eq!(sub_books[&ExchangeType::Deribit].bids().len(), 2);
+        assert_eq!(sub_books[&ExchangeType::Deribit].consecutive_failures(), 1);
+        assert_eq!(
+            book.venue_freshness()[&ExchangeType::Deribit],
+            VenueFreshness::Failed
+        );
+        assert!(book.consolidated().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn venues_are_fetched_concurrently() {
+        let book = AggregatedOrderBook::new(Instrument::BtcUsdt, &Vec::new());
+        let fetch = |exchange, latency, price| async move {
+            tokio::time::sleep(Duration::from_millis(latency)).await;
+            let bids = vec![Bid::new(Instrument::BtcUsdt, exchange, 1.0, price)];
+            fetched_at(bids, Vec::new(), clock::now())
+        };
+
+        let started = Instant::now();
+        let update = book.update_from([
+            (
+                ExchangeType::Deribit,
+                fetch(ExchangeType::Deribit, 300, 100.0),
+            ),
+            (
+                ExchangeType::Binance,
+                fetch(ExchangeType::Binance, 200, 101.0),
+            ),
+        ]);
+        tokio::pin!(update);
+
+        // Readers are not held up while the venues are fetched.
+        tokio::select! {
+            _ = &mut update => panic!("Update should still be fetching"),
+            _ = tokio::time::sleep(Duration::from_millis(100)) => {
+                assert!(book.consolidated().is_empty())
+            }
+        }
+
+        update.await.unwrap();
+        assert_eq!(started.elapsed(), Duration::from_millis(300));
+
+        let consolidated = book.consolidated();
+        assert_eq!(consolidated.bids().count(), 2);
+        assert_eq!(consolidated.best_bid().unwrap().price(), 101.0);
+    }
+
+    struct BidLevels;
+
+    impl BookAnalytic for BidLevels {
+        fn name(&self) -> String {
+            "bid_levels".to_string()
+        }
+
+        fn compute(&self, book: &ConsolidatedBook) -> Option<f64> {
+            Some(book.bids().count() as f64)
+        }
+    }
+
+    #[tokio::test]
+    async fn registered_analytics_are_computed_on_update() {
+        let book = AggregatedOrderBook::new(Instrument::BtcUsdt, &Vec::new());
+        book.register_analytic(Mid);
+        book.register_analytic(BidLevels);
+        book.register_analytic(Mid);
+
+        let levels = |bids: Vec<f64>, asks: Vec<f64>| async move {
+            let (instrument, exchange) = (Instrument::BtcUsdt, ExchangeType::Deribit);
+            fetched_at(
+                bids.into_iter()
+                    .map(|price| Bid::new(instrument, exchange, 1.0, price))
+                    .collect(),
+                asks.into_iter()
+                    .map(|price| Ask::new(instrument, exchange, 1.0, price))
+                    .collect(),
+                clock::now(),
+            )
+        };
+
+        book.update_from([(ExchangeType::Deribit, levels(vec![99.0, 98.0], vec![101.0]))])
+            .await
+            .unwrap();
+        let analytics = book.analytics();
+        assert_eq!(analytics.len(), 2);
+        assert_eq!(analytics["mid"], 100.0);
+        assert_eq!(book.analytic("bid_levels"), Some(2.0));
+
+        // With no asks there is no mid, so it is left out.
+        book.update_from([(ExchangeType::Deribit, levels(vec![99.0], Vec::new()))])
+            .await
+            .unwrap();
+        assert_eq!(book.analytic("mid"), None);
+        assert_eq!(book.analytic("bid_levels"), Some(1.0));
+    }
+
+    #[tokio::test]
+    async fn imbalance_is_consolidated_and_per_venue() {
+        let book = AggregatedOrderBook::new(Instrument::BtcUsdt, &Vec::new());
+        let levels = |exchange, bid: f64, ask: f64| async move {
+            let instrument = Instrument::BtcUsdt;
+            fetched_at(
+                vec![Bid::new(instrument, exchange, bid, 99.0)],
+                vec![Ask::new(instrument, exchange, ask, 101.0)],
+                clock::now(),
+            )
+        };
+        assert_eq!(book.imbalance(&Imbalance::default()), None);
+
+        book.update_from([
+            (
+                ExchangeType::Deribit,
+                levels(ExchangeType::Deribit, 3.0, 1.0),
+            ),
+            (
+                ExchangeType::Binance,
+                levels(ExchangeType::Binance, 1.0, 3.0),
+            ),
+        ])
+        .await
+        .unwrap();
+
+        assert_eq!(book.imbalance(&Imbalance::default()), Some(0.0));
+        let venues = book.venue_imbalances(&Imbalance::levels(1));
+        assert_eq!(venues[&ExchangeType::Deribit], 0.5);
+        assert_eq!(venues[&ExchangeType::Binance], -0.5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn order_flow_is_tracked_per_venue() {
+        let second = Duration::from_secs(1);
+        let order_flow = Arc::new(OrderFlow::new(1, [second]));
+        let book = AggregatedOrderBook::new(Instrument::BtcUsdt, &Vec::new())
+            .with_order_flow(Arc::clone(&order_flow));
+        let levels = |exchange, bid: f64| async move {
+            let instrument = Instrument::BtcUsdt;
+            fetched_at(
+                vec![Bid::new(instrument, exchange, bid, 99.0)],
+                vec![Ask::new(instrument, exchange, 1.0, 101.0)],
+                clock::now(),
+            )
+        };
+        let (deribit, binance) = (ExchangeType::Deribit, ExchangeType::Binance);
+
+        book.update_from([
+            (deribit, levels(deribit, 1.0)),
+            (binance, levels(binance, 1.0)),
+        ])
+        .await
+        .unwrap();
+        book.update_from([
+            (deribit, levels(deribit, 4.0)),
+            (binance, levels(binance, 0.5)),
+        ])
+        .await
+        .unwrap();
+
+        let best = |venue| order_flow.current(venue, second).unwrap().best();
+        assert_eq!(best(None), 2.5);
+        assert_eq!(best(Some(deribit)), 3.0);
+        assert_eq!(best(Some(binance)), -0.5);
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_immutable_and_sequenced() {
+        let book = AggregatedOrderBook::new(Instrument::BtcUsdt, &Vec::new());
+        let levels = |exchange, price: f64| async move {
+            let instrument = Instrument::BtcUsdt;
+            fetched_at(
+                vec![Bid::new(instrument, exchange, 1.0, price)],
+                vec![Ask::new(instrument, exchange, 1.0, price + 1.0)],
+                clock::now(),
+            )
+        };
+        let (deribit, binance) = (ExchangeType::Deribit, ExchangeType::Binance);
+        assert_eq!(book.snapshot().sequence(), 0);
+
+        book.update_from([(deribit, levels(deribit, 100.0))])
+            .await
+            .unwrap();
+        let first = book.snapshot();
+
+        book.update_from([
+            (binance, levels(binance, 99.0)),
+            (deribit, levels(deribit, 98.0)),
+        ])
+        .await
+        .unwrap();
+        let second = book.snapshot();
+
+        // The first snapshot still reads as it was published.
+        assert_eq!(first.sequence(), 1);
+        assert_eq!(
+            first.bids().map(|level| level.price()).collect::<Vec<_>>(),
+            vec![100.0]
+        );
+        assert_eq!(first.venue_timestamps().len(), 1);
+
+        assert_eq!(second.sequence(), 2);
+        assert_eq!(
+            second.bids().map(|level| level.price()).collect::<Vec<_>>(),
+            vec![99.0, 98.0]
+        );
+        assert_eq!(second.venue_timestamps().len(), 2);
+        assert!(second.published() >= first.published());
+        assert!(second.pretty_print().unwrap().contains("(update 2)"));
+    }
 }
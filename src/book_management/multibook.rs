@@ -10,6 +10,12 @@ pub struct Multibook {
     pub subscribed: BTreeMap<u32, Arc<AggregatedOrderBook>>,
 }
 
+impl Default for Multibook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Multibook {
     pub fn new() -> Self {
         Self {
@@ -28,6 +34,6 @@ impl Multibook {
 
     fn get_new_id(&mut self) -> u32 {
         self.curr_id += 1;
-        return self.curr_id;
+        self.curr_id
     }
 }
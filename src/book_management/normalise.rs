@@ -0,0 +1,327 @@
+//! Books quoted in different stablecoins on a common footing.
+//!
+//! BTC trades against USDT on some venues and USDC or USD on others, while
+//! an [`AggregatedOrderBook`] only merges venues quoting one instrument. A
+//! [`QuoteNormaliser`] reads the live USDC/USDT rate from that pair's own
+//! book and uses it to bring books quoted in each stablecoin into a single
+//! book quoted in USD. USDC is taken to be at par with USD.
+use std::fmt::{self, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::AggregatedOrderBook;
+use super::consolidated::{ConsolidatedBook, ConsolidatedLevel};
+use super::traded_instruments::Instrument;
+use crate::exchange_connectivity::clock;
+
+/// Currency of a USD-equivalent book.
+pub const USD: &str = "USD";
+
+/// Age after which the conversion rate is no longer trusted.
+const MAX_RATE_AGE: Duration = Duration::from_secs(10);
+
+/// Price of one `base` in `quote`, as of `updated`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConversionRate {
+    pub base: &'static str,
+    pub quote: &'static str,
+    pub rate: f64,
+    /// Normalised time of the update the rate was read from.
+    pub updated: Duration,
+}
+
+impl ConversionRate {
+    /// How old the rate is at local time `now`.
+    pub fn age(&self, now: Duration) -> Duration {
+        now.saturating_sub(self.updated)
+    }
+}
+
+impl fmt::Display for ConversionRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{} {:.6}", self.base, self.quote, self.rate)
+    }
+}
+
+/// Converts prices between USDT, USDC and USD using a live rate.
+pub struct QuoteNormaliser {
+    rates: Arc<AggregatedOrderBook>,
+    max_age: Duration,
+}
+
+impl QuoteNormaliser {
+    /// Convert at the mid of `rates`, which must be the USDC/USDT book.
+    pub fn new(rates: Arc<AggregatedOrderBook>) -> Result<Self, String> {
+        if rates.instrument() != Instrument::UsdcUsdt {
+            return Err(format!(
+                "Conversion rates must come from {}, not {}",
+                Instrument::UsdcUsdt,
+                rates.instrument()
+            ));
+        }
+
+        Ok(QuoteNormaliser {
+            rates,
+            max_age: MAX_RATE_AGE,
+        })
+    }
+
+    /// Refuse to convert with a rate older than `max_age`.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// The current USDC/USDT rate.
+    pub fn rate(&self) -> Result<ConversionRate, String> {
+        let instrument = self.rates.instrument();
+        // Read the mid and its time from one snapshot so an update landing
+        // in between cannot pair a new rate with an old time.
+        let snapshot = self.rates.snapshot();
+        let rate = snapshot
+            .book()
+            .mid()
+            .filter(|rate| *rate > 0.0)
+            .ok_or_else(|| format!("No {} book to convert with", instrument))?;
+
+        let rate = ConversionRate {
+            base: instrument.base_currency(),
+            quote: instrument.quote_currency(),
+            rate,
+            updated: snapshot.last_time(),
+        };
+
+        let age = rate.age(clock::now());
+        if age > self.max_age {
+            return Err(format!("{} rate is {:?} old", instrument, age));
+        }
+        Ok(rate)
+    }
+
+    /// What a price quoted in `currency` is multiplied by to give USD.
+    pub fn usd_factor(currency: &str, rate: &ConversionRate) -> Option<f64> {
+        match currency {
+            USD => Some(1.0),
+            _ if currency == rate.base => Some(1.0),
+            _ if currency == rate.quote => Some(1.0 / rate.rate),
+            _ => None,
+        }
+    }
+
+    /// Consolidate `books`, which must share a base currency, into one
+    /// book quoted in USD.
+    pub fn usd_equivalent(
+        &self,
+        books: &[Arc<AggregatedOrderBook>],
+    ) -> Result<NormalisedBook, String> {
+        let base = match books.first() {
+            Some(book) => book.instrument().base_currency(),
+            None => return Err("No books to normalise".to_string()),
+        };
+        let rate = self.rate()?;
+
+        let mut book = ConsolidatedBook::new();
+        for source in books {
+            let instrument = source.instrument();
+            if instrument.base_currency() != base {
+                return Err(format!(
+                    "Cannot consolidate {} with {} books",
+                    instrument, base
+                ));
+            }
+
+            let factor = Self::usd_factor(instrument.quote_currency(), &rate)
+                .ok_or_else(|| format!("Cannot convert {} to {}", instrument, USD))?;
+            book.merge(&source.consolidated().scaled(factor));
+        }
+
+        Ok(NormalisedBook {
+            base,
+            sources: books.iter().map(|book| book.instrument()).collect(),
+            book,
+            rate,
+        })
+    }
+}
+
+/// Several books with the same base currency, quoted in USD.
+#[derive(Clone, Debug)]
+pub struct NormalisedBook {
+    base: &'static str,
+    sources: Vec<Instrument>,
+    book: ConsolidatedBook,
+    rate: ConversionRate,
+}
+
+impl NormalisedBook {
+    pub fn base(&self) -> &'static str {
+        self.base
+    }
+
+    pub fn quote(&self) -> &'static str {
+        USD
+    }
+
+    /// Instruments the book was built from.
+    pub fn sources(&self) -> &[Instrument] {
+        &self.sources
+    }
+
+    pub fn book(&self) -> &ConsolidatedBook {
+        &self.book
+    }
+
+    /// Rate the non-USD books were converted at.
+    pub fn rate(&self) -> ConversionRate {
+        self.rate
+    }
+
+    pub fn pretty_print(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let sources = self
+            .sources
+            .iter()
+            .map(Instrument::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut output = String::new();
+        write!(
+            output,
+            "{} vs {} equivalent ({})\n\n",
+            self.base, USD, sources
+        )?;
+        write!(
+            output,
+            "Converted at {} ({:.1}s old)\n\n",
+            self.rate,
+            self.rate.age(clock::now()).as_secs_f64()
+        )?;
+        writeln!(
+            output,
+            "{:<40} {:<40}",
+            "Bids (Price - Qty)", "Asks (Price - Qty)"
+        )?;
+        writeln!(output, "{:-<80}", "")?;
+
+        let format_level = |level: &ConsolidatedLevel| {
+            format!("{:.6} - {:.6}", level.price(), level.total_quantity())
+        };
+
+        let mut bids = self.book.bids();
+        let mut asks = self.book.asks();
+        loop {
+            let (bid, ask) = (bids.next(), asks.next());
+            if bid.is_none() && ask.is_none() {
+                break;
+            }
+
+            let bid = bid.map_or(String::new(), format_level);
+            let ask = ask.map_or(String::new(), format_level);
+            writeln!(output, "{:<40} {:<40}", bid, ask)?;
+        }
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::QuoteNormaliser;
+    use crate::book_management::fees::FeeSchedule;
+    use crate::book_management::mock_books::book_with;
+    use crate::book_management::traded_instruments::Instrument;
+    use crate::exchange_connectivity::ExchangeType;
+    use crate::exchange_connectivity::clock;
+
+    #[tokio::test]
+    async fn usdt_and_usdc_books_consolidate_in_usd() {
+        let rates = book_with(
+            Instrument::UsdcUsdt,
+            vec![(
+                ExchangeType::Binance,
+                (vec![(1.0, 1.0)], vec![(1.002, 1.0)]),
+            )],
+            FeeSchedule::new(),
+        )
+        .await;
+        let normaliser = QuoteNormaliser::new(rates).unwrap();
+
+        let usdt = book_with(
+            Instrument::BtcUsdt,
+            vec![(
+                ExchangeType::Binance,
+                (vec![(100100.0, 1.0)], vec![(100200.1, 1.0)]),
+            )],
+            FeeSchedule::new(),
+        )
+        .await;
+        let usdc = book_with(
+            Instrument::BtcUsdc,
+            vec![(
+                ExchangeType::Deribit,
+                (vec![(100000.0, 1.0)], vec![(100200.0, 1.0)]),
+            )],
+            FeeSchedule::new(),
+        )
+        .await;
+        let normalised = normaliser.usd_equivalent(&[usdt, usdc]).unwrap();
+
+        assert_eq!(normalised.rate().rate, 1.001);
+        assert!(normalised.rate().age(clock::now()) < Duration::from_secs(1));
+
+        // 100100 USDT is 100000 USD, level with Deribit's bid.
+        let at_best = normalised
+            .book()
+            .bids()
+            .filter(|level| (level.price() - 100000.0).abs() < 1e-6)
+            .map(|level| level.total_quantity())
+            .sum::<f64>();
+        assert_eq!(at_best, 2.0);
+
+        // Binance's offer is cheaper once converted.
+        let ask = normalised.book().best_ask().unwrap();
+        assert!((ask.price() - 100100.0).abs() < 1e-6);
+        assert_eq!(ask.quantity(ExchangeType::Binance), 1.0);
+
+        let printed = normalised.pretty_print().unwrap();
+        assert!(printed.contains("USDC/USDT 1.001000"));
+    }
+
+    #[tokio::test]
+    async fn refuses_stale_rates_and_mismatched_books() {
+        let rates = book_with(
+            Instrument::UsdcUsdt,
+            vec![(
+                ExchangeType::Binance,
+                (vec![(1.0, 1.0)], vec![(1.002, 1.0)]),
+            )],
+            FeeSchedule::new(),
+        )
+        .await;
+        let normaliser = QuoteNormaliser::new(Arc::clone(&rates)).unwrap();
+
+        let btc = book_with(
+            Instrument::BtcUsdt,
+            vec![(ExchangeType::Binance, (vec![(1.0, 1.0)], vec![(2.0, 1.0)]))],
+            FeeSchedule::new(),
+        )
+        .await;
+        let eth = book_with(
+            Instrument::EthUsdc,
+            vec![(ExchangeType::Binance, (vec![(1.0, 1.0)], vec![(2.0, 1.0)]))],
+            FeeSchedule::new(),
+        )
+        .await;
+        assert!(normaliser.usd_equivalent(&[btc.clone(), eth]).is_err());
+        assert!(QuoteNormaliser::new(btc).is_err());
+
+        let stale = QuoteNormaliser::new(rates)
+            .unwrap()
+            .with_max_age(Duration::ZERO);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(stale.rate().is_err());
+    }
+}
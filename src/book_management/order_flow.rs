@@ -0,0 +1,341 @@
+//! Order flow imbalance from consecutive book states.
+//!
+//! Following Cont, Kukanov and Stoikov, each change to a level is read as
+//! the net quantity that moved it: a bid that rises or grows is buying
+//! pressure, one that falls or shrinks is its withdrawal, and the mirror
+//! image holds for asks. Summed over a window this order flow imbalance
+//! (OFI) is close to linear in the price change over the same window.
+//! [`OrderFlow`] computes it for the best few levels of the consolidated
+//! book and of each venue's own book, and sums it into fixed windows kept
+//! in a [`TimeSeriesArray`] per window length.
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::consolidated::{ConsolidatedBook, ConsolidatedLevel};
+use crate::exchange_connectivity::ExchangeType;
+use crate::time_series_array::TimeSeriesArray;
+
+/// Windows kept per series before the oldest are dropped.
+const DEFAULT_CAPACITY: usize = 10_000;
+
+/// OFI summed over one window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderFlowWindow {
+    /// Local time the window starts, a multiple of `length`.
+    pub start: Duration,
+    pub length: Duration,
+    /// Book updates seen in the window.
+    pub updates: u64,
+    /// OFI of each level, best first.
+    pub levels: Vec<f64>,
+}
+
+impl OrderFlowWindow {
+    fn new(start: Duration, length: Duration, flows: &[f64]) -> Self {
+        OrderFlowWindow {
+            start,
+            length,
+            updates: 1,
+            levels: flows.to_vec(),
+        }
+    }
+
+    fn add(&mut self, flows: &[f64]) {
+        self.updates += 1;
+        for (total, flow) in self.levels.iter_mut().zip(flows) {
+            *total += flow;
+        }
+    }
+
+    /// OFI of the best bid and offer.
+    pub fn best(&self) -> f64 {
+        self.levels.first().copied().unwrap_or(0.0)
+    }
+
+    /// OFI summed over every level.
+    pub fn total(&self) -> f64 {
+        self.levels.iter().sum()
+    }
+}
+
+impl fmt::Display for OrderFlowWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?}+{:?}: {:+.4} best, {:+.4} total over {} updates",
+            self.start,
+            self.length,
+            self.best(),
+            self.total(),
+            self.updates
+        )
+    }
+}
+
+/// Flow into one side's level, with prices arranged so that higher is
+/// better: a level that improves adds its new quantity, one that holds
+/// its price adds the change in quantity, and one that worsens removes
+/// its old quantity.
+fn flow(previous: Option<(f64, f64)>, current: Option<(f64, f64)>) -> f64 {
+    match (previous, current) {
+        (Some((previous_price, previous_quantity)), Some((price, quantity))) => {
+            match price.total_cmp(&previous_price) {
+                Ordering::Greater => quantity,
+                Ordering::Equal => quantity - previous_quantity,
+                Ordering::Less => -previous_quantity,
+            }
+        }
+        (None, Some((_, quantity))) => quantity,
+        (Some((_, previous_quantity)), None) => -previous_quantity,
+        (None, None) => 0.0,
+    }
+}
+
+/// OFI of each of the best `levels` levels going from `previous` to
+/// `current`: bid flow less ask flow, where a missing level counts as
+/// empty.
+pub fn level_flows(
+    previous: &ConsolidatedBook,
+    current: &ConsolidatedBook,
+    levels: usize,
+) -> Vec<f64> {
+    let bid = |level: &ConsolidatedLevel| (level.price(), level.total_quantity());
+    // Lower asks are better, so flip their prices.
+    let ask = |level: &ConsolidatedLevel| (-level.price(), level.total_quantity());
+
+    let (mut previous_bids, mut bids) = (previous.bids().map(bid), current.bids().map(bid));
+    let (mut previous_asks, mut asks) = (previous.asks().map(ask), current.asks().map(ask));
+    (0..levels)
+        .map(|_| flow(previous_bids.next(), bids.next()) - flow(previous_asks.next(), asks.next()))
+        .collect()
+}
+
+#[derive(Debug)]
+struct Series {
+    /// The window updates are currently summed into.
+    open: Option<OrderFlowWindow>,
+    /// Completed windows keyed by start. Windows with no updates are left
+    /// out; their OFI is zero.
+    closed: TimeSeriesArray<Duration, OrderFlowWindow>,
+}
+
+impl Series {
+    fn new(capacity: usize) -> Self {
+        Series {
+            open: None,
+            closed: TimeSeriesArray::new_with_capacity(capacity),
+        }
+    }
+
+    fn record(&mut self, start: Duration, length: Duration, flows: &[f64]) {
+        // An update older than the open window still counts towards it.
+        if let Some(open) = self.open.as_mut().filter(|open| start <= open.start) {
+            open.add(flows);
+            return;
+        }
+
+        if let Some(closed) = self
+            .open
+            .replace(OrderFlowWindow::new(start, length, flows))
+        {
+            // Starts only increase, so the key is always new.
+            let _ = self.closed.insert(closed.start, &closed);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct FlowState {
+    /// Last book seen for the consolidated book (`None`) and each venue.
+    previous: BTreeMap<Option<ExchangeType>, ConsolidatedBook>,
+    series: BTreeMap<(Option<ExchangeType>, Duration), Series>,
+}
+
+/// Multi-level OFI of one instrument's books, summed over windows of each
+/// configured length. Books are identified by venue, with `None` for the
+/// consolidated book.
+#[derive(Debug)]
+pub struct OrderFlow {
+    levels: usize,
+    windows: Vec<Duration>,
+    capacity: usize,
+    state: Mutex<FlowState>,
+}
+
+impl OrderFlow {
+    /// OFI of the best `levels` levels over each of `windows`. Zero-length
+    /// windows are ignored.
+    pub fn new(levels: usize, windows: impl IntoIterator<Item = Duration>) -> Self {
+        let mut windows = windows
+            .into_iter()
+            .filter(|window| !window.is_zero())
+            .collect::<Vec<_>>();
+        windows.sort();
+        windows.dedup();
+
+        OrderFlow {
+            levels: levels.max(1),
+            windows,
+            capacity: DEFAULT_CAPACITY,
+            state: Mutex::new(FlowState::default()),
+        }
+    }
+
+    /// Keep at most `capacity` completed windows per series.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels
+    }
+
+    pub fn windows(&self) -> &[Duration] {
+        &self.windows
+    }
+
+    /// Add the flow from the last book seen for `venue` to `book`, at
+    /// local time `now`. The first book seen only sets the baseline.
+    pub fn observe(&self, venue: Option<ExchangeType>, book: &ConsolidatedBook, now: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let Some(previous) = state.previous.insert(venue, book.clone()) else {
+            return;
+        };
+
+        let flows = level_flows(&previous, book, self.levels);
+        for length in &self.windows {
+            let window = length.as_nanos();
+            let start = Duration::from_nanos((now.as_nanos() / window * window) as u64);
+            state
+                .series
+                .entry((venue, *length))
+                .or_insert_with(|| Series::new(self.capacity))
+                .record(start, *length, &flows);
+        }
+    }
+
+    /// The window of `length` updates are being summed into for `venue`.
+    pub fn current(
+        &self,
+        venue: Option<ExchangeType>,
+        length: Duration,
+    ) -> Option<OrderFlowWindow> {
+        let state = self.state.lock().unwrap();
+        state.series.get(&(venue, length))?.open.clone()
+    }
+
+    /// The most recently completed window of `length` for `venue`.
+    pub fn last(&self, venue: Option<ExchangeType>, length: Duration) -> Option<OrderFlowWindow> {
+        let state = self.state.lock().unwrap();
+        let series = state.series.get(&(venue, length))?;
+        let (_, window) = series.closed.last_value_for_key(&Duration::MAX)?;
+        Some(window.clone())
+    }
+
+    /// Completed windows of `length` for `venue` starting in
+    /// [`from`, `to`), oldest first.
+    pub fn history(
+        &self,
+        venue: Option<ExchangeType>,
+        length: Duration,
+        from: Duration,
+        to: Duration,
+    ) -> Vec<OrderFlowWindow> {
+        let state = self.state.lock().unwrap();
+        state
+            .series
+            .get(&(venue, length))
+            .map(|series| {
+                series
+                    .closed
+                    .range_query(from, to)
+                    .map(|(_, window)| window.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{OrderFlow, level_flows};
+    use crate::book_management::consolidated::ConsolidatedBook;
+    use crate::exchange_connectivity::ExchangeType;
+
+    fn book(bids: &[(f64, f64)], asks: &[(f64, f64)]) -> ConsolidatedBook {
+        let mut book = ConsolidatedBook::new();
+        for (price, quantity) in bids {
+            book.add_bid(ExchangeType::Deribit, *price, *quantity);
+        }
+        for (price, quantity) in asks {
+            book.add_ask(ExchangeType::Deribit, *price, *quantity);
+        }
+        book
+    }
+
+    #[test]
+    fn flows_follow_level_changes() {
+        let previous = book(&[(100.0, 2.0), (99.0, 1.0)], &[(101.0, 3.0), (102.0, 1.0)]);
+
+        // Bid grows by 1 at the same price, ask shrinks by 2.
+        let current = book(&[(100.0, 3.0), (99.0, 1.0)], &[(101.0, 1.0), (102.0, 1.0)]);
+        assert_eq!(level_flows(&previous, &current, 2), vec![1.0 + 2.0, 0.0]);
+
+        // Bid improves to 100.5 with 4; the best ask is lifted, so 101
+        // moves to 102 and the old 3 is gone.
+        let current = book(&[(100.5, 4.0), (100.0, 2.0)], &[(102.0, 1.0)]);
+        assert_eq!(
+            level_flows(&previous, &current, 2),
+            vec![4.0 + 3.0, 2.0 + 1.0]
+        );
+
+        // Bid drops to 99 and a better ask appears at 100.5.
+        let current = book(&[(99.0, 1.0)], &[(100.5, 2.0), (101.0, 3.0)]);
+        assert_eq!(
+            level_flows(&previous, &current, 2),
+            vec![-2.0 - 2.0, -1.0 - 3.0]
+        );
+    }
+
+    #[test]
+    fn flows_are_summed_into_windows() {
+        let (second, minute) = (Duration::from_secs(1), Duration::from_secs(60));
+        let order_flow = OrderFlow::new(1, [minute, second, Duration::ZERO]);
+        assert_eq!(order_flow.windows(), &[second, minute]);
+
+        let at = Duration::from_millis;
+        let bid = |quantity| book(&[(100.0, quantity)], &[(101.0, 1.0)]);
+        order_flow.observe(None, &bid(1.0), at(100));
+        assert_eq!(order_flow.current(None, second), None);
+
+        order_flow.observe(None, &bid(3.0), at(400));
+        order_flow.observe(None, &bid(2.0), at(900));
+        order_flow.observe(None, &bid(5.0), at(1_200));
+        order_flow.observe(None, &bid(5.0), at(3_500));
+
+        let windows = order_flow.history(None, second, Duration::ZERO, at(10_000));
+        assert_eq!(windows.len(), 2);
+        assert_eq!((windows[0].start, windows[0].updates), (Duration::ZERO, 2));
+        assert_eq!(windows[0].best(), 1.0);
+        assert_eq!((windows[1].start, windows[1].best()), (second, 3.0));
+        assert_eq!(order_flow.last(None, second), Some(windows[1].clone()));
+
+        let current = order_flow.current(None, second).unwrap();
+        assert_eq!((current.start, current.best()), (3 * second, 0.0));
+
+        // All four updates are still in the open minute.
+        let current = order_flow.current(None, minute).unwrap();
+        assert_eq!((current.updates, current.total()), (4, 4.0));
+        assert_eq!(order_flow.last(None, minute), None);
+        assert_eq!(
+            order_flow.current(Some(ExchangeType::Binance), second),
+            None
+        );
+    }
+}
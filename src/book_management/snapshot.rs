@@ -0,0 +1,211 @@
+//! Immutable views of an aggregated book.
+//!
+//! Every update of an [`AggregatedOrderBook`](super::AggregatedOrderBook)
+//! builds a new [`BookSnapshot`] and publishes it with an atomic pointer
+//! swap. A reader loads the current snapshot without taking a lock and
+//! keeps it for as long as it likes, so everything it reads from one
+//! snapshot (levels, venue timestamps, analytics) comes from the same
+//! update, however many updates are published meanwhile.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use super::analytics::{BookAnalytic, Imbalance};
+use super::consolidated::{ConsolidatedBook, ConsolidatedLevel};
+use super::traded_instruments::Instrument;
+use super::venue_book::{SubBook, VenueFreshness};
+use super::{Ask, Bid};
+use crate::exchange_connectivity::ExchangeType;
+use crate::exchange_connectivity::clock::UpdateTimestamps;
+
+/// The book of one instrument as of one update.
+#[derive(Clone, Debug)]
+pub struct BookSnapshot {
+    instrument: Instrument,
+    sequence: u64,
+    published: Duration,
+    sub_books: BTreeMap<ExchangeType, SubBook>,
+    book: ConsolidatedBook,
+    analytics: BTreeMap<String, f64>,
+}
+
+impl BookSnapshot {
+    /// The snapshot of a book that has never been updated.
+    pub fn empty(instrument: Instrument) -> Self {
+        BookSnapshot {
+            instrument,
+            sequence: 0,
+            published: Duration::ZERO,
+            sub_books: BTreeMap::new(),
+            book: ConsolidatedBook::new(),
+            analytics: BTreeMap::new(),
+        }
+    }
+
+    /// The snapshot following this one.
+    pub(super) fn next(
+        &self,
+        sub_books: BTreeMap<ExchangeType, SubBook>,
+        book: ConsolidatedBook,
+        analytics: BTreeMap<String, f64>,
+        published: Duration,
+    ) -> Self {
+        BookSnapshot {
+            instrument: self.instrument,
+            sequence: self.sequence + 1,
+            published,
+            sub_books,
+            book,
+            analytics,
+        }
+    }
+
+    pub fn instrument(&self) -> Instrument {
+        self.instrument
+    }
+
+    /// Number of updates published before and including this one, so 0
+    /// for a book never updated.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Local time the snapshot was built.
+    pub fn published(&self) -> Duration {
+        self.published
+    }
+
+    /// The consolidated book of the venues that were fresh.
+    pub fn book(&self) -> &ConsolidatedBook {
+        &self.book
+    }
+
+    /// Consolidated bids, best first.
+    pub fn bids(&self) -> impl Iterator<Item = &ConsolidatedLevel> {
+        self.book.bids()
+    }
+
+    /// Consolidated asks, best first.
+    pub fn asks(&self) -> impl Iterator<Item = &ConsolidatedLevel> {
+        self.book.asks()
+    }
+
+    /// Copy of the bids and asks on every venue, best first.
+    pub fn levels(&self) -> (Vec<Bid>, Vec<Ask>) {
+        self.book.orders(self.instrument)
+    }
+
+    /// Each venue's sub-book, including any left out of the consolidated
+    /// book for being stale.
+    pub fn sub_books(&self) -> &BTreeMap<ExchangeType, SubBook> {
+        &self.sub_books
+    }
+
+    /// Timestamps of the last update received from each venue.
+    pub fn venue_timestamps(&self) -> BTreeMap<ExchangeType, UpdateTimestamps> {
+        self.sub_books
+            .iter()
+            .filter_map(|(exchange, sub_book)| Some((*exchange, sub_book.updated()?)))
+            .collect()
+    }
+
+    /// Most recent normalised update time across all venues.
+    pub fn last_time(&self) -> Duration {
+        self.sub_books
+            .values()
+            .filter_map(|sub_book| Some(sub_book.updated()?.normalised))
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Freshness of each venue's sub-book at `now`.
+    pub fn venue_freshness(
+        &self,
+        now: Duration,
+        stale_after: Duration,
+    ) -> BTreeMap<ExchangeType, VenueFreshness> {
+        self.sub_books
+            .iter()
+            .map(|(exchange, sub_book)| (*exchange, sub_book.freshness(now, stale_after)))
+            .collect()
+    }
+
+    /// Value of each registered analytic, leaving out any with no value
+    /// for this book.
+    pub fn analytics(&self) -> &BTreeMap<String, f64> {
+        &self.analytics
+    }
+
+    pub fn analytic(&self, name: &str) -> Option<f64> {
+        self.analytics.get(name).copied()
+    }
+
+    /// `imbalance` of the consolidated book, or `None` if it has no
+    /// levels to count.
+    pub fn imbalance(&self, imbalance: &Imbalance) -> Option<f64> {
+        imbalance.compute(&self.book)
+    }
+
+    /// `imbalance` of each venue's levels in the consolidated book.
+    pub fn venue_imbalances(&self, imbalance: &Imbalance) -> BTreeMap<ExchangeType, f64> {
+        self.book
+            .exchanges()
+            .into_iter()
+            .filter_map(|exchange| Some((exchange, imbalance.compute(&self.book.venue(exchange))?)))
+            .collect()
+    }
+
+    pub fn pretty_print(&self) -> Result<String, Box<dyn std::error::Error>> {
+        let imbalance = self
+            .imbalance(&Imbalance::default())
+            .map_or("n/a".to_string(), |imbalance| format!("{:.4}", imbalance));
+
+        let mut output = String::new();
+        write!(
+            output,
+            "Instrument: {} (update {})\n\n",
+            self.instrument, self.sequence
+        )?;
+        write!(
+            output,
+            "Bid/Ask Imbalance (top 10 levels): {}\n\n",
+            imbalance
+        )?;
+        writeln!(
+            output,
+            "{:<60} {:<60}",
+            "Bids (Price - Qty - Exchange Qty)", "Asks (Price - Qty - Exchange Qty)"
+        )?;
+        writeln!(output, "{:-<120}", "")?;
+
+        let format_level = |level: &ConsolidatedLevel| {
+            let venues = level
+                .venues()
+                .map(|(exchange, quantity)| format!("{:?} {:.6}", exchange, quantity))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!(
+                "{:.6} - {:.6} - {}",
+                level.price(),
+                level.total_quantity(),
+                venues
+            )
+        };
+
+        let mut bids_iter = self.bids();
+        let mut asks_iter = self.asks();
+
+        loop {
+            let (bid, ask) = (bids_iter.next(), asks_iter.next());
+            if bid.is_none() && ask.is_none() {
+                break;
+            }
+
+            let bid = bid.map_or(String::new(), format_level);
+            let ask = ask.map_or(String::new(), format_level);
+            writeln!(output, "{:<60} {:<60}", bid, ask)?;
+        }
+
+        Ok(output)
+    }
+}
@@ -0,0 +1,354 @@
+//! Cross-rate books implied by two legs against a common currency.
+//!
+//! ETH/BTC can be traded directly, or through ETH/USDC and BTC/USDT:
+//! selling ETH for dollars and buying BTC with them is an implied ETH/BTC
+//! bid, and the reverse an implied ask. A [`CrossRate`] composes the legs
+//! level by level, so the implied ladder reflects the depth of both legs
+//! rather than just their top of book. Legs are only composed within a
+//! venue, since both trades have to happen where the funds are.
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use super::AggregatedOrderBook;
+use super::consolidated::{ConsolidatedBook, ConsolidatedLevel};
+use super::normalise::QuoteNormaliser;
+use crate::exchange_connectivity::ExchangeType;
+
+/// A cross rate between the base currencies of two legs.
+pub struct CrossRate {
+    base_leg: Arc<AggregatedOrderBook>,
+    quote_leg: Arc<AggregatedOrderBook>,
+    normaliser: Option<QuoteNormaliser>,
+}
+
+impl CrossRate {
+    /// The rate of `base_leg`'s base currency in `quote_leg`'s, e.g.
+    /// ETH/BTC from ETH/USDC and BTC/USDT.
+    pub fn new(base_leg: Arc<AggregatedOrderBook>, quote_leg: Arc<AggregatedOrderBook>) -> Self {
+        CrossRate {
+            base_leg,
+            quote_leg,
+            normaliser: None,
+        }
+    }
+
+    /// Convert between the legs' quote currencies when they differ.
+    pub fn with_normaliser(mut self, normaliser: QuoteNormaliser) -> Self {
+        self.normaliser = Some(normaliser);
+        self
+    }
+
+    /// Currencies of the implied book, as (base, quote).
+    pub fn currencies(&self) -> (&'static str, &'static str) {
+        (
+            self.base_leg.instrument().base_currency(),
+            self.quote_leg.instrument().base_currency(),
+        )
+    }
+
+    /// Multipliers bringing each leg's prices into a common currency.
+    fn leg_factors(&self) -> Result<(f64, f64), String> {
+        let base_quote = self.base_leg.instrument().quote_currency();
+        let quote_quote = self.quote_leg.instrument().quote_currency();
+        if base_quote == quote_quote {
+            return Ok((1.0, 1.0));
+        }
+
+        let normaliser = self.normaliser.as_ref().ok_or_else(|| {
+            format!(
+                "Legs are quoted in {} and {} but there is no conversion rate",
+                base_quote, quote_quote
+            )
+        })?;
+        let rate = normaliser.rate()?;
+        let factor = |currency| {
+            QuoteNormaliser::usd_factor(currency, &rate)
+                .ok_or_else(|| format!("Cannot convert {} with {}", currency, rate))
+        };
+        Ok((factor(base_quote)?, factor(quote_quote)?))
+    }
+
+    /// The implied book on every venue quoting both legs.
+    pub fn implied(&self) -> Result<ImpliedBook, String> {
+        let (base_factor, quote_factor) = self.leg_factors()?;
+        let base_leg = self.base_leg.consolidated().scaled(base_factor);
+        let quote_leg = self.quote_leg.consolidated().scaled(quote_factor);
+
+        let venues = base_leg
+            .exchanges()
+            .intersection(&quote_leg.exchanges())
+            .map(|exchange| {
+                let implied = compose(
+                    &base_leg.venue(*exchange),
+                    &quote_leg.venue(*exchange),
+                    *exchange,
+                );
+                (*exchange, implied)
+            })
+            .collect();
+
+        Ok(ImpliedBook { venues })
+    }
+
+    /// Best implied and direct prices on each venue and across all of
+    /// them. `direct` should quote the same cross rate as this.
+    pub fn compare(
+        &self,
+        direct: &AggregatedOrderBook,
+    ) -> Result<Vec<CrossRateComparison>, String> {
+        let implied = self.implied()?;
+        let direct = direct.consolidated();
+
+        let mut exchanges = direct.exchanges();
+        exchanges.extend(implied.venues.keys());
+
+        let mut comparisons = exchanges
+            .into_iter()
+            .map(|exchange| {
+                let implied = implied.venue(exchange).cloned().unwrap_or_default();
+                CrossRateComparison::new(Some(exchange), &implied, &direct.venue(exchange))
+            })
+            .collect::<Vec<_>>();
+        comparisons.push(CrossRateComparison::new(
+            None,
+            &implied.consolidated(),
+            &direct,
+        ));
+        Ok(comparisons)
+    }
+}
+
+/// Levels of a cross rate implied on each venue.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImpliedBook {
+    venues: BTreeMap<ExchangeType, ConsolidatedBook>,
+}
+
+impl ImpliedBook {
+    pub fn venue(&self, exchange: ExchangeType) -> Option<&ConsolidatedBook> {
+        self.venues.get(&exchange)
+    }
+
+    pub fn venues(&self) -> impl Iterator<Item = (ExchangeType, &ConsolidatedBook)> {
+        self.venues.iter().map(|(exchange, book)| (*exchange, book))
+    }
+
+    /// Every venue's implied levels in one book.
+    pub fn consolidated(&self) -> ConsolidatedBook {
+        let mut book = ConsolidatedBook::new();
+        for venue in self.venues.values() {
+            book.merge(venue);
+        }
+        book
+    }
+}
+
+/// The top of an implied book next to the direct book's.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CrossRateComparison {
+    /// The venue compared, or `None` for every venue together.
+    pub exchange: Option<ExchangeType>,
+    pub implied_bid: Option<f64>,
+    pub implied_ask: Option<f64>,
+    pub direct_bid: Option<f64>,
+    pub direct_ask: Option<f64>,
+}
+
+impl CrossRateComparison {
+    fn new(
+        exchange: Option<ExchangeType>,
+        implied: &ConsolidatedBook,
+        direct: &ConsolidatedBook,
+    ) -> Self {
+        CrossRateComparison {
+            exchange,
+            implied_bid: implied.best_bid().map(ConsolidatedLevel::price),
+            implied_ask: implied.best_ask().map(ConsolidatedLevel::price),
+            direct_bid: direct.best_bid().map(ConsolidatedLevel::price),
+            direct_ask: direct.best_ask().map(ConsolidatedLevel::price),
+        }
+    }
+
+    /// How far the direct bid is above the implied one.
+    pub fn bid_difference(&self) -> Option<f64> {
+        Some(self.direct_bid? - self.implied_bid?)
+    }
+
+    /// How far the direct ask is above the implied one.
+    pub fn ask_difference(&self) -> Option<f64> {
+        Some(self.direct_ask? - self.implied_ask?)
+    }
+}
+
+/// Compose two legs quoted in the same currency into the book of their
+/// cross rate on `exchange`.
+///
+/// An implied bid sells the base leg's base currency into its bids and
+/// buys the quote leg's with the proceeds from its asks; an implied ask
+/// does the opposite. Both legs are walked best level first, matching
+/// them by notional, so each implied level is as deep as the shallower of
+/// the two leg levels it spans.
+pub fn compose(
+    base_leg: &ConsolidatedBook,
+    quote_leg: &ConsolidatedBook,
+    exchange: ExchangeType,
+) -> ConsolidatedBook {
+    let mut book = ConsolidatedBook::new();
+    for (price, quantity) in compose_side(base_leg.bids(), quote_leg.asks()) {
+        book.add_bid(exchange, price, quantity);
+    }
+    for (price, quantity) in compose_side(base_leg.asks(), quote_leg.bids()) {
+        book.add_ask(exchange, price, quantity);
+    }
+    book
+}
+
+/// Match `base` levels against `quote` levels by notional, returning the
+/// cross price and base quantity of each match.
+fn compose_side<'a>(
+    mut base: impl Iterator<Item = &'a ConsolidatedLevel>,
+    mut quote: impl Iterator<Item = &'a ConsolidatedLevel>,
+) -> Vec<(f64, f64)> {
+    let notional = |level: &ConsolidatedLevel| level.price() * level.total_quantity();
+    let mut levels = Vec::new();
+
+    let (Some(mut base_level), Some(mut quote_level)) = (base.next(), quote.next()) else {
+        return levels;
+    };
+    let mut base_left = notional(base_level);
+    let mut quote_left = notional(quote_level);
+
+    loop {
+        let matched = base_left.min(quote_left);
+        if matched > 0.0 && quote_level.price() > 0.0 {
+            levels.push((
+                base_level.price() / quote_level.price(),
+                matched / base_level.price(),
+            ));
+        }
+
+        if base_left <= quote_left {
+            quote_left -= matched;
+            match base.next() {
+                Some(level) => base_level = level,
+                None => break,
+            }
+            base_left = notional(base_level);
+        } else {
+            base_left -= matched;
+            match quote.next() {
+                Some(level) => quote_level = level,
+                None => break,
+            }
+            quote_left = notional(quote_level);
+        }
+    }
+
+    levels
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CrossRate, compose};
+    use crate::book_management::consolidated::ConsolidatedBook;
+    use crate::book_management::fees::FeeSchedule;
+    use crate::book_management::mock_books::book_with;
+    use crate::book_management::traded_instruments::Instrument;
+    use crate::exchange_connectivity::ExchangeType;
+
+    #[test]
+    fn implied_levels_walk_both_legs() {
+        let mut eth = ConsolidatedBook::new();
+        eth.add_bid(ExchangeType::Binance, 2000.0, 1.0);
+        eth.add_bid(ExchangeType::Binance, 1990.0, 2.0);
+        eth.add_ask(ExchangeType::Binance, 2010.0, 1.0);
+        let mut btc = ConsolidatedBook::new();
+        btc.add_ask(ExchangeType::Binance, 40000.0, 0.1);
+        btc.add_ask(ExchangeType::Binance, 40100.0, 1.0);
+        btc.add_bid(ExchangeType::Binance, 39900.0, 0.01);
+
+        let implied = compose(&eth, &btc, ExchangeType::Binance);
+
+        // 1 ETH buys 0.05 BTC at the best ask, leaving 2000 USD of it for the
+        // next ETH level, which then runs into the second BTC ask.
+        let bids = implied.bids().collect::<Vec<_>>();
+        assert_eq!(bids[0].price(), 2000.0 / 40000.0);
+        assert_eq!(bids[0].total_quantity(), 1.0);
+        assert_eq!(bids[1].price(), 1990.0 / 40000.0);
+        assert!((bids[1].total_quantity() - 2000.0 / 1990.0).abs() < 1e-12);
+        assert_eq!(bids[2].price(), 1990.0 / 40100.0);
+        assert!((implied.bids().map(|l| l.total_quantity()).sum::<f64>() - 3.0).abs() < 1e-12);
+
+        // Only 399 USD of BTC bids, so the implied ask is that deep.
+        let ask = implied.best_ask().unwrap();
+        assert_eq!(ask.price(), 2010.0 / 39900.0);
+        assert!((ask.total_quantity() - 399.0 / 2010.0).abs() < 1e-12);
+        assert_eq!(implied.asks().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn compares_implied_and_direct_books_per_venue() {
+        let (binance, deribit) = (ExchangeType::Binance, ExchangeType::Deribit);
+        let eth = book_with(
+            Instrument::EthUsdc,
+            vec![(binance, (vec![(2000.0, 10.0)], vec![(2002.0, 10.0)]))],
+            FeeSchedule::new(),
+        )
+        .await;
+        let btc = book_with(
+            Instrument::BtcUsdc,
+            vec![
+                (binance, (vec![(40000.0, 10.0)], vec![(40010.0, 10.0)])),
+                (deribit, (vec![(40000.0, 10.0)], vec![(40010.0, 10.0)])),
+            ],
+            FeeSchedule::new(),
+        )
+        .await;
+        let direct = book_with(
+            Instrument::EthBtc,
+            vec![
+                (binance, (vec![(0.0499, 10.0)], vec![(0.0502, 10.0)])),
+                (deribit, (vec![(0.05, 10.0)], vec![(0.0501, 10.0)])),
+            ],
+            FeeSchedule::new(),
+        )
+        .await;
+
+        let cross = CrossRate::new(eth, btc);
+        assert_eq!(cross.currencies(), ("ETH", "BTC"));
+
+        let comparisons = cross.compare(&direct).unwrap();
+        assert_eq!(comparisons.len(), 3);
+
+        let on_binance = comparisons[1];
+        assert_eq!(on_binance.exchange, Some(binance));
+        assert_eq!(on_binance.implied_bid, Some(2000.0 / 40010.0));
+        assert_eq!(on_binance.implied_ask, Some(2002.0 / 40000.0));
+        assert!(on_binance.bid_difference().unwrap() < 0.0);
+
+        // Deribit has no ETH leg, so nothing is implied there.
+        let on_deribit = comparisons[0];
+        assert_eq!(on_deribit.implied_bid, None);
+        assert_eq!(on_deribit.direct_bid, Some(0.05));
+
+        let overall = comparisons[2];
+        assert_eq!(overall.exchange, None);
+        assert_eq!(overall.direct_bid, Some(0.05));
+        assert_eq!(overall.implied_ask, on_binance.implied_ask);
+
+        // Legs in different stablecoins need a conversion rate.
+        let usdt = book_with(
+            Instrument::BtcUsdt,
+            vec![(binance, (vec![(40000.0, 10.0)], vec![(40010.0, 10.0)]))],
+            FeeSchedule::new(),
+        )
+        .await;
+        let eth = book_with(
+            Instrument::EthUsdc,
+            vec![(binance, (vec![(2000.0, 10.0)], vec![(2002.0, 10.0)]))],
+            FeeSchedule::new(),
+        )
+        .await;
+        assert!(CrossRate::new(eth, usdt).implied().is_err());
+    }
+}
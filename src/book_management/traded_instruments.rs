@@ -1,15 +1,37 @@
 use std::fmt::{self, Debug, Display};
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Instrument {
     BtcUsdt,
+    BtcUsdc,
     EthUsdc,
     EthBtc,
+    UsdcUsdt,
+}
+
+impl Instrument {
+    /// Currency being bought or sold.
+    pub fn base_currency(&self) -> &'static str {
+        match self {
+            Instrument::BtcUsdt | Instrument::BtcUsdc => "BTC",
+            Instrument::EthUsdc | Instrument::EthBtc => "ETH",
+            Instrument::UsdcUsdt => "USDC",
+        }
+    }
+
+    /// Currency prices and fees are quoted in.
+    pub fn quote_currency(&self) -> &'static str {
+        match self {
+            Instrument::BtcUsdt | Instrument::UsdcUsdt => "USDT",
+            Instrument::BtcUsdc | Instrument::EthUsdc => "USDC",
+            Instrument::EthBtc => "BTC",
+        }
+    }
 }
 
 impl Debug for Instrument {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        return <dyn Display>::fmt(self, f);
+        <dyn Display>::fmt(self, f)
     }
 }
 
@@ -17,8 +39,10 @@ impl fmt::Display for Instrument {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Instrument::BtcUsdt => write!(f, "BTC_USDT"),
+            Instrument::BtcUsdc => write!(f, "BTC_USDC"),
             Instrument::EthUsdc => write!(f, "ETH_USDC"),
             Instrument::EthBtc => write!(f, "ETH_BTC"),
+            Instrument::UsdcUsdt => write!(f, "USDC_USDT"),
         }
     }
 }
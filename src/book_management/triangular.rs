@@ -0,0 +1,390 @@
+//! Triangular arbitrage through chains of books.
+//!
+//! Starting from one currency and trading through a cycle of books back to
+//! it, e.g. USDT to BTC to ETH to USDC and back to USDT, can end with more
+//! than was started with. A [`TriangularScanner`] finds every such cycle
+//! through the books it is given, walks each book's real depth to see how
+//! much the cycle can take, and reports those which pay after taker fees,
+//! both with every leg on one venue and with legs spread across venues.
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use super::AggregatedOrderBook;
+use super::consolidated::ConsolidatedBook;
+use super::fees::Liquidity;
+use super::traded_instruments::Instrument;
+use crate::exchange_connectivity::ExchangeType;
+use crate::exchange_connectivity::orders::Side;
+
+/// Longest cycle searched for, in trades.
+const MAX_LEGS: usize = 4;
+
+/// Iterations when searching for a cycle's capacity and best size.
+const SEARCH_STEPS: usize = 100;
+
+/// One trade of a cycle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Leg {
+    pub instrument: Instrument,
+    pub exchange: ExchangeType,
+    pub side: Side,
+    pub from: &'static str,
+    pub to: &'static str,
+}
+
+/// A cycle which ends with more than it started with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cycle {
+    pub legs: Vec<Leg>,
+    /// Return of a marginal amount at the best prices, after fees.
+    pub top_return: f64,
+    /// Amount of the starting currency which makes the most profit.
+    pub max_size: f64,
+    /// Profit at `max_size`, in the starting currency.
+    pub profit: f64,
+}
+
+impl Cycle {
+    pub fn currency(&self) -> &'static str {
+        self.legs[0].from
+    }
+
+    /// Return at `max_size` after walking every book and paying fees.
+    pub fn fee_adjusted_return(&self) -> f64 {
+        self.profit / self.max_size
+    }
+
+    /// The venue every leg trades on, or `None` if the legs are spread
+    /// across venues.
+    pub fn venue(&self) -> Option<ExchangeType> {
+        let exchange = self.legs[0].exchange;
+        self.legs
+            .iter()
+            .all(|leg| leg.exchange == exchange)
+            .then_some(exchange)
+    }
+}
+
+/// A book's levels on one venue along with the fee for taking them.
+struct VenueLeg {
+    book: ConsolidatedBook,
+    fee: f64,
+}
+
+impl VenueLeg {
+    /// How much is received for `amount`, or `None` if the book is not
+    /// deep enough to take all of it.
+    fn convert(&self, side: Side, amount: f64) -> Option<f64> {
+        let mut left = amount;
+        let mut received = 0.0;
+
+        match side {
+            Side::Buy => {
+                for level in self.book.asks() {
+                    let spent = left.min(level.price() * level.total_quantity());
+                    received += spent / level.price();
+                    left -= spent;
+                    if left <= 0.0 {
+                        break;
+                    }
+                }
+            }
+            Side::Sell => {
+                for level in self.book.bids() {
+                    let sold = left.min(level.total_quantity());
+                    received += sold * level.price();
+                    left -= sold;
+                    if left <= 0.0 {
+                        break;
+                    }
+                }
+            }
+        }
+
+        (left <= 0.0).then_some(received * (1.0 - self.fee))
+    }
+}
+
+/// Finds profitable cycles through a set of books.
+pub struct TriangularScanner {
+    books: Vec<Arc<AggregatedOrderBook>>,
+}
+
+impl TriangularScanner {
+    /// Each book's own fee schedule is used for the legs through it.
+    pub fn new(books: Vec<Arc<AggregatedOrderBook>>) -> Self {
+        TriangularScanner { books }
+    }
+
+    /// Every cycle from `currency` back to itself, through at most four
+    /// books, as the instruments and sides to trade in turn.
+    pub fn routes(&self, currency: &'static str) -> Vec<Vec<(Instrument, Side)>> {
+        let mut routes = Vec::new();
+        let mut path = Vec::new();
+        self.extend_route(currency, currency, &mut path, &mut routes);
+        routes
+    }
+
+    fn extend_route(
+        &self,
+        start: &'static str,
+        at: &'static str,
+        path: &mut Vec<(Instrument, Side)>,
+        routes: &mut Vec<Vec<(Instrument, Side)>>,
+    ) {
+        if path.len() == MAX_LEGS {
+            return;
+        }
+
+        for book in &self.books {
+            let instrument = book.instrument();
+            if path.iter().any(|(used, _)| *used == instrument) {
+                continue;
+            }
+
+            let (side, to) = if instrument.quote_currency() == at {
+                (Side::Buy, instrument.base_currency())
+            } else if instrument.base_currency() == at {
+                (Side::Sell, instrument.quote_currency())
+            } else {
+                continue;
+            };
+
+            // Currencies are only revisited to close the cycle.
+            let visited = path.iter().any(|(used, side)| match side {
+                Side::Buy => used.quote_currency() == to,
+                Side::Sell => used.base_currency() == to,
+            });
+            if visited && to != start {
+                continue;
+            }
+
+            path.push((instrument, side));
+            if to == start {
+                if path.len() >= 3 {
+                    routes.push(path.clone());
+                }
+            } else {
+                self.extend_route(start, to, path, routes);
+            }
+            path.pop();
+        }
+    }
+
+    /// Profitable cycles from `currency`, most profitable first.
+    pub fn scan(&self, currency: &'static str) -> Vec<Cycle> {
+        let mut venues: BTreeMap<Instrument, BTreeMap<ExchangeType, VenueLeg>> = BTreeMap::new();
+        for book in &self.books {
+            let instrument = book.instrument();
+            let consolidated = book.consolidated();
+            let legs = consolidated
+                .exchanges()
+                .into_iter()
+                .map(|exchange| {
+                    let leg = VenueLeg {
+                        book: consolidated.venue(exchange),
+                        fee: book.fees().rate(exchange, instrument, Liquidity::Taker),
+                    };
+                    (exchange, leg)
+                })
+                .collect();
+            venues.insert(instrument, legs);
+        }
+
+        let mut cycles = Vec::new();
+        for route in self.routes(currency) {
+            for exchanges in Self::venue_choices(&route, &venues) {
+                let legs = route
+                    .iter()
+                    .zip(&exchanges)
+                    .map(|((instrument, side), exchange)| {
+                        let (from, to) = match side {
+                            Side::Buy => (instrument.quote_currency(), instrument.base_currency()),
+                            Side::Sell => (instrument.base_currency(), instrument.quote_currency()),
+                        };
+                        Leg {
+                            instrument: *instrument,
+                            exchange: *exchange,
+                            side: *side,
+                            from,
+                            to,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                if let Some(cycle) = Self::evaluate(legs, &venues) {
+                    cycles.push(cycle);
+                }
+            }
+        }
+
+        cycles.sort_by(|a, b| b.profit.total_cmp(&a.profit));
+        cycles
+    }
+
+    /// Every way of picking a venue for each leg of `route`.
+    fn venue_choices(
+        route: &[(Instrument, Side)],
+        venues: &BTreeMap<Instrument, BTreeMap<ExchangeType, VenueLeg>>,
+    ) -> Vec<Vec<ExchangeType>> {
+        let mut choices = vec![Vec::new()];
+        for (instrument, _) in route {
+            let available = venues
+                .get(instrument)
+                .map(|legs| legs.keys().copied().collect::<Vec<_>>())
+                .unwrap_or_default();
+            choices = choices
+                .into_iter()
+                .flat_map(|chosen| {
+                    available.iter().map(move |exchange| {
+                        let mut chosen = chosen.clone();
+                        chosen.push(*exchange);
+                        chosen
+                    })
+                })
+                .collect();
+        }
+        choices
+    }
+
+    /// Size and profit of the cycle through `legs`, if it pays.
+    fn evaluate(
+        legs: Vec<Leg>,
+        venues: &BTreeMap<Instrument, BTreeMap<ExchangeType, VenueLeg>>,
+    ) -> Option<Cycle> {
+        let run = |amount: f64| {
+            legs.iter().try_fold(amount, |amount, leg| {
+                venues[&leg.instrument][&leg.exchange].convert(leg.side, amount)
+            })
+        };
+
+        // Largest amount every book is deep enough for.
+        let first = &venues[&legs[0].instrument][&legs[0].exchange].book;
+        let mut upper = match legs[0].side {
+            Side::Buy => first
+                .asks()
+                .map(|level| level.price() * level.total_quantity())
+                .sum(),
+            Side::Sell => first.bids().map(|level| level.total_quantity()).sum(),
+        };
+        let mut lower = 0.0;
+        for _ in 0..SEARCH_STEPS {
+            let mid = (lower + upper) / 2.0;
+            match run(mid) {
+                Some(_) => lower = mid,
+                None => upper = mid,
+            }
+        }
+        let capacity = lower;
+        if capacity <= 0.0 {
+            return None;
+        }
+
+        let marginal = capacity * 1e-9;
+        let top_return = run(marginal)? / marginal - 1.0;
+        if top_return <= 0.0 {
+            return None;
+        }
+
+        // Profit only ever grows more slowly with size, so the best size is
+        // where it stops growing.
+        let profit = |amount: f64| run(amount).map_or(f64::MIN, |received| received - amount);
+        let (mut lower, mut upper) = (0.0, capacity);
+        for _ in 0..SEARCH_STEPS {
+            let third = (upper - lower) / 3.0;
+            if profit(lower + third) < profit(upper - third) {
+                lower += third;
+            } else {
+                upper -= third;
+            }
+        }
+
+        let max_size = (lower + upper) / 2.0;
+        let profit = profit(max_size);
+        (profit > 0.0).then_some(Cycle {
+            legs,
+            top_return,
+            max_size,
+            profit,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TriangularScanner;
+    use crate::book_management::fees::{FeeSchedule, FeeTier};
+    use crate::book_management::mock_books::book_with;
+    use crate::book_management::traded_instruments::Instrument;
+    use crate::exchange_connectivity::ExchangeType;
+    use crate::exchange_connectivity::orders::Side;
+
+    #[tokio::test]
+    async fn finds_profitable_cycles_and_sizes_them() {
+        let (binance, deribit) = (ExchangeType::Binance, ExchangeType::Deribit);
+        let no_fees = FeeSchedule::new;
+
+        let btc = book_with(
+            Instrument::BtcUsdt,
+            vec![
+                (
+                    binance,
+                    (vec![(39990.0, 1.0)], vec![(40000.0, 0.5), (43000.0, 1.0)]),
+                ),
+                (deribit, (vec![(39990.0, 1.0)], vec![(40000.0, 1.0)])),
+            ],
+            no_fees().with_venue(deribit, [FeeTier::new(0.0, 0.0, 0.01)]),
+        )
+        .await;
+        let eth_btc = book_with(
+            Instrument::EthBtc,
+            vec![(binance, (vec![(0.0499, 100.0)], vec![(0.05, 100.0)]))],
+            no_fees(),
+        )
+        .await;
+        let eth = book_with(
+            Instrument::EthUsdc,
+            vec![(binance, (vec![(2100.0, 100.0)], vec![(2101.0, 100.0)]))],
+            no_fees(),
+        )
+        .await;
+        let usdc = book_with(
+            Instrument::UsdcUsdt,
+            vec![(binance, (vec![(1.0, 1e6)], vec![(1.001, 1e6)]))],
+            no_fees(),
+        )
+        .await;
+
+        let scanner = TriangularScanner::new(vec![btc, eth_btc, eth, usdc]);
+        let routes = scanner.routes("USDT");
+        assert_eq!(routes.len(), 2);
+        assert!(routes.contains(&vec![
+            (Instrument::BtcUsdt, Side::Buy),
+            (Instrument::EthBtc, Side::Buy),
+            (Instrument::EthUsdc, Side::Sell),
+            (Instrument::UsdcUsdt, Side::Sell),
+        ]));
+
+        let cycles = scanner.scan("USDT");
+        assert_eq!(cycles.len(), 2);
+
+        // Buying BTC on Deribit and the rest on Binance is deeper, so makes
+        // more despite the fee.
+        let across = &cycles[0];
+        assert_eq!(across.venue(), None);
+        assert_eq!(across.legs[0].exchange, deribit);
+        assert!((across.top_return - (0.99 * 1.05 - 1.0)).abs() < 1e-6);
+        assert!((across.max_size - 40000.0).abs() < 1e-3);
+
+        // 1 USDT buys 1/40000 BTC, then 1/2000 ETH, sold for 1.05 USDC.
+        let cycle = &cycles[1];
+        assert_eq!(cycle.currency(), "USDT");
+        assert_eq!(cycle.venue(), Some(binance));
+        assert!((cycle.top_return - 0.05).abs() < 1e-6);
+        // Binance's second BTC level at 43000 loses money, so the cycle
+        // stops at the first level's 20000 USDT.
+        assert!((cycle.max_size - 20000.0).abs() < 1e-3);
+        assert!((cycle.profit - 1000.0).abs() < 1e-3);
+        assert!((cycle.fee_adjusted_return() - 0.05).abs() < 1e-6);
+    }
+}
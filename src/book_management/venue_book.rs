@@ -0,0 +1,221 @@
+//! The part of an aggregated book contributed by a single venue.
+//!
+//! Each venue's levels are kept in their own [`SubBook`] along with when
+//! they were last refreshed and whether the last refresh worked. The
+//! consolidated book is rebuilt from the sub-books that are fresh, so a
+//! venue which times out or falls behind drops out of the aggregate
+//! without taking the other venues with it.
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use super::consolidated::ConsolidatedBook;
+use super::{Ask, Bid};
+use crate::exchange_connectivity::ExchangeType;
+use crate::exchange_connectivity::clock::UpdateTimestamps;
+
+/// Whether a venue's sub-book can be trusted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VenueFreshness {
+    /// Refreshed successfully and recently.
+    Fresh,
+    /// The last refresh worked, but too long ago.
+    Stale,
+    /// The last refresh failed.
+    Failed,
+    /// Never refreshed.
+    Empty,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SubBook {
+    bids: Vec<Bid>,
+    asks: Vec<Ask>,
+    updated: Option<UpdateTimestamps>,
+    last_error: Option<String>,
+    consecutive_failures: u32,
+}
+
+impl SubBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_success(&mut self, bids: Vec<Bid>, asks: Vec<Ask>, times: UpdateTimestamps) {
+        self.bids = bids;
+        self.asks = asks;
+        self.updated = Some(times);
+        self.last_error = None;
+        self.consecutive_failures = 0;
+    }
+
+    /// Note a failed refresh, keeping the last good levels.
+    pub fn record_failure(&mut self, error: impl Into<String>) {
+        self.last_error = Some(error.into());
+        self.consecutive_failures += 1;
+    }
+
+    pub fn bids(&self) -> &[Bid] {
+        &self.bids
+    }
+
+    pub fn asks(&self) -> &[Ask] {
+        &self.asks
+    }
+
+    /// Timestamps of the last successful refresh.
+    pub fn updated(&self) -> Option<UpdateTimestamps> {
+        self.updated
+    }
+
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+
+    /// Freshness at local time `now`, counting levels read more than
+    /// `stale_after` ago as stale.
+    pub fn freshness(&self, now: Duration, stale_after: Duration) -> VenueFreshness {
+        match self.updated {
+            _ if self.consecutive_failures > 0 => VenueFreshness::Failed,
+            None => VenueFreshness::Empty,
+            Some(times) if now.saturating_sub(times.received) > stale_after => {
+                VenueFreshness::Stale
+            }
+            Some(_) => VenueFreshness::Fresh,
+        }
+    }
+}
+
+/// Consolidate the sub-books which are fresh at `now`.
+pub fn consolidate(
+    sub_books: &BTreeMap<ExchangeType, SubBook>,
+    now: Duration,
+    stale_after: Duration,
+) -> ConsolidatedBook {
+    let mut book = ConsolidatedBook::new();
+    for sub_book in sub_books.values() {
+        if sub_book.freshness(now, stale_after) == VenueFreshness::Fresh {
+            book.extend(sub_book.bids(), sub_book.asks());
+        }
+    }
+    book
+}
+
+/// The venues which failed to refresh, with why.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VenueErrors {
+    errors: BTreeMap<ExchangeType, String>,
+}
+
+impl VenueErrors {
+    pub fn insert(&mut self, exchange: ExchangeType, error: impl Into<String>) {
+        self.errors.insert(exchange, error.into());
+    }
+
+    pub fn get(&self, exchange: ExchangeType) -> Option<&str> {
+        self.errors.get(&exchange).map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ExchangeType, &str)> {
+        self.errors
+            .iter()
+            .map(|(exchange, error)| (*exchange, error.as_str()))
+    }
+
+    /// `Ok` if no venue failed.
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.is_empty() { Ok(()) } else { Err(self) }
+    }
+}
+
+impl fmt::Display for VenueErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors = self
+            .iter()
+            .map(|(exchange, error)| format!("{:?}: {}", exchange, error))
+            .collect::<Vec<_>>();
+        write!(f, "{}", errors.join("; "))
+    }
+}
+
+impl Error for VenueErrors {}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    use super::{SubBook, VenueFreshness, consolidate};
+    use crate::book_management::mock_books::fetched_at;
+    use crate::book_management::traded_instruments::Instrument;
+    use crate::book_management::{Ask, Bid, Order};
+    use crate::exchange_connectivity::ExchangeType;
+
+    fn sub_book(exchange: ExchangeType, price: f64, received: u64) -> SubBook {
+        let (bids, asks, times) = fetched_at(
+            vec![Bid::new(Instrument::BtcUsdt, exchange, 1.0, price)],
+            vec![Ask::new(Instrument::BtcUsdt, exchange, 1.0, price + 1.0)],
+            Duration::from_secs(received),
+        )
+        .unwrap();
+        let mut sub_book = SubBook::new();
+        sub_book.record_success(bids, asks, times);
+        sub_book
+    }
+
+    #[test]
+    fn only_fresh_venues_are_consolidated() {
+        let stale_after = Duration::from_secs(5);
+        let now = Duration::from_secs(100);
+
+        let mut sub_books = BTreeMap::from([
+            (
+                ExchangeType::Deribit,
+                sub_book(ExchangeType::Deribit, 100.0, 99),
+            ),
+            (
+                ExchangeType::Binance,
+                sub_book(ExchangeType::Binance, 101.0, 99),
+            ),
+        ]);
+        assert_eq!(consolidate(&sub_books, now, stale_after).bids().count(), 2);
+
+        // A failed refresh keeps the old levels but drops the venue.
+        let binance = sub_books.get_mut(&ExchangeType::Binance).unwrap();
+        binance.record_failure("timed out");
+        assert_eq!(binance.bids().len(), 1);
+        assert_eq!(binance.freshness(now, stale_after), VenueFreshness::Failed);
+
+        let book = consolidate(&sub_books, now, stale_after);
+        assert_eq!(book.best_bid().unwrap().price(), 100.0);
+        assert_eq!(book.bids().count(), 1);
+
+        // Deribit goes stale as time passes; Binance recovers.
+        sub_books.insert(
+            ExchangeType::Binance,
+            sub_book(ExchangeType::Binance, 102.0, 106),
+        );
+        let later = Duration::from_secs(106);
+        assert_eq!(
+            sub_books[&ExchangeType::Deribit].freshness(later, stale_after),
+            VenueFreshness::Stale
+        );
+        let book = consolidate(&sub_books, later, stale_after);
+        assert_eq!(book.best_bid().unwrap().price(), 102.0);
+        assert_eq!(book.bids().count(), 1);
+
+        assert_eq!(
+            SubBook::new().freshness(now, stale_after),
+            VenueFreshness::Empty
+        );
+    }
+}
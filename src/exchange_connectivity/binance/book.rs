@@ -1,26 +1,28 @@
 use super::Binance;
+use super::messages::{DepthResult, Response};
 use crate::book_management::{Ask, Bid};
+use crate::exchange_connectivity::message_id::WireId;
 use crate::{
-    book_management::{Order, traded_instruments::Instrument},
-    exchange_connectivity::{ConnectedExchangeForBook, ExchangeType},
+    book_management::traded_instruments::Instrument,
+    exchange_connectivity::{
+        BidsAsksResult, ConnectedExchangeForBook, ExchangeType, ReceivedFrame, fast_parse, levels,
+    },
 };
 
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-
-use std::error::Error;
+use std::sync::atomic::Ordering;
 
 use futures_util::SinkExt;
-use serde_json::{Value, json};
+use serde_json::json;
 
 impl Binance {
     async fn request_get_order_book(
         &self,
-        id: u64,
+        id: &WireId,
         instrument_name: &str,
         depth: u32,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let msg = json!({
-            "id": id.to_string(),
+            "id": id,
             "method": "depth",
             "params": {
                 "symbol": instrument_name,
@@ -33,23 +35,55 @@ impl Binance {
         Ok(())
     }
 
-    fn convert_vec_values_to_orders<T: Order>(
-        exchange: ExchangeType,
-        vec: &[Value],
-        instrument: Instrument,
-    ) -> Result<Vec<T>, Box<dyn Error + Send + Sync>> {
-        Ok(vec
-            .iter()
-            .filter_map(|elem| {
-                elem.as_array()
-                    .filter(|order_pair| order_pair.len() == 2)
-                    .and_then(|order_pair| {
-                        let price = order_pair[0].as_str()?.parse().ok()?;
-                        let qty = order_pair[1].as_str()?.parse().ok()?;
-                        Some(T::new(instrument, exchange, qty, price))
-                    })
-            })
-            .collect::<Vec<T>>())
+    /// Decode a `depth` response, trying the zero-copy fast path before
+    /// the typed decoder.
+    ///
+    /// Binance doesn't stamp depth responses, so only the receive time is
+    /// known.
+    fn decode_book(&self, frame: &ReceivedFrame, instrument: Instrument) -> BidsAsksResult {
+        let text = &frame.text;
+        let timestamp = self.clock.stamp(None, frame.received);
+
+        if let Some((bids, asks)) = fast_parse::parse_book(text, ExchangeType::Binance, instrument)
+        {
+            return Ok((bids, asks, timestamp));
+        }
+
+        let depth = serde_json::from_str::<Response<DepthResult>>(text)
+            .map_err(|err| format!("Failed to parse message: {}", err))?
+            .into_result()?;
+        log::debug!(
+            "Decoded Binance depth at update {} with the typed decoder",
+            depth.last_update_id
+        );
+
+        let bids = levels::into_orders::<Bid>(&depth.bids, ExchangeType::Binance, instrument);
+        let asks = levels::into_orders::<Ask>(&depth.asks, ExchangeType::Binance, instrument);
+        self.record_malformed_levels(instrument, bids.malformed + asks.malformed);
+
+        Ok((bids.orders, asks.orders, timestamp))
+    }
+
+    fn record_malformed_levels(&self, instrument: Instrument, count: usize) {
+        if count == 0 {
+            return;
+        }
+
+        let total = self
+            .malformed_levels
+            .fetch_add(count as u64, Ordering::Relaxed)
+            + count as u64;
+        log::warn!(
+            "Discarded {} malformed {} book levels from Binance ({} in total).",
+            count,
+            instrument,
+            total
+        );
+    }
+
+    /// Number of book levels discarded as malformed since connecting.
+    pub fn malformed_levels(&self) -> u64 {
+        self.malformed_levels.load(Ordering::Relaxed)
     }
 }
 
@@ -57,60 +91,29 @@ impl ConnectedExchangeForBook for Binance {
     fn to_instrument_name(instrument: Instrument) -> String {
         match instrument {
             Instrument::BtcUsdt => "BTCUSDT".to_string(),
+            Instrument::BtcUsdc => "BTCUSDC".to_string(),
             Instrument::EthUsdc => "ETHUSDC".to_string(),
-            Instrument::EthBtc => "ETCBTC".to_string(),
+            Instrument::EthBtc => "ETHBTC".to_string(),
+            Instrument::UsdcUsdt => "USDCUSDT".to_string(),
         }
     }
 
-    async fn pull_bids_asks(
-        &self,
-        mut depth: u32,
-        instrument: Instrument,
-    ) -> Result<(Vec<Bid>, Vec<Ask>, Duration), Box<dyn Error + Send + Sync>> {
+    async fn pull_bids_asks(&self, mut depth: u32, instrument: Instrument) -> BidsAsksResult {
         if depth > 5000 {
             depth = 5000;
         }
 
-        let req_id = self.get_new_id();
-
-        self.request_get_order_book(req_id, &Binance::to_instrument_name(instrument), depth)
-            .await?;
-
-        for _ in 0..5 {
-            let mut multimap = self.non_main_stream.lock().await;
-            let multimap_entry = multimap.get(&req_id);
-            if let Some(entry) = multimap_entry {
-                log::info!("Found msg with id {}: {}", &req_id, entry);
-                let msg: serde_json::Value = serde_json::from_str(&entry)
-                    .map_err(|err| format!("Failed to parse message: {}", err))?;
-
-                if let Ok(timestamp) = SystemTime::now().duration_since(UNIX_EPOCH) {
-                    if let Some(bids) = msg["result"]["bids"].as_array()
-                        && let Some(asks) = msg["result"]["asks"].as_array()
-                    {
-                        let bid_vec = Binance::convert_vec_values_to_orders(
-                            ExchangeType::Binance,
-                            &bids,
-                            instrument,
-                        )?;
-
-                        let ask_vec = Binance::convert_vec_values_to_orders(
-                            ExchangeType::Binance,
-                            &asks,
-                            instrument,
-                        )?;
-                        multimap.remove(&req_id);
-                        return Ok((bid_vec, ask_vec, timestamp));
-                    }
-                }
-            }
-            tokio::time::sleep(Duration::from_millis(100)).await;
-        }
+        let request = self.requests.allocate()?;
 
-        Err(
-            "Did not recieve response for order book from Binance after multiple attempts. "
-                .to_string()
-                .into(),
+        self.request_get_order_book(
+            &request.wire_text(),
+            &Binance::to_instrument_name(instrument),
+            depth,
         )
+        .await?;
+
+        let frame = self.await_response(&request).await?;
+        log::info!("Found depth msg with id {}", request.id());
+        self.decode_book(&frame, instrument)
     }
 }
@@ -0,0 +1,123 @@
+//! Typed models of the Binance WebSocket API messages we consume.
+use std::borrow::Cow;
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::exchange_connectivity::levels::RawLevel;
+use crate::exchange_connectivity::message_id::WireId;
+
+/// Just enough of any inbound frame to decide how to dispatch it.
+#[derive(Debug, Deserialize)]
+pub struct Envelope<'a> {
+    pub id: Option<WireId>,
+    #[serde(borrow)]
+    pub method: Option<Cow<'a, str>>,
+}
+
+/// A response to one of our requests.
+#[derive(Debug, Deserialize)]
+pub struct Response<T> {
+    pub id: WireId,
+    pub status: u16,
+    pub result: Option<T>,
+    pub error: Option<ApiError>,
+}
+
+impl<T> Response<T> {
+    /// Unwrap the result, turning an error status into an `Err`.
+    pub fn into_result(self) -> Result<T, String> {
+        match (self.result, self.error) {
+            (Some(result), _) => Ok(result),
+            (None, Some(err)) => Err(format!(
+                "Binance returned status {} for id {}: {}",
+                self.status, self.id, err
+            )),
+            (None, None) => Err(format!(
+                "Binance response for id {} had no result (status {})",
+                self.id, self.status
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApiError {
+    pub code: i64,
+    pub msg: String,
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (code {})", self.msg, self.code)
+    }
+}
+
+/// Result of the `depth` request. Prices and quantities arrive as
+/// decimal strings and are decoded straight into [`RawLevel`]s.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DepthResult {
+    pub last_update_id: u64,
+    pub bids: Vec<RawLevel>,
+    pub asks: Vec<RawLevel>,
+}
+
+/// Result of the `time` request.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerTime {
+    pub server_time: u64,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DepthResult, Envelope, Response, ServerTime};
+    use crate::exchange_connectivity::levels::RawLevel;
+
+    #[test]
+    fn parses_depth_response() {
+        let text = r#"{"id":"10001","status":200,"result":{"lastUpdateId":1027024,
+            "bids":[["4.00000000","431.00000000"]],"asks":[["4.00000200","12.00000000"],["4.1"]]},
+            "rateLimits":[{"rateLimitType":"REQUEST_WEIGHT","interval":"MINUTE","intervalNum":1,"limit":6000,"count":2}]}"#;
+
+        let envelope: Envelope = serde_json::from_str(text).unwrap();
+        assert_eq!(envelope.id.and_then(|id| id.as_u64()), Some(10001));
+
+        let depth = serde_json::from_str::<Response<DepthResult>>(text)
+            .unwrap()
+            .into_result()
+            .unwrap();
+        assert_eq!(depth.last_update_id, 1027024);
+        assert_eq!(
+            depth.bids[0],
+            RawLevel::Valid {
+                price: 4.0,
+                quantity: 431.0
+            }
+        );
+        assert_eq!(depth.asks[1], RawLevel::Malformed);
+    }
+
+    #[test]
+    fn surfaces_error_status() {
+        let text = r#"{"id":"10002","status":400,"error":{"code":-1121,"msg":"Invalid symbol."}}"#;
+
+        let err = serde_json::from_str::<Response<DepthResult>>(text)
+            .unwrap()
+            .into_result()
+            .unwrap_err();
+        assert!(err.contains("Invalid symbol."));
+    }
+
+    #[test]
+    fn parses_server_time() {
+        let text = r#"{"id":"10003","status":200,"result":{"serverTime":1656400526260}}"#;
+
+        let time = serde_json::from_str::<Response<ServerTime>>(text)
+            .unwrap()
+            .into_result()
+            .unwrap();
+        assert_eq!(time.server_time, 1656400526260);
+    }
+}
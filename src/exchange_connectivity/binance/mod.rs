@@ -1,20 +1,27 @@
 pub mod book;
-
-use std::sync::{
-    Arc,
-    atomic::{AtomicBool, AtomicU64, Ordering},
+mod messages;
+
+use std::sync::{Arc, atomic::AtomicU64};
+use std::time::Duration;
+
+use crate::exchange_connectivity::ReceivedFrame;
+use crate::exchange_connectivity::clock::{self, CLOCK_SYNC_INTERVAL, ClockSample, ClockSync};
+use crate::exchange_connectivity::message_id::{ControlId, MessageIdAllocator, RequestId, WireId};
+use crate::exchange_connectivity::proxy::{self, ProxyConfig};
+use crate::exchange_connectivity::shutdown::{CancellationToken, TaskGroup};
+use crate::exchange_connectivity::watchdog::{
+    ConnectionMonitor, WatchdogConfig, Watched, run_watchdog,
 };
-
 use futures_util::{
     SinkExt, StreamExt,
     stream::{SplitSink, SplitStream},
 };
-use multimap::MultiMap;
+use messages::{Envelope, Response, ServerTime};
 use serde_json::json;
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
 use tokio_tungstenite::{
-    MaybeTlsStream, WebSocketStream, connect_async, tungstenite::protocol::Message,
+    MaybeTlsStream, WebSocketStream, tungstenite::Utf8Bytes, tungstenite::protocol::Message,
 };
 
 type Sink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
@@ -23,21 +30,33 @@ type Stream = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
 const BINANCE_WS_URL: &str = "wss://ws-api.binance.com:9443/ws-api/v3";
 const BINANCE_WS_TEST_URL: &str = "wss://testnet.binance.vision/ws-api/v3";
 
+/// Interval at which Binance sends ping frames.
+const SERVER_PING_INTERVAL: Duration = Duration::from_secs(20);
+
+/// Longest a read waits on the socket before letting go of it, so a
+/// reconnect can swap the stream out from under a stalled read.
+const READ_TIMEOUT: Duration = Duration::from_millis(250);
+
 /// Connect then ws manager
 ///
-/// warning that ids for binance are 64-bit signed integers
-/// but program enforces u64.
+/// Request ids are sent as strings; responses carrying string or
+/// signed-integer ids are both understood.
 #[derive(Debug)]
 pub struct Binance {
+    url: String,
+    proxy: Option<ProxyConfig>,
     sink: Arc<Mutex<Sink>>,
     stream: Arc<Mutex<Stream>>,
-    non_main_stream: Arc<Mutex<MultiMap<u64, String>>>,
-    keep_alive: Arc<AtomicBool>,
-    curr_msg_id: AtomicU64,
+    requests: MessageIdAllocator<ReceivedFrame>,
+    /// Tasks serving this connection, stopped together on shutdown.
+    pub(crate) tasks: TaskGroup,
+    malformed_levels: AtomicU64,
+    pub(super) clock: ClockSync,
+    pub(super) monitor: ConnectionMonitor,
 }
 
 impl Binance {
-    pub async fn connect() -> Option<(Self, Arc<AtomicBool>)> {
+    pub async fn connect(proxy: Option<ProxyConfig>) -> Option<(Self, CancellationToken)> {
         let connection_url = if cfg!(test) || cfg!(feature = "test-apis") {
             log::info!("Using Binance test URL");
             BINANCE_WS_TEST_URL
@@ -46,7 +65,16 @@ impl Binance {
             BINANCE_WS_URL
         };
 
-        let connection_response = connect_async(connection_url).await;
+        Self::connect_to(connection_url, proxy).await
+    }
+
+    /// Connect to the Binance WebSocket API served at `connection_url`,
+    /// through `proxy` if one is given.
+    pub(crate) async fn connect_to(
+        connection_url: &str,
+        proxy: Option<ProxyConfig>,
+    ) -> Option<(Self, CancellationToken)> {
+        let connection_response = proxy::connect_ws(connection_url, proxy.as_ref()).await;
 
         match connection_response {
             Err(err) => {
@@ -54,47 +82,42 @@ impl Binance {
                 log::error!("Error connecting to Binance client: {}", err);
                 None
             }
-            Ok(ok) => {
+            Ok(ws) => {
                 log::info!("Connection established with Binance client");
-                let (sink, stream) = ok.0.split();
-                let keep_alive = Arc::new(AtomicBool::new(true));
+                let (sink, stream) = ws.split();
+                let shutdown = CancellationToken::new();
 
                 Some((
                     Binance {
+                        url: connection_url.to_string(),
+                        proxy,
                         sink: Arc::new(Mutex::new(sink)),
                         stream: Arc::new(Mutex::new(stream)),
-                        non_main_stream: Arc::new(Mutex::new(MultiMap::new())),
-                        keep_alive: Arc::clone(&keep_alive),
-                        curr_msg_id: AtomicU64::new(10000),
+                        requests: MessageIdAllocator::new(),
+                        tasks: TaskGroup::new(shutdown.clone()),
+                        malformed_levels: AtomicU64::new(0),
+                        clock: ClockSync::new(),
+                        monitor: ConnectionMonitor::default(),
                     },
-                    keep_alive,
+                    shutdown,
                 ))
             }
         }
     }
 
     pub async fn ws_manager(&self) {
-        loop {
-            let keep_running = {
-                let guard = &self.keep_alive;
-                guard.load(Ordering::Relaxed)
-            };
-
-            if !keep_running {
-                break;
+        // Reads time out every READ_TIMEOUT, so cancellation is noticed
+        // without cutting a message off half way through handling.
+        while !self.tasks.token().is_cancelled() {
+            if let Err(err) = self.process_next_message().await {
+                log::error!("Error processing WebSocket message: {}", err);
             }
-
-            while self.keep_alive.load(Ordering::Relaxed) {
-                if let Err(err) = self.process_next_message().await {
-                    log::error!("Error processing WebSocket message: {}", err);
-                }
-            }
-
-            log::info!("WebSocket manager shutting down gracefully.");
         }
+
+        log::info!("WebSocket manager shutting down gracefully.");
     }
 
-    async fn ws_pong(&self, id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    async fn ws_pong(&self, id: &WireId) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let msg = json!({
             "id": id,
             "method": "pong",
@@ -108,31 +131,56 @@ impl Binance {
     async fn process_next_message(&self) -> Result<(), String> {
         let next_message = {
             let mut stream = self.stream.lock().await;
-            stream.next().await
+            match tokio::time::timeout(READ_TIMEOUT, stream.next()).await {
+                Ok(message) => message,
+                Err(_) => return Ok(()),
+            }
         };
 
+        let received = clock::now();
         match next_message {
-            Some(Ok(Message::Text(text))) => self.handle_text_message(&text).await,
+            Some(Ok(Message::Text(text))) => {
+                self.monitor.record_inbound(received);
+                self.handle_text_message(text, received).await
+            }
+            Some(Ok(Message::Ping(_))) => {
+                // tungstenite queues the pong itself.
+                self.monitor.record_heartbeat(received);
+                Ok(())
+            }
+            Some(Ok(Message::Pong(_))) => {
+                self.monitor.record_inbound(received);
+                Ok(())
+            }
             Some(Ok(_)) => {
+                self.monitor.record_inbound(received);
                 log::warn!("Unexpected non-text message received.");
                 Ok(())
             }
-            Some(Err(err)) => Err(format!("Error reading WebSocket stream: {}", err)),
+            Some(Err(err)) => {
+                self.monitor.mark_stale();
+                Err(format!("Error reading WebSocket stream: {}", err))
+            }
             None => {
                 log::warn!("WebSocket stream closed.");
+                self.monitor.mark_stale();
+                tokio::time::sleep(READ_TIMEOUT).await;
                 Ok(())
             }
         }
     }
 
-    async fn handle_text_message(&self, text: &str) -> Result<(), String> {
-        let msg: serde_json::Value = serde_json::from_str(text)
+    async fn handle_text_message(&self, text: Utf8Bytes, received: Duration) -> Result<(), String> {
+        let envelope: Envelope = serde_json::from_str(&text)
             .map_err(|err| format!("Failed to parse message: {}", err))?;
 
-        if let Some("ping") = msg["method"].as_str()
-            && let Some(id) = msg["id"].as_str()
+        let request_id = envelope.id.as_ref().and_then(WireId::as_u64);
+
+        if let Some("ping") = envelope.method.as_deref()
+            && let Some(id) = &envelope.id
         {
             log::info!("Processed message: {}", text);
+            self.monitor.record_heartbeat(received);
             match self.ws_pong(id).await {
                 Err(err) => {
                     log::error!("Error sending pong: {}", err);
@@ -141,17 +189,19 @@ impl Binance {
                     log::info!("Successfully responded pong to Binance ping.");
                 }
             }
-        } else if let Some(id_str) = msg["id"].as_str()
-            && let Ok(id) = id_str.parse()
-        {
-            self.non_main_stream
-                .lock()
-                .await
-                .insert(id, text.to_string());
-            log::info!(
-                "Unprocessed message with valid ID from Binance stored with id: {}",
-                id
-            );
+        } else if let Some(control) = request_id.and_then(ControlId::from_id) {
+            log::info!("Recieved Binance {:?} response {}", control, text);
+        } else if let Some(id) = request_id {
+            let frame = ReceivedFrame {
+                text: text.clone(),
+                received,
+            };
+            if self.requests.deliver(id, frame).is_err() {
+                log::warn!(
+                    "Dropping Binance response to unknown or expired request id {}",
+                    id
+                );
+            }
         } else {
             log::info!(
                 "Unprocessed message with no valid ID component from Binance: {}",
@@ -164,7 +214,7 @@ impl Binance {
 
     pub async fn ws_request_time(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let msg = json!({
-            "id": "1000",
+            "id": ControlId::Ping.id().to_string(),
             "method": "ping",
         });
 
@@ -173,27 +223,128 @@ impl Binance {
         Ok(())
     }
 
-    fn get_new_id(&self) -> u64 {
-        if self.curr_msg_id.load(Ordering::Relaxed) > 1000000 {
-            self.curr_msg_id.store(10000, Ordering::Relaxed);
-        } else {
-            self.curr_msg_id.fetch_add(1, Ordering::Relaxed);
+    /// Wait for the response to `request` to be read off the socket.
+    async fn await_response(
+        &self,
+        request: &RequestId<'_, ReceivedFrame>,
+    ) -> Result<ReceivedFrame, Box<dyn std::error::Error + Send + Sync>> {
+        for _ in 0..5 {
+            if let Some(frame) = request.try_take() {
+                return Ok(frame);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        Err(format!(
+            "Did not recieve response for id {} from Binance after multiple attempts.",
+            request.id()
+        )
+        .into())
+    }
+
+    /// Take one `time` round trip and record it.
+    async fn sync_clock(&self) -> Result<ClockSample, Box<dyn std::error::Error + Send + Sync>> {
+        let request = self.requests.allocate()?;
+        let msg = json!({
+            "id": request.wire_text(),
+            "method": "time",
+        });
+
+        let sent = clock::now();
+        self.sink.lock().await.send(msg.to_string().into()).await?;
+        let frame = self.await_response(&request).await?;
+
+        let time = serde_json::from_str::<Response<ServerTime>>(&frame.text)?.into_result()?;
+        let sample = ClockSample {
+            sent,
+            exchange: Duration::from_millis(time.server_time),
+            received: frame.received,
+        };
+        self.clock.record_sample(sample);
+
+        Ok(sample)
+    }
+
+    /// Watch for stalls, expecting a ping frame every 20 seconds.
+    pub async fn watchdog_task(&self) {
+        run_watchdog(
+            self,
+            self.tasks.token(),
+            WatchdogConfig::for_heartbeat(SERVER_PING_INTERVAL),
+        )
+        .await;
+    }
+
+    /// Periodically re-estimate the clock offset to Binance.
+    pub async fn clock_sync_task(&self) {
+        while !self.tasks.token().is_cancelled() {
+            match self.sync_clock().await {
+                Ok(sample) => log::info!(
+                    "Binance clock offset {}us with round trip {:?}.",
+                    sample.offset_micros(),
+                    sample.rtt()
+                ),
+                Err(err) => log::warn!("Failed to sync clock with Binance: {}", err),
+            }
+
+            self.tasks.token().sleep(CLOCK_SYNC_INTERVAL).await;
+        }
+    }
+
+    /// Stop every task serving this connection, send a Close frame and
+    /// wait for the tasks to finish.
+    pub async fn shutdown(&self) {
+        self.tasks.token().cancel();
+
+        if let Err(err) = self.sink.lock().await.send(Message::Close(None)).await {
+            log::warn!("Failed to send Close frame to Binance: {}", err);
         }
 
-        self.curr_msg_id.load(Ordering::Relaxed)
+        self.tasks.join().await;
+        log::info!("Binance connection shut down.");
+    }
+}
+
+impl Watched for Binance {
+    const NAME: &'static str = "Binance";
+
+    fn monitor(&self) -> &ConnectionMonitor {
+        &self.monitor
+    }
+
+    async fn probe(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.sink
+            .lock()
+            .await
+            .send(Message::Ping(Default::default()))
+            .await?;
+        Ok(())
+    }
+
+    async fn reconnect(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let (sink, stream) = proxy::connect_ws(&self.url, self.proxy.as_ref())
+            .await?
+            .split();
+        *self.sink.lock().await = sink;
+        *self.stream.lock().await = stream;
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::exchange_connectivity::clock;
+    use crate::exchange_connectivity::mock_server::MockServer;
+    use crate::exchange_connectivity::shutdown::CancellationToken;
+    use crate::exchange_connectivity::watchdog::{WatchdogConfig, run_watchdog};
     use crate::exchange_connectivity::{ConnectedExchangeForBook, Instrument};
     use std::sync::Arc;
-    use std::sync::atomic::AtomicBool;
+    use std::time::Duration;
 
     use super::Binance;
 
-    async fn setup() -> (Binance, Arc<AtomicBool>) {
-        match Binance::connect().await {
+    async fn setup() -> (Binance, CancellationToken) {
+        match Binance::connect(None).await {
             None => panic!("Expected successful connection."),
             Some(x) => x,
         }
@@ -201,9 +352,8 @@ mod test {
 
     #[tokio::test]
     async fn test_connect() {
-        match Binance::connect().await {
-            None => panic!("Expected successful connection."),
-            Some(_) => (),
+        if Binance::connect(None).await.is_none() {
+            panic!("Expected successful connection.")
         }
     }
 
@@ -250,4 +400,33 @@ mod test {
         assert!(result_eth_usdc.0.len() <= 10);
         assert!(result_eth_usdc.1.len() <= 10);
     }
+
+    #[tokio::test]
+    async fn ping_probes_are_answered() {
+        let server = MockServer::start(|_: &serde_json::Value| Vec::new()).await;
+        let (binance, _) = Binance::connect_to(&server.url, None).await.unwrap();
+        let binance = Arc::new(binance);
+
+        let binance_clone = Arc::clone(&binance);
+        tokio::spawn(async move { binance_clone.ws_manager().await });
+
+        let config = WatchdogConfig {
+            check_interval: Duration::from_millis(20),
+            probe_after: Duration::from_millis(100),
+            heartbeat_timeout: None,
+            probe_timeout: Duration::from_millis(200),
+        };
+        let started = clock::now();
+        let shutdown = CancellationToken::new();
+        let (binance_clone, running) = (Arc::clone(&binance), shutdown.clone());
+        tokio::spawn(async move { run_watchdog(&*binance_clone, &running, config).await });
+
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        shutdown.cancel();
+
+        // The server never speaks, so only a Pong answering a probe moves
+        // the heartbeat on without a reconnect.
+        assert!(binance.monitor.last_heartbeat() >= started + config.probe_after);
+        assert_eq!(binance.monitor.reconnects(), 0);
+    }
 }
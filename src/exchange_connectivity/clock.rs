@@ -0,0 +1,252 @@
+//! Exchange clock-offset estimation and feed latency measurement.
+//!
+//! Each connector periodically asks the exchange for its time and feeds
+//! the round trip into a [`ClockSync`]. The offset is taken from the
+//! sample with the smallest round-trip time among the most recent ones,
+//! as that sample bounds the offset most tightly (the usual NTP trick).
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How often connectors re-estimate the clock offset.
+pub const CLOCK_SYNC_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Number of recent samples the offset estimate is chosen from.
+const SAMPLE_WINDOW: usize = 8;
+
+/// Weight given to the newest sample in the latency moving average.
+const LATENCY_SMOOTHING: f64 = 0.2;
+
+/// Current local time as a duration after the UNIX epoch.
+pub fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Timestamps carried by every book update.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UpdateTimestamps {
+    /// Time stamped by the exchange on its own clock, if it sent one.
+    pub exchange: Option<Duration>,
+    /// Local time at which the response was read off the socket.
+    pub received: Duration,
+    /// Exchange time mapped onto the local clock with the current offset
+    /// estimate. Taken as-is before the first clock sync, and falls back
+    /// to the receive time if the exchange sent no timestamp.
+    pub normalised: Duration,
+}
+
+impl UpdateTimestamps {
+    /// One-way latency of this update, if the exchange stamped it.
+    pub fn latency(&self) -> Option<Duration> {
+        self.exchange
+            .map(|_| self.received.saturating_sub(self.normalised))
+    }
+}
+
+/// One round trip of a time request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClockSample {
+    pub sent: Duration,
+    pub exchange: Duration,
+    pub received: Duration,
+}
+
+impl ClockSample {
+    pub fn rtt(&self) -> Duration {
+        self.received.saturating_sub(self.sent)
+    }
+
+    /// Exchange clock minus local clock, in microseconds, assuming the
+    /// exchange stamped the request halfway through the round trip.
+    pub fn offset_micros(&self) -> i64 {
+        let midpoint = self.sent + self.rtt() / 2;
+        self.exchange.as_micros() as i64 - midpoint.as_micros() as i64
+    }
+}
+
+/// Best current estimate of an exchange's clock.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClockEstimate {
+    /// Exchange clock minus local clock, in microseconds.
+    pub offset_micros: i64,
+    /// Round-trip time of the sample the offset was taken from.
+    pub rtt: Duration,
+    /// Local time at which that sample was received.
+    pub measured_at: Duration,
+}
+
+/// One-way feed latency of a venue.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FeedLatency {
+    /// Latency of the most recent update.
+    pub last: Duration,
+    /// Exponentially weighted moving average over updates.
+    pub average: Duration,
+    /// Whether the figures come from exchange-stamped updates, or are
+    /// half of the clock-sync round trip because the venue doesn't stamp
+    /// its book responses.
+    pub from_updates: bool,
+}
+
+#[derive(Debug, Default)]
+struct ClockState {
+    samples: VecDeque<ClockSample>,
+    latency: Option<(Duration, f64)>,
+}
+
+/// Per-connection clock offset, round-trip and latency tracking.
+#[derive(Debug, Default)]
+pub struct ClockSync {
+    state: Mutex<ClockState>,
+}
+
+impl ClockSync {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the round trip of a time request.
+    pub fn record_sample(&self, sample: ClockSample) {
+        let mut state = self.state.lock().unwrap();
+        if state.samples.len() >= SAMPLE_WINDOW {
+            state.samples.pop_front();
+        }
+        state.samples.push_back(sample);
+    }
+
+    pub fn estimate(&self) -> Option<ClockEstimate> {
+        let state = self.state.lock().unwrap();
+        state
+            .samples
+            .iter()
+            .min_by_key(|sample| sample.rtt())
+            .map(|sample| ClockEstimate {
+                offset_micros: sample.offset_micros(),
+                rtt: sample.rtt(),
+                measured_at: sample.received,
+            })
+    }
+
+    /// Map an exchange timestamp onto the local clock.
+    pub fn normalise(&self, exchange_time: Duration) -> Option<Duration> {
+        let offset = self.estimate()?.offset_micros;
+        let local = exchange_time.as_micros() as i64 - offset;
+        Some(Duration::from_micros(local.max(0) as u64))
+    }
+
+    /// Build the timestamps of an update received at `received`, and
+    /// fold its latency into the running statistics.
+    pub fn stamp(&self, exchange_time: Option<Duration>, received: Duration) -> UpdateTimestamps {
+        let synced = exchange_time.and_then(|time| self.normalise(time));
+        let timestamps = UpdateTimestamps {
+            exchange: exchange_time,
+            received,
+            normalised: synced.or(exchange_time).unwrap_or(received),
+        };
+
+        if synced.is_some()
+            && let Some(latency) = timestamps.latency()
+        {
+            self.record_latency(latency);
+        }
+
+        timestamps
+    }
+
+    fn record_latency(&self, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let average = match state.latency {
+            Some((_, average)) => {
+                LATENCY_SMOOTHING * latency.as_secs_f64() + (1.0 - LATENCY_SMOOTHING) * average
+            }
+            None => latency.as_secs_f64(),
+        };
+        state.latency = Some((latency, average));
+    }
+
+    /// One-way feed latency, measured from exchange-stamped updates when
+    /// there are any and estimated as half the round trip otherwise.
+    pub fn feed_latency(&self) -> Option<FeedLatency> {
+        if let Some((last, average)) = self.state.lock().unwrap().latency {
+            return Some(FeedLatency {
+                last,
+                average: Duration::from_secs_f64(average),
+                from_updates: true,
+            });
+        }
+
+        let half_rtt = self.estimate()?.rtt / 2;
+        Some(FeedLatency {
+            last: half_rtt,
+            average: half_rtt,
+            from_updates: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{ClockSample, ClockSync};
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn offset_comes_from_tightest_round_trip() {
+        let clock = ClockSync::new();
+
+        // exchange runs 500ms ahead; the first sample is skewed by a slow
+        // return path, the second is tight.
+        clock.record_sample(ClockSample {
+            sent: ms(1_000),
+            exchange: ms(1_510),
+            received: ms(1_200),
+        });
+        clock.record_sample(ClockSample {
+            sent: ms(2_000),
+            exchange: ms(2_505),
+            received: ms(2_010),
+        });
+
+        let estimate = clock.estimate().unwrap();
+        assert_eq!(estimate.rtt, ms(10));
+        assert_eq!(estimate.offset_micros, 500_000);
+        assert_eq!(clock.normalise(ms(10_500)), Some(ms(10_000)));
+    }
+
+    #[test]
+    fn stamps_and_measures_latency() {
+        let clock = ClockSync::new();
+
+        let unsynced = clock.stamp(Some(ms(4_000)), ms(4_025));
+        assert_eq!(unsynced.normalised, ms(4_000));
+        assert_eq!(unsynced.latency(), Some(ms(25)));
+        assert!(clock.feed_latency().is_none());
+
+        clock.record_sample(ClockSample {
+            sent: ms(0),
+            exchange: ms(1_010),
+            received: ms(20),
+        });
+        let half_rtt = clock.feed_latency().unwrap();
+        assert!(!half_rtt.from_updates);
+        assert_eq!(half_rtt.last, ms(10));
+
+        let synced = clock.stamp(Some(ms(6_000)), ms(5_030));
+        assert_eq!(synced.normalised, ms(5_000));
+        assert_eq!(synced.latency(), Some(ms(30)));
+
+        let latency = clock.feed_latency().unwrap();
+        assert!(latency.from_updates);
+        assert_eq!(latency.last, ms(30));
+
+        let unstamped = clock.stamp(None, ms(7_000));
+        assert_eq!(unstamped.normalised, ms(7_000));
+        assert_eq!(unstamped.latency(), None);
+    }
+}
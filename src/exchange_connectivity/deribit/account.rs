@@ -0,0 +1,516 @@
+//! Private account data: balances, positions and open orders.
+//!
+//! Snapshots are fetched with the `private/get_*` endpoints on the
+//! authenticated session and kept current by the `user.portfolio` and
+//! `user.changes` subscriptions. Both feed the same [`AccountState`],
+//! which can be read at any time with [`Deribit::account_state`].
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer};
+use serde_json::json;
+
+use super::Deribit;
+use super::messages::{Notification, SubscriptionParams};
+use crate::exchange_connectivity::clock;
+use crate::exchange_connectivity::orders::OrderState;
+
+/// Balances and margin of one currency, from `private/get_account_summary`
+/// or a `user.portfolio` notification.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct AccountSummary {
+    pub currency: String,
+    pub balance: f64,
+    pub equity: f64,
+    pub available_funds: f64,
+    pub margin_balance: f64,
+    pub initial_margin: f64,
+    pub maintenance_margin: f64,
+    #[serde(default)]
+    pub total_pl: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Buy,
+    Sell,
+    /// Reported for positions that are flat.
+    Zero,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Position {
+    pub instrument_name: String,
+    pub kind: String,
+    pub direction: Direction,
+    /// Signed size, negative when short.
+    pub size: f64,
+    pub average_price: f64,
+    #[serde(default)]
+    pub mark_price: f64,
+    #[serde(default)]
+    pub floating_profit_loss: f64,
+}
+
+/// An order as reported by the private endpoints and user channels.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct OrderInfo {
+    pub order_id: String,
+    pub instrument_name: String,
+    pub direction: Direction,
+    /// Limit price, or `None` for market orders.
+    #[serde(deserialize_with = "limit_price")]
+    pub price: Option<f64>,
+    pub amount: f64,
+    #[serde(default)]
+    pub filled_amount: f64,
+    #[serde(default)]
+    pub average_price: f64,
+    pub order_state: OrderState,
+    pub order_type: String,
+    #[serde(default)]
+    pub label: String,
+    pub creation_timestamp: u64,
+    pub last_update_timestamp: u64,
+}
+
+/// Market orders carry the string `"market_price"` instead of a price.
+fn limit_price<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Price {
+        Limit(f64),
+        Market(#[allow(dead_code)] String),
+    }
+
+    Ok(match Price::deserialize(deserializer)? {
+        Price::Limit(price) => Some(price),
+        Price::Market(_) => None,
+    })
+}
+
+/// One of our fills.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct UserTrade {
+    pub trade_id: String,
+    pub order_id: String,
+    pub instrument_name: String,
+    pub direction: Direction,
+    pub price: f64,
+    pub amount: f64,
+    #[serde(default)]
+    pub fee: f64,
+    #[serde(default)]
+    pub fee_currency: String,
+    pub timestamp: u64,
+}
+
+/// Data of a `user.changes` notification.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct AccountChanges {
+    #[serde(default)]
+    pub orders: Vec<OrderInfo>,
+    #[serde(default)]
+    pub positions: Vec<Position>,
+    #[serde(default)]
+    pub trades: Vec<UserTrade>,
+}
+
+/// Our balances, positions and open orders on Deribit.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AccountState {
+    /// Keyed by upper-case currency.
+    pub summaries: BTreeMap<String, AccountSummary>,
+    /// Open positions keyed by instrument name.
+    pub positions: BTreeMap<String, Position>,
+    /// Open orders keyed by order id.
+    pub open_orders: BTreeMap<String, OrderInfo>,
+    /// Local time of the last update, if there has been one.
+    pub updated: Option<Duration>,
+    /// Currency whose snapshot or channel each position came from, keyed
+    /// by instrument name. Deribit groups by settlement currency, which
+    /// is not always the instrument's base, e.g. `BTC_USDC-PERPETUAL` is
+    /// under `USDC`.
+    position_currencies: BTreeMap<String, String>,
+    /// The same for open orders, keyed by order id.
+    order_currencies: BTreeMap<String, String>,
+}
+
+impl AccountState {
+    pub fn balance(&self, currency: &str) -> Option<f64> {
+        self.summaries
+            .get(&currency.to_uppercase())
+            .map(|summary| summary.balance)
+    }
+
+    /// Signed position in `instrument_name`, zero if flat.
+    pub fn position(&self, instrument_name: &str) -> f64 {
+        self.positions
+            .get(instrument_name)
+            .map_or(0.0, |position| position.size)
+    }
+
+    pub fn open_orders_for<'a>(
+        &'a self,
+        instrument_name: &'a str,
+    ) -> impl Iterator<Item = &'a OrderInfo> {
+        self.open_orders
+            .values()
+            .filter(move |order| order.instrument_name == instrument_name)
+    }
+
+    fn update_summary(&mut self, summary: AccountSummary) {
+        self.summaries
+            .insert(summary.currency.to_uppercase(), summary);
+    }
+
+    fn update_position(&mut self, currency: &str, position: Position) {
+        let name = position.instrument_name.clone();
+        if position.size == 0.0 {
+            self.positions.remove(&name);
+            self.position_currencies.remove(&name);
+        } else {
+            self.positions.insert(name.clone(), position);
+            self.position_currencies
+                .insert(name, currency.to_uppercase());
+        }
+    }
+
+    fn update_order(&mut self, currency: &str, order: OrderInfo) {
+        let id = order.order_id.clone();
+        if order.order_state.is_open() {
+            self.open_orders.insert(id.clone(), order);
+            self.order_currencies.insert(id, currency.to_uppercase());
+        } else {
+            self.open_orders.remove(&id);
+            self.order_currencies.remove(&id);
+        }
+    }
+
+    /// Replace every position from `currency` with a fresh snapshot.
+    fn replace_positions(&mut self, currency: &str, positions: Vec<Position>) {
+        let stale = Self::from_currency(&self.position_currencies, currency);
+        for name in stale {
+            self.positions.remove(&name);
+            self.position_currencies.remove(&name);
+        }
+        positions
+            .into_iter()
+            .for_each(|position| self.update_position(currency, position));
+    }
+
+    fn replace_open_orders(&mut self, currency: &str, orders: Vec<OrderInfo>) {
+        let stale = Self::from_currency(&self.order_currencies, currency);
+        for id in stale {
+            self.open_orders.remove(&id);
+            self.order_currencies.remove(&id);
+        }
+        orders
+            .into_iter()
+            .for_each(|order| self.update_order(currency, order));
+    }
+
+    /// Keys of `currencies` recorded as coming from `currency`.
+    fn from_currency(currencies: &BTreeMap<String, String>, currency: &str) -> Vec<String> {
+        currencies
+            .iter()
+            .filter(|(_, from)| from.eq_ignore_ascii_case(currency))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Apply a `user.changes` notification for `currency`.
+    fn apply_changes(&mut self, currency: &str, changes: AccountChanges) {
+        changes
+            .positions
+            .into_iter()
+            .for_each(|position| self.update_position(currency, position));
+        changes
+            .orders
+            .into_iter()
+            .for_each(|order| self.update_order(currency, order));
+    }
+}
+
+impl Deribit {
+    pub async fn get_account_summary(
+        &self,
+        currency: &str,
+    ) -> Result<AccountSummary, Box<dyn std::error::Error + Send + Sync>> {
+        let summary: AccountSummary = self
+            .request(
+                "private/get_account_summary",
+                json!({ "currency": currency }),
+            )
+            .await?;
+
+        let mut account = self.account.lock().await;
+        account.update_summary(summary.clone());
+        account.updated = Some(clock::now());
+        Ok(summary)
+    }
+
+    pub async fn get_positions(
+        &self,
+        currency: &str,
+    ) -> Result<Vec<Position>, Box<dyn std::error::Error + Send + Sync>> {
+        let positions: Vec<Position> = self
+            .request("private/get_positions", json!({ "currency": currency }))
+            .await?;
+
+        let mut account = self.account.lock().await;
+        account.replace_positions(currency, positions.clone());
+        account.updated = Some(clock::now());
+        Ok(positions)
+    }
+
+    pub async fn get_open_orders_by_currency(
+        &self,
+        currency: &str,
+    ) -> Result<Vec<OrderInfo>, Box<dyn std::error::Error + Send + Sync>> {
+        let orders: Vec<OrderInfo> = self
+            .request(
+                "private/get_open_orders_by_currency",
+                json!({ "currency": currency }),
+            )
+            .await?;
+
+        let mut account = self.account.lock().await;
+        account.replace_open_orders(currency, orders.clone());
+        account.updated = Some(clock::now());
+        Ok(orders)
+    }
+
+    /// Fetch a fresh snapshot of everything held in `currency`.
+    pub async fn refresh_account(
+        &self,
+        currency: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.get_account_summary(currency).await?;
+        self.get_positions(currency).await?;
+        self.get_open_orders_by_currency(currency).await?;
+        Ok(())
+    }
+
+    /// Subscribe to portfolio and order/position changes in `currency`.
+    /// Returns the channels Deribit confirmed.
+    pub async fn subscribe_account(
+        &self,
+        currency: &str,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let channels = [
+            format!("user.portfolio.{}", currency.to_lowercase()),
+            format!("user.changes.any.{}.raw", currency.to_uppercase()),
+        ];
+
+        let subscribed = self.subscribe(channels.to_vec()).await?;
+        log::info!("Subscribed to Deribit account channels {:?}", subscribed);
+        Ok(subscribed)
+    }
+
+    /// Current view of our account.
+    pub async fn account_state(&self) -> AccountState {
+        self.account.lock().await.clone()
+    }
+
+    pub(super) async fn handle_account_notification(
+        &self,
+        channel: &str,
+        text: &str,
+    ) -> Result<(), String> {
+        if channel.starts_with("user.portfolio.") {
+            let portfolio =
+                serde_json::from_str::<Notification<SubscriptionParams<AccountSummary>>>(text)
+                    .map_err(|err| format!("Failed to parse {}: {}", channel, err))?;
+
+            let mut account = self.account.lock().await;
+            account.update_summary(portfolio.params.data);
+            account.updated = Some(clock::now());
+        } else if channel.starts_with("user.changes.") {
+            // user.changes.{kind}.{currency}.{interval}
+            let currency = channel
+                .split('.')
+                .nth(3)
+                .ok_or_else(|| format!("No currency in channel {}", channel))?;
+            let changes =
+                serde_json::from_str::<Notification<SubscriptionParams<AccountChanges>>>(text)
+                    .map_err(|err| format!("Failed to parse {}: {}", channel, err))?;
+
+            let mut account = self.account.lock().await;
+            account.apply_changes(currency, changes.params.data);
+            account.updated = Some(clock::now());
+        } else {
+            log::info!("Unhandled Deribit account channel {}", channel);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::{AccountChanges, AccountState, Direction, OrderInfo, Position};
+    use crate::exchange_connectivity::deribit::Deribit;
+    use crate::exchange_connectivity::mock_server::{
+        MockServer, connect_deribit, deribit_fixture, deribit_subscription,
+    };
+    use crate::exchange_connectivity::orders::OrderState;
+
+    async fn wait_for_state(
+        deribit: &Deribit,
+        done: impl Fn(&AccountState) -> bool,
+    ) -> AccountState {
+        for _ in 0..50 {
+            let state = deribit.account_state().await;
+            if done(&state) {
+                return state;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("Account state never reached the expected state");
+    }
+
+    #[test]
+    fn market_orders_have_no_price() {
+        let order: OrderInfo = serde_json::from_value(json!({
+            "order_id": "1", "instrument_name": "BTC_USDT", "direction": "buy",
+            "price": "market_price", "amount": 0.1, "order_state": "filled",
+            "order_type": "market", "creation_timestamp": 1, "last_update_timestamp": 2,
+        }))
+        .unwrap();
+
+        assert_eq!(order.price, None);
+        assert_eq!(order.direction, Direction::Buy);
+        assert!(!order.order_state.is_open());
+    }
+
+    #[test]
+    fn snapshots_replace_only_their_settlement_currency() {
+        let position = |name: &str, size: f64| Position {
+            instrument_name: name.to_string(),
+            kind: "future".to_string(),
+            direction: Direction::Buy,
+            size,
+            average_price: 42000.0,
+            mark_price: 0.0,
+            floating_profit_loss: 0.0,
+        };
+        let order = |id: &str, name: &str| OrderInfo {
+            order_id: id.to_string(),
+            instrument_name: name.to_string(),
+            direction: Direction::Buy,
+            price: Some(42000.0),
+            amount: 0.1,
+            filled_amount: 0.0,
+            average_price: 0.0,
+            order_state: OrderState::Open,
+            order_type: "limit".to_string(),
+            label: String::new(),
+            creation_timestamp: 1,
+            last_update_timestamp: 1,
+        };
+
+        let mut state = AccountState::default();
+        state.replace_positions("USDC", vec![position("BTC_USDC-PERPETUAL", 0.5)]);
+        state.replace_open_orders("USDC", vec![order("USDC-1", "BTC_USDC-PERPETUAL")]);
+        state.replace_positions("BTC", vec![position("BTC-PERPETUAL", 1000.0)]);
+        state.replace_open_orders("BTC", vec![order("BTC-1", "BTC-PERPETUAL")]);
+
+        // A BTC snapshot leaves the linear USDC instrument alone...
+        state.replace_positions("BTC", Vec::new());
+        state.replace_open_orders("BTC", Vec::new());
+        assert_eq!(state.position("BTC_USDC-PERPETUAL"), 0.5);
+        assert_eq!(state.position("BTC-PERPETUAL"), 0.0);
+        assert_eq!(state.open_orders_for("BTC_USDC-PERPETUAL").count(), 1);
+        assert!(!state.open_orders.contains_key("BTC-1"));
+
+        // ...and a USDC one clears it.
+        state.apply_changes(
+            "usdc",
+            AccountChanges {
+                positions: vec![position("ETH_USDC-PERPETUAL", 2.0)],
+                ..AccountChanges::default()
+            },
+        );
+        state.replace_positions("USDC", Vec::new());
+        state.replace_open_orders("USDC", Vec::new());
+        assert!(state.positions.is_empty());
+        assert!(state.open_orders.is_empty());
+    }
+
+    #[tokio::test]
+    async fn fetches_account_snapshots() {
+        let server = MockServer::start(deribit_fixture).await;
+        let deribit = connect_deribit(&server).await;
+
+        deribit.refresh_account("BTC").await.unwrap();
+
+        let state = deribit.account_state().await;
+        assert_eq!(state.balance("btc"), Some(2.5));
+        assert_eq!(state.position("BTC-PERPETUAL"), 1000.0);
+        assert_eq!(state.open_orders_for("BTC_USDT").count(), 1);
+        assert!(state.updated.is_some());
+
+        let request = server.wait_for("private/get_positions").await;
+        assert_eq!(request["params"]["currency"], "BTC");
+    }
+
+    #[tokio::test]
+    async fn applies_subscription_updates() {
+        let server = MockServer::start(deribit_fixture).await;
+        let deribit = connect_deribit(&server).await;
+
+        deribit.refresh_account("BTC").await.unwrap();
+        let channels = deribit.subscribe_account("BTC").await.unwrap();
+        assert_eq!(
+            channels,
+            vec!["user.portfolio.btc", "user.changes.any.BTC.raw"]
+        );
+
+        server.push(deribit_subscription(
+            "user.portfolio.btc",
+            json!({
+                "currency": "BTC", "balance": 3.0, "equity": 3.1, "available_funds": 2.5,
+                "margin_balance": 3.1, "initial_margin": 0.4, "maintenance_margin": 0.2,
+            }),
+        ));
+        server.push(deribit_subscription(
+            "user.changes.any.BTC.raw",
+            json!({
+                "instrument_name": "BTC_USDT",
+                "orders": [{
+                    "order_id": "ETH-1", "instrument_name": "BTC_USDT", "direction": "sell",
+                    "price": 45000.0, "amount": 0.1, "filled_amount": 0.1,
+                    "order_state": "filled", "order_type": "limit",
+                    "creation_timestamp": 1, "last_update_timestamp": 3,
+                }],
+                "positions": [{
+                    "instrument_name": "BTC-PERPETUAL", "kind": "future", "direction": "zero",
+                    "size": 0.0, "average_price": 0.0,
+                }],
+                "trades": [],
+            }),
+        ));
+
+        let state = wait_for_state(&deribit, |state| {
+            state.balance("BTC") == Some(3.0) && state.open_orders.is_empty()
+        })
+        .await;
+        assert_eq!(state.position("BTC-PERPETUAL"), 0.0);
+        assert_eq!(
+            state.summaries["BTC"].available_funds, 2.5,
+            "portfolio updates replace the summary"
+        );
+        assert!(
+            !state
+                .open_orders
+                .values()
+                .any(|o| o.order_state == OrderState::Filled)
+        );
+    }
+}
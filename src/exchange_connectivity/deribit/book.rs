@@ -1,13 +1,16 @@
 //! Order book related bits
-use crate::book_management::{Ask, Bid, Order};
-use crate::exchange_connectivity::{ConnectedExchangeForBook, ExchangeType, Instrument};
+use crate::book_management::{Ask, Bid};
+use crate::exchange_connectivity::{
+    BidsAsksResult, ConnectedExchangeForBook, ExchangeType, Instrument, ReceivedFrame, fast_parse,
+    levels,
+};
 
-use super::Deribit;
-use serde_json::{Value, json};
-
-use std::error::Error;
+use super::messages::{OrderBookResult, RpcResponse};
+use super::{Deribit, RESPONSE_TIMEOUT};
+use serde_json::json;
 
 use futures_util::SinkExt;
+use std::sync::atomic::Ordering;
 use std::time::Duration;
 
 #[derive(Clone, Copy)]
@@ -59,81 +62,81 @@ impl Deribit {
         Ok(())
     }
 
-    fn convert_vec_values_to_orders<T: Order>(
-        exchange: ExchangeType,
-        vec: &[Value],
-        instrument: Instrument,
-    ) -> Result<Vec<T>, Box<dyn Error + Send + Sync>> {
-        Ok(vec
-            .iter()
-            .filter_map(|elem| {
-                elem.as_array()
-                    .filter(|order_pair| order_pair.len() == 2)
-                    .and_then(|order_pair| {
-                        let price = order_pair[0].as_f64()?;
-                        let qty = order_pair[1].as_f64()?;
-                        Some(T::new(instrument, exchange, qty, price))
-                    })
-            })
-            .collect::<Vec<T>>())
+    /// Decode a `public/get_order_book` response, trying the zero-copy
+    /// fast path before the typed decoder.
+    fn decode_book(&self, frame: &ReceivedFrame, instrument: Instrument) -> BidsAsksResult {
+        let text = &frame.text;
+        if let Some(timestamp) = fast_parse::find_u64(text, "timestamp")
+            && let Some((bids, asks)) =
+                fast_parse::parse_book(text, ExchangeType::Deribit, instrument)
+        {
+            let timestamps = self
+                .clock
+                .stamp(Some(Duration::from_millis(timestamp)), frame.received);
+            return Ok((bids, asks, timestamps));
+        }
+
+        let book = serde_json::from_str::<RpcResponse<OrderBookResult>>(text)
+            .map_err(|err| format!("Failed to parse message: {}", err))?
+            .into_result()?;
+
+        let bids = levels::into_orders::<Bid>(&book.bids, ExchangeType::Deribit, instrument);
+        let asks = levels::into_orders::<Ask>(&book.asks, ExchangeType::Deribit, instrument);
+        self.record_malformed_levels(instrument, bids.malformed + asks.malformed);
+
+        let timestamps = self
+            .clock
+            .stamp(Some(Duration::from_millis(book.timestamp)), frame.received);
+        Ok((bids.orders, asks.orders, timestamps))
+    }
+
+    fn record_malformed_levels(&self, instrument: Instrument, count: usize) {
+        if count == 0 {
+            return;
+        }
+
+        let total = self
+            .malformed_levels
+            .fetch_add(count as u64, Ordering::Relaxed)
+            + count as u64;
+        log::warn!(
+            "Discarded {} malformed {} book levels from Deribit ({} in total).",
+            count,
+            instrument,
+            total
+        );
+    }
+
+    /// Number of book levels discarded as malformed since connecting.
+    pub fn malformed_levels(&self) -> u64 {
+        self.malformed_levels.load(Ordering::Relaxed)
     }
 }
 
 impl ConnectedExchangeForBook for Deribit {
-    async fn pull_bids_asks(
-        &self,
-        depth: u32,
-        instrument: Instrument,
-    ) -> Result<(Vec<Bid>, Vec<Ask>, Duration), Box<dyn Error + Send + Sync>> {
+    async fn pull_bids_asks(&self, depth: u32, instrument: Instrument) -> BidsAsksResult {
         let depth = ValidOrderDepth::from_number(depth);
-        let req_id = self.get_new_id();
-
-        self.request_get_order_book(req_id, &Deribit::to_instrument_name(instrument), depth)
-            .await?;
-
-        for _ in 0..5 {
-            let mut multimap = self.non_main_stream.lock().await;
-            let multimap_entry = multimap.get(&req_id);
-            if let Some(entry) = multimap_entry {
-                log::info!("Found depth msg with id {}: {}", &req_id, entry);
-                let msg: serde_json::Value = serde_json::from_str(&entry)
-                    .map_err(|err| format!("Failed to parse message: {}", err))?;
-
-                if let Some(timestamp) = msg["result"]["timestamp"].as_u64() {
-                    if let Some(bids) = msg["result"]["bids"].as_array()
-                        && let Some(asks) = msg["result"]["asks"].as_array()
-                    {
-                        let bid_vec = Deribit::convert_vec_values_to_orders(
-                            ExchangeType::Deribit,
-                            &bids,
-                            instrument,
-                        )?;
-
-                        let ask_vec = Deribit::convert_vec_values_to_orders(
-                            ExchangeType::Deribit,
-                            &asks,
-                            instrument,
-                        )?;
-                        multimap.remove(&req_id);
-                        return Ok((bid_vec, ask_vec, Duration::from_millis(timestamp)));
-                    }
-                }
-            }
-            tokio::time::sleep(Duration::from_millis(100)).await;
-        }
+        let request = self.requests.allocate()?;
 
-        Err(
-            "Did not recieve response for order book from Deribit after multiple attempts. "
-                .to_string()
-                .into(),
+        self.request_get_order_book(
+            request.id(),
+            &Deribit::to_instrument_name(instrument),
+            depth,
         )
+        .await?;
+
+        let frame = self.await_response(&request, RESPONSE_TIMEOUT).await?;
+        log::info!("Found depth msg with id {}: {}", request.id(), frame.text);
+        self.decode_book(&frame, instrument)
     }
 
     fn to_instrument_name(instrument: Instrument) -> String {
         match instrument {
             Instrument::BtcUsdt => "BTC_USDT".to_string(),
+            Instrument::BtcUsdc => "BTC_USDC".to_string(),
             Instrument::EthUsdc => "ETH_USDC".to_string(),
             Instrument::EthBtc => "ETH_BTC".to_string(),
+            Instrument::UsdcUsdt => "USDC_USDT".to_string(),
         }
     }
 }
@@ -0,0 +1,148 @@
+//! Typed models of the Deribit JSON-RPC messages we consume.
+use std::borrow::Cow;
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::exchange_connectivity::levels::RawLevel;
+
+/// Just enough of any inbound frame to decide how to dispatch it.
+#[derive(Debug, Deserialize)]
+pub struct Envelope<'a> {
+    pub id: Option<u64>,
+    #[serde(borrow)]
+    pub method: Option<Cow<'a, str>>,
+}
+
+/// A JSON-RPC response to one of our requests.
+#[derive(Debug, Deserialize)]
+pub struct RpcResponse<T> {
+    pub id: u64,
+    pub result: Option<T>,
+    pub error: Option<RpcError>,
+}
+
+impl<T> RpcResponse<T> {
+    /// Unwrap the result, turning a JSON-RPC error into an `Err`.
+    pub fn into_result(self) -> Result<T, String> {
+        match (self.result, self.error) {
+            (Some(result), _) => Ok(result),
+            (None, Some(err)) => Err(format!(
+                "Deribit returned error for id {}: {}",
+                self.id, err
+            )),
+            (None, None) => Err(format!("Deribit response for id {} had no result", self.id)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// Result of `public/auth`.
+#[derive(Debug, Deserialize)]
+pub struct AuthResult {
+    pub refresh_token: String,
+    pub expires_in: Option<u64>,
+}
+
+/// Result of `public/get_order_book`.
+#[derive(Debug, Deserialize)]
+pub struct OrderBookResult {
+    pub timestamp: u64,
+    pub bids: Vec<RawLevel>,
+    pub asks: Vec<RawLevel>,
+}
+
+/// Notification pushed by the server without a request id.
+#[derive(Debug, Deserialize)]
+pub struct Notification<P> {
+    pub params: P,
+}
+
+/// Params of a `subscription` notification.
+#[derive(Debug, Deserialize)]
+pub struct SubscriptionParams<T> {
+    pub channel: String,
+    pub data: T,
+}
+
+/// Params of a `heartbeat` notification.
+#[derive(Debug, Deserialize)]
+pub struct HeartbeatParams {
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        AuthResult, Envelope, HeartbeatParams, Notification, OrderBookResult, RpcResponse,
+        SubscriptionParams,
+    };
+    use crate::exchange_connectivity::levels::RawLevel;
+    use serde::de::IgnoredAny;
+
+    #[test]
+    fn parses_order_book_response() {
+        let text = r#"{"jsonrpc":"2.0","id":10001,"result":{"timestamp":1700000000000,
+            "bids":[[42000.5,1.25],["bad",1]],"asks":[[42001.0,0.5]],"instrument_name":"BTC_USDT"},
+            "usIn":1,"usOut":2}"#;
+
+        let envelope: Envelope = serde_json::from_str(text).unwrap();
+        assert_eq!(envelope.id, Some(10001));
+        assert!(envelope.method.is_none());
+
+        let book = serde_json::from_str::<RpcResponse<OrderBookResult>>(text)
+            .unwrap()
+            .into_result()
+            .unwrap();
+        assert_eq!(book.timestamp, 1700000000000);
+        assert_eq!(book.bids[1], RawLevel::Malformed);
+        assert_eq!(book.asks.len(), 1);
+    }
+
+    #[test]
+    fn surfaces_rpc_errors() {
+        let text =
+            r#"{"jsonrpc":"2.0","id":7,"error":{"code":10009,"message":"not_enough_funds"}}"#;
+
+        let err = serde_json::from_str::<RpcResponse<AuthResult>>(text)
+            .unwrap()
+            .into_result()
+            .unwrap_err();
+        assert!(err.contains("not_enough_funds"));
+    }
+
+    #[test]
+    fn parses_heartbeat() {
+        let text = r#"{"jsonrpc":"2.0","method":"heartbeat","params":{"type":"test_request"}}"#;
+
+        let heartbeat: Notification<HeartbeatParams> = serde_json::from_str(text).unwrap();
+        assert_eq!(heartbeat.params.kind, "test_request");
+    }
+
+    #[test]
+    fn parses_subscription_channel() {
+        let text = r#"{"jsonrpc":"2.0","method":"subscription",
+            "params":{"channel":"user.portfolio.btc","data":{"balance":1.0}}}"#;
+
+        let envelope: Envelope = serde_json::from_str(text).unwrap();
+        assert_eq!(envelope.method.as_deref(), Some("subscription"));
+
+        let notification: Notification<SubscriptionParams<IgnoredAny>> =
+            serde_json::from_str(text).unwrap();
+        assert_eq!(notification.params.channel, "user.portfolio.btc");
+    }
+}
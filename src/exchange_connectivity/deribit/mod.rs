@@ -1,46 +1,112 @@
+pub mod account;
 pub mod book;
+mod messages;
+pub mod orders;
 
+use std::collections::BTreeSet;
+use std::fmt;
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::exchange_connectivity::ReceivedFrame;
+use crate::exchange_connectivity::clock::{self, CLOCK_SYNC_INTERVAL, ClockSample, ClockSync};
+use crate::exchange_connectivity::message_id::{ControlId, MessageIdAllocator, RequestId};
+use crate::exchange_connectivity::orders::{OrderEvent, OrderTracker};
+use crate::exchange_connectivity::proxy::{self, ProxyConfig};
+use crate::exchange_connectivity::shutdown::{CancellationToken, TaskGroup};
+use crate::exchange_connectivity::watchdog::{
+    ConnectionMonitor, WatchdogConfig, Watched, run_watchdog,
+};
+use account::AccountState;
 use futures_util::{SinkExt, StreamExt, stream::SplitSink, stream::SplitStream};
 use log::info;
-use multimap::MultiMap;
-use serde_json::json;
+use messages::{
+    AuthResult, Envelope, HeartbeatParams, Notification, RpcResponse, SubscriptionParams,
+};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde_json::{Value, json};
 use std::time::Duration;
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, broadcast};
 use tokio_tungstenite::{
-    MaybeTlsStream, WebSocketStream, connect_async, tungstenite::protocol::Message,
+    MaybeTlsStream, WebSocketStream, tungstenite::Utf8Bytes, tungstenite::protocol::Message,
 };
 
+/// No response to a request arrived in time. The request may still have
+/// been acted on.
+#[derive(Debug)]
+pub struct ResponseTimeout {
+    pub id: u64,
+}
+
+impl fmt::Display for ResponseTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Did not receive response for id {} from Deribit in time.",
+            self.id
+        )
+    }
+}
+
+impl std::error::Error for ResponseTimeout {}
+
 type Sink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
 type Stream = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
 
 #[derive(Debug)]
 pub struct Deribit {
+    url: String,
+    proxy: Option<ProxyConfig>,
     client_id: String,
     client_secret: String,
     sink: Arc<Mutex<Sink>>,
     stream: Arc<Mutex<Stream>>,
-    non_main_stream: Arc<Mutex<MultiMap<u64, String>>>,
+    requests: MessageIdAllocator<ReceivedFrame>,
     refresh_token: Arc<Mutex<Option<String>>>,
     refresh_token_expiry_time: Arc<Mutex<Option<Duration>>>,
-    keep_alive: Arc<AtomicBool>,
-    curr_msg_id: AtomicU64,
+    /// Tasks serving this connection, stopped together on shutdown.
+    pub(crate) tasks: TaskGroup,
+    malformed_levels: AtomicU64,
+    account: Mutex<AccountState>,
+    orders: Mutex<OrderTracker>,
+    order_events: broadcast::Sender<OrderEvent>,
+    /// Private channels to restore after a reconnect.
+    channels: Mutex<BTreeSet<String>>,
+    cancel_on_disconnect: AtomicBool,
+    pub(super) clock: ClockSync,
+    pub(super) monitor: ConnectionMonitor,
 }
 
 const DERIBIT_WS_URL: &str = "wss://www.deribit.com/ws/api/v2";
 const DERIBIT_WS_TEST_URL: &str = "wss://test.deribit.com/ws/api/v2";
 
+/// Order events buffered for each subscriber before the slowest lags.
+const ORDER_EVENT_CAPACITY: usize = 1024;
+
+/// Longest a request waits for its response.
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Longest an order request waits for its response. Giving up leaves the
+/// order's state unknown, so this is well above [`RESPONSE_TIMEOUT`].
+const ORDER_RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interval at which Deribit is asked to send heartbeats.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Longest a read waits on the socket before letting go of it, so a
+/// reconnect can swap the stream out from under a stalled read.
+const READ_TIMEOUT: Duration = Duration::from_millis(250);
+
 impl Deribit {
     pub async fn connect(
         client_id: String,
         client_secret: String,
-    ) -> Option<(Self, Arc<AtomicBool>)> {
+        proxy: Option<ProxyConfig>,
+    ) -> Option<(Self, CancellationToken)> {
         let connection_url = if cfg!(test) || cfg!(feature = "test-apis") {
             info!("Using Deribit test URL");
             DERIBIT_WS_TEST_URL
@@ -48,29 +114,49 @@ impl Deribit {
             DERIBIT_WS_URL
         };
 
-        let connection_response = connect_async(connection_url).await;
+        Self::connect_to(connection_url, client_id, client_secret, proxy).await
+    }
+
+    /// Connect to the Deribit API served at `connection_url`, through
+    /// `proxy` if one is given.
+    pub(crate) async fn connect_to(
+        connection_url: &str,
+        client_id: String,
+        client_secret: String,
+        proxy: Option<ProxyConfig>,
+    ) -> Option<(Self, CancellationToken)> {
+        let connection_response = proxy::connect_ws(connection_url, proxy.as_ref()).await;
         match connection_response {
             Err(err) => {
                 log::error!("Error connecting to client. {}", err);
                 None
             }
-            Ok(ok) => {
-                let (sink, stream) = ok.0.split();
-                let keep_alive = Arc::new(AtomicBool::new(true));
+            Ok(ws) => {
+                let (sink, stream) = ws.split();
+                let shutdown = CancellationToken::new();
 
                 Some((
                     Deribit {
-                        client_id: client_id,
-                        client_secret: client_secret,
+                        url: connection_url.to_string(),
+                        proxy,
+                        client_id,
+                        client_secret,
                         sink: Arc::new(Mutex::new(sink)),
                         stream: Arc::new(Mutex::new(stream)),
-                        non_main_stream: Arc::new(Mutex::new(MultiMap::new())),
+                        requests: MessageIdAllocator::new(),
                         refresh_token: Arc::new(Mutex::new(None)),
                         refresh_token_expiry_time: Arc::new(Mutex::new(None)),
-                        keep_alive: keep_alive.clone(),
-                        curr_msg_id: AtomicU64::new(10000),
+                        tasks: TaskGroup::new(shutdown.clone()),
+                        malformed_levels: AtomicU64::new(0),
+                        account: Mutex::new(AccountState::default()),
+                        orders: Mutex::new(OrderTracker::new()),
+                        order_events: broadcast::channel(ORDER_EVENT_CAPACITY).0,
+                        channels: Mutex::new(BTreeSet::new()),
+                        cancel_on_disconnect: AtomicBool::new(false),
+                        clock: ClockSync::new(),
+                        monitor: ConnectionMonitor::default(),
                     },
-                    keep_alive.clone(),
+                    shutdown,
                 ))
             }
         }
@@ -84,26 +170,15 @@ impl Deribit {
 
         self.spawn_refresh_auth_task();
 
-        // Subscribe here
-
-        loop {
-            let keep_running = {
-                let guard = &self.keep_alive;
-                guard.load(Ordering::Relaxed)
-            };
-
-            if !keep_running {
-                break;
+        // Reads time out every READ_TIMEOUT, so cancellation is noticed
+        // without cutting a message off half way through handling.
+        while !self.tasks.token().is_cancelled() {
+            if let Err(err) = self.process_next_message().await {
+                log::error!("Error processing WebSocket message: {}", err);
             }
-
-            while self.keep_alive.load(Ordering::Relaxed) {
-                if let Err(err) = self.process_next_message().await {
-                    log::error!("Error processing WebSocket message: {}", err);
-                }
-            }
-
-            log::info!("WebSocket manager shutting down gracefully.");
         }
+
+        log::info!("WebSocket manager shutting down gracefully.");
     }
 
     async fn initialize_ws(&self) -> Result<(), String> {
@@ -122,7 +197,7 @@ impl Deribit {
     async fn ws_auth(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let msg = json!({
             "jsonrpc": "2.0",
-            "id": 9929,
+            "id": ControlId::Auth.id(),
             "method": "public/auth",
             "params": {
                 "grant_type": "client_credentials",
@@ -141,10 +216,11 @@ impl Deribit {
     }
 
     fn spawn_refresh_auth_task(&self) {
-        tokio::spawn(Self::ws_refresh_auth(
+        self.tasks.spawn(Self::ws_refresh_auth(
             Arc::clone(&self.sink),
             Arc::clone(&self.refresh_token),
             Arc::clone(&self.refresh_token_expiry_time),
+            self.tasks.token().clone(),
         ));
         log::info!("Refresh authentication task spawned.");
     }
@@ -152,71 +228,106 @@ impl Deribit {
     async fn process_next_message(&self) -> Result<(), String> {
         let next_message = {
             let mut stream = self.stream.lock().await;
-            stream.next().await
+            match tokio::time::timeout(READ_TIMEOUT, stream.next()).await {
+                Ok(message) => message,
+                Err(_) => return Ok(()),
+            }
         };
 
+        let received = clock::now();
         match next_message {
-            Some(Ok(Message::Text(text))) => self.handle_text_message(&text).await,
+            Some(Ok(Message::Text(text))) => {
+                self.monitor.record_inbound(received);
+                self.handle_text_message(text, received).await
+            }
             Some(Ok(_)) => {
+                self.monitor.record_inbound(received);
                 log::warn!("Unexpected non-text message received.");
                 Ok(())
             }
-            Some(Err(err)) => Err(format!("Error reading WebSocket stream: {}", err)),
+            Some(Err(err)) => {
+                self.monitor.mark_stale();
+                Err(format!("Error reading WebSocket stream: {}", err))
+            }
             None => {
                 log::warn!("WebSocket stream closed.");
+                self.monitor.mark_stale();
+                tokio::time::sleep(READ_TIMEOUT).await;
                 Ok(())
             }
         }
     }
 
-    async fn handle_text_message(&self, text: &str) -> Result<(), String> {
-        let msg: serde_json::Value = serde_json::from_str(text)
+    async fn handle_text_message(&self, text: Utf8Bytes, received: Duration) -> Result<(), String> {
+        let envelope: Envelope = serde_json::from_str(&text)
             .map_err(|err| format!("Failed to parse message: {}", err))?;
 
-        if let Some(9929) = msg["id"].as_u64() {
-            log::info!("Processed message: {}", text);
-            self.update_auth_tokens(&msg).await?;
-        } else if let Some("heartbeat") = msg["method"].as_str() {
-            log::info!("Processed heartbeat: {}", text);
-            self.heartbeat_response()
-                .await
-                .map_err(|e| format!("Failed to send heartbeat response: {}", e))?;
-        } else if let Some(8212) = msg["id"].as_u64() {
-            log::info!("Recieved Deribit heartbeat response {}", msg);
-        } else if let Some(id) = msg["id"].as_u64() {
-            self.non_main_stream
-                .lock()
-                .await
-                .insert(id, text.to_string());
-            log::info!(
-                "Unprocessed message with valid ID from Deribit stored with id: {}\n\n msg: {}",
-                id,
-                msg,
-            );
-        } else {
-            log::info!(
-                "Unprocessed message with no valid ID component from Deribit: {}\n\n msg: {}",
-                text,
-                msg,
-            );
+        let control = envelope.id.and_then(ControlId::from_id);
+
+        match (control, envelope.id, envelope.method.as_deref()) {
+            (Some(ControlId::Auth), _, _) => {
+                log::info!("Processed message: {}", text);
+                let auth = serde_json::from_str::<RpcResponse<AuthResult>>(&text)
+                    .map_err(|err| format!("Failed to parse auth response: {}", err))?
+                    .into_result()?;
+                self.update_auth_tokens(auth).await?;
+            }
+            (_, _, Some("heartbeat")) => {
+                let heartbeat = serde_json::from_str::<Notification<HeartbeatParams>>(&text)
+                    .map_err(|err| format!("Failed to parse heartbeat: {}", err))?;
+                log::info!("Processed heartbeat of type {}", heartbeat.params.kind);
+                self.monitor.record_heartbeat(received);
+                self.heartbeat_response()
+                    .await
+                    .map_err(|e| format!("Failed to send heartbeat response: {}", e))?;
+            }
+            (_, _, Some("subscription")) => {
+                let notification =
+                    serde_json::from_str::<Notification<SubscriptionParams<IgnoredAny>>>(&text)
+                        .map_err(|err| format!("Failed to parse subscription: {}", err))?;
+                let channel = notification.params.channel;
+                if channel.starts_with("user.orders.") || channel.starts_with("user.trades.") {
+                    self.handle_order_notification(&channel, &text).await?;
+                } else if channel.starts_with("user.") {
+                    self.handle_account_notification(&channel, &text).await?;
+                } else {
+                    log::info!("Unhandled Deribit subscription on {}", channel);
+                }
+            }
+            (Some(control), _, _) => {
+                log::info!("Recieved Deribit {:?} response {}", control, text);
+            }
+            (None, Some(id), _) => {
+                let frame = ReceivedFrame {
+                    text: text.clone(),
+                    received,
+                };
+                if self.requests.deliver(id, frame).is_err() {
+                    log::warn!(
+                        "Dropping Deribit response to unknown or expired request id {}",
+                        id
+                    );
+                }
+            }
+            (None, None, _) => {
+                log::info!(
+                    "Unprocessed message with no valid ID component from Deribit: {}",
+                    text,
+                );
+            }
         }
         Ok(())
     }
 
-    async fn update_auth_tokens(&self, msg: &serde_json::Value) -> Result<(), String> {
-        let refresh_token = msg["result"]["refresh_token"]
-            .as_str()
-            .ok_or("Missing refresh token")?
-            .to_string();
-
-        let expires_in = msg["result"]["expires_in"]
-            .as_u64()
+    async fn update_auth_tokens(&self, auth: AuthResult) -> Result<(), String> {
+        let expires_in = auth
+            .expires_in
             .map(Duration::from_secs)
             .unwrap_or_else(|| Duration::from_secs(540))
-            - Duration::from_secs(240);
+            .saturating_sub(Duration::from_secs(240));
 
         let mut refresh_token_guard = self.refresh_token.lock().await;
-        *refresh_token_guard = Some(refresh_token);
+        *refresh_token_guard = Some(auth.refresh_token);
 
         if let Ok(curr_time) = SystemTime::now().duration_since(UNIX_EPOCH) {
             let mut expiry_time_guard = self.refresh_token_expiry_time.lock().await;
@@ -232,10 +343,10 @@ impl Deribit {
     async fn establish_heartbeat(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let msg = json!({
             "jsonrpc": "2.0",
-            "id": 9098,
+            "id": ControlId::SetHeartbeat.id(),
             "method": "public/set_heartbeat",
             "params": {
-                "interval": 30,
+                "interval": HEARTBEAT_INTERVAL.as_secs(),
             },
         });
 
@@ -251,7 +362,7 @@ impl Deribit {
     async fn heartbeat_response(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let msg = json!({
             "jsonrpc": "2.0",
-            "id": 8212,
+            "id": ControlId::HeartbeatTest.id(),
             "method": "public/test",
             "params": {},
         });
@@ -265,8 +376,9 @@ impl Deribit {
         sink: Arc<Mutex<Sink>>,
         refresh_token: Arc<Mutex<Option<String>>>,
         refresh_token_expiry_time: Arc<Mutex<Option<Duration>>>,
+        shutdown: CancellationToken,
     ) {
-        loop {
+        while !shutdown.is_cancelled() {
             log::info!("Checking if Deribit auth is close to expiry.");
 
             if let Some(expiry_time) = *refresh_token_expiry_time.lock().await
@@ -277,7 +389,7 @@ impl Deribit {
                     if curr_time > expiry_time {
                         let msg = json!({
                             "jsonrpc": "2.0",
-                            "id": 9929,
+                            "id": ControlId::Auth.id(),
                             "method": "public/auth",
                             "params": {
                                 "grant_type": "refresh_token",
@@ -309,37 +421,213 @@ impl Deribit {
                 }
             }
 
-            tokio::time::sleep(Duration::from_secs(150)).await;
+            shutdown.sleep(Duration::from_secs(150)).await;
         }
+
+        log::info!("Deribit auth refresh task stopped.");
     }
 
-    fn get_new_id(&self) -> u64 {
-        if self.curr_msg_id.load(Ordering::Relaxed) > 1000000 {
-            self.curr_msg_id.store(10000, Ordering::Relaxed);
-        } else {
-            self.curr_msg_id.fetch_add(1, Ordering::Relaxed);
+    /// Wait up to `timeout` for the response to `request` to be read off
+    /// the socket.
+    async fn await_response(
+        &self,
+        request: &RequestId<'_, ReceivedFrame>,
+        timeout: Duration,
+    ) -> Result<ReceivedFrame, Box<dyn std::error::Error + Send + Sync>> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            if let Some(frame) = request.try_take() {
+                return Ok(frame);
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(Box::new(ResponseTimeout { id: request.id() }));
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
         }
+    }
 
-        self.curr_msg_id.load(Ordering::Relaxed)
+    /// Send a JSON-RPC request and decode the result of its response.
+    /// An error response is returned as an [`RpcError`](messages::RpcError).
+    async fn request<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<T, Box<dyn std::error::Error + Send + Sync>> {
+        self.request_with_timeout(method, params, RESPONSE_TIMEOUT)
+            .await
+    }
+
+    async fn request_with_timeout<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+        timeout: Duration,
+    ) -> Result<T, Box<dyn std::error::Error + Send + Sync>> {
+        let request = self.requests.allocate()?;
+        let msg = json!({
+            "jsonrpc": "2.0",
+            "id": request.id(),
+            "method": method,
+            "params": params,
+        });
+
+        self.sink.lock().await.send(msg.to_string().into()).await?;
+        let frame = self.await_response(&request, timeout).await?;
+
+        let response = serde_json::from_str::<RpcResponse<T>>(&frame.text)
+            .map_err(|err| format!("Failed to parse {} response: {}", method, err))?;
+        if let Some(err) = response.error {
+            return Err(Box::new(err));
+        }
+        Ok(response.into_result()?)
+    }
+
+    /// Subscribe to private `channels`, remembering them so they are
+    /// restored after a reconnect.
+    async fn subscribe(
+        &self,
+        channels: Vec<String>,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let subscribed: Vec<String> = self
+            .request("private/subscribe", json!({ "channels": channels }))
+            .await?;
+        self.channels
+            .lock()
+            .await
+            .extend(subscribed.iter().cloned());
+        Ok(subscribed)
+    }
+
+    /// Watch for stalls with thresholds based on the heartbeat interval.
+    pub async fn watchdog_task(&self) {
+        run_watchdog(
+            self,
+            self.tasks.token(),
+            WatchdogConfig::for_heartbeat(HEARTBEAT_INTERVAL),
+        )
+        .await;
+    }
+
+    /// Take one `public/get_time` round trip and record it.
+    async fn sync_clock(&self) -> Result<ClockSample, Box<dyn std::error::Error + Send + Sync>> {
+        let request = self.requests.allocate()?;
+        let msg = json!({
+            "jsonrpc": "2.0",
+            "id": request.id(),
+            "method": "public/get_time",
+            "params": {},
+        });
+
+        let sent = clock::now();
+        self.sink.lock().await.send(msg.to_string().into()).await?;
+        let frame = self.await_response(&request, RESPONSE_TIMEOUT).await?;
+
+        let exchange = serde_json::from_str::<RpcResponse<u64>>(&frame.text)?.into_result()?;
+        let sample = ClockSample {
+            sent,
+            exchange: Duration::from_millis(exchange),
+            received: frame.received,
+        };
+        self.clock.record_sample(sample);
+
+        Ok(sample)
+    }
+
+    /// Periodically re-estimate the clock offset to Deribit.
+    pub async fn clock_sync_task(&self) {
+        while !self.tasks.token().is_cancelled() {
+            match self.sync_clock().await {
+                Ok(sample) => log::info!(
+                    "Deribit clock offset {}us with round trip {:?}.",
+                    sample.offset_micros(),
+                    sample.rtt()
+                ),
+                Err(err) => log::warn!("Failed to sync clock with Deribit: {}", err),
+            }
+
+            self.tasks.token().sleep(CLOCK_SYNC_INTERVAL).await;
+        }
+    }
+
+    /// Stop every task serving this connection, send a Close frame and
+    /// wait for the tasks to finish.
+    pub async fn shutdown(&self) {
+        self.tasks.token().cancel();
+
+        if let Err(err) = self.sink.lock().await.send(Message::Close(None)).await {
+            log::warn!("Failed to send Close frame to Deribit: {}", err);
+        }
+
+        self.tasks.join().await;
+        log::info!("Deribit connection shut down.");
+    }
+}
+
+impl Watched for Deribit {
+    const NAME: &'static str = "Deribit";
+
+    fn monitor(&self) -> &ConnectionMonitor {
+        &self.monitor
+    }
+
+    async fn probe(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.heartbeat_response().await
+    }
+
+    async fn reconnect(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let (sink, stream) = proxy::connect_ws(&self.url, self.proxy.as_ref())
+            .await?
+            .split();
+        *self.sink.lock().await = sink;
+        *self.stream.lock().await = stream;
+
+        self.initialize_ws().await?;
+        if self.cancel_on_disconnect.load(Ordering::Relaxed) {
+            self.enable_cancel_on_disconnect().await?;
+        }
+
+        let channels = self
+            .channels
+            .lock()
+            .await
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        if !channels.is_empty() {
+            self.subscribe(channels).await?;
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::sync::{Arc, atomic::AtomicBool};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use serde_json::{Value, json};
 
     use crate::{
         book_management::traded_instruments::Instrument,
-        exchange_connectivity::{ConnectedExchangeForBook, ExchangeKeys},
+        exchange_connectivity::{
+            ConnectedExchangeForBook, ExchangeKeys,
+            mock_server::{MockServer, connect_deribit, deribit_fixture},
+            shutdown::CancellationToken,
+            watchdog::{ConnectionHealth, WatchdogConfig, run_watchdog},
+        },
     };
 
     use super::Deribit;
 
-    async fn create_exchange() -> (Deribit, Arc<AtomicBool>) {
+    async fn create_exchange() -> (Deribit, CancellationToken) {
         let keys = ExchangeKeys::get_environment();
-        Deribit::connect(keys.deribit_client_id, keys.deribit_api_key)
-            .await
-            .expect("Issue found connecting to Deribit")
+        Deribit::connect(
+            keys.deribit_client_id,
+            keys.deribit_api_key,
+            keys.deribit_proxy,
+        )
+        .await
+        .expect("Issue found connecting to Deribit")
     }
 
     #[tokio::test]
@@ -349,7 +637,7 @@ mod test {
         let auth = deribit.0.ws_auth().await;
 
         if let Err(err) = auth {
-            println!("{}", err.to_string());
+            println!("{}", err);
             panic!("unexpected error!");
         }
     }
@@ -362,7 +650,7 @@ mod test {
         let result = deribit.0.establish_heartbeat().await;
 
         if let Err(err) = result {
-            println!("{}", err.to_string());
+            println!("{}", err);
             panic!("unexpected error!");
         }
     }
@@ -395,4 +683,67 @@ mod test {
         assert!(result_eth_usdc.0.len() <= 10);
         assert!(result_eth_usdc.1.len() <= 10);
     }
+
+    fn watchdog_config() -> WatchdogConfig {
+        WatchdogConfig {
+            check_interval: Duration::from_millis(20),
+            probe_after: Duration::from_millis(100),
+            heartbeat_timeout: None,
+            probe_timeout: Duration::from_millis(100),
+        }
+    }
+
+    fn spawn_watchdog(deribit: &Arc<Deribit>) -> CancellationToken {
+        let shutdown = CancellationToken::new();
+        let (deribit, running) = (Arc::clone(deribit), shutdown.clone());
+        tokio::spawn(async move { run_watchdog(&*deribit, &running, watchdog_config()).await });
+        shutdown
+    }
+
+    #[tokio::test]
+    async fn reconnects_when_probe_goes_unanswered() {
+        let server = MockServer::start(|request: &Value| match request["method"].as_str() {
+            Some("public/test") => Vec::new(),
+            _ => deribit_fixture(request),
+        })
+        .await;
+        let deribit = connect_deribit(&server).await;
+        deribit.subscribe_account("BTC").await.unwrap();
+
+        let watchdog = spawn_watchdog(&deribit);
+        for _ in 0..100 {
+            if deribit.monitor.reconnects() > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        watchdog.cancel();
+
+        assert!(deribit.monitor.reconnects() > 0);
+        assert!(!server.requests("public/test").is_empty());
+        assert!(server.requests("public/auth").len() >= 2);
+
+        // Private subscriptions are restored on the new connection.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let subscriptions = server.requests("private/subscribe");
+        assert!(subscriptions.len() >= 2);
+        assert_eq!(
+            subscriptions.last().unwrap()["params"]["channels"],
+            json!(["user.changes.any.BTC.raw", "user.portfolio.btc"])
+        );
+    }
+
+    #[tokio::test]
+    async fn answered_probes_keep_the_connection() {
+        let server = MockServer::start(deribit_fixture).await;
+        let deribit = connect_deribit(&server).await;
+
+        let watchdog = spawn_watchdog(&deribit);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        watchdog.cancel();
+
+        assert!(!server.requests("public/test").is_empty());
+        assert_eq!(deribit.monitor.reconnects(), 0);
+        assert_ne!(deribit.monitor.health(), ConnectionHealth::Stale);
+    }
 }
@@ -0,0 +1,549 @@
+//! Order entry on the authenticated Deribit session.
+//!
+//! Orders are sent with `private/buy`, `private/sell`, `private/edit`,
+//! `private/cancel` and `private/cancel_all`. Their lifecycle is followed
+//! on the `user.orders` and `user.trades` channels once
+//! [`Deribit::track_orders`] has subscribed to them.
+//!
+//! Every order is sent with a label so that one whose response never
+//! arrives can be found again: it is looked up with
+//! `private/get_order_state_by_label`, and failing that held as pending
+//! until an update with its label comes in on `user.orders`.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::sync::broadcast;
+
+use super::account::{Direction, OrderInfo, UserTrade};
+use super::messages::{Notification, RpcError, SubscriptionParams};
+use super::{Deribit, ORDER_RESPONSE_TIMEOUT};
+use crate::exchange_connectivity::clock;
+use crate::exchange_connectivity::orders::{
+    Fill, OrderAck, OrderEvent, OrderGateway, OrderRequest, OrderResult, OrderStateUnknown,
+    OrderType, OrderUpdate, Side, TimeInForce,
+};
+use crate::exchange_connectivity::{ConnectedExchangeForBook, ExchangeType};
+
+/// Distinguishes labels given to orders sent without one.
+static NEXT_LABEL: AtomicU64 = AtomicU64::new(0);
+
+/// A label unique to this process for an order sent without one.
+fn generated_label() -> String {
+    format!(
+        "ma-{}-{}",
+        clock::now().as_millis(),
+        NEXT_LABEL.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Result of `private/buy`, `private/sell` and `private/edit`.
+#[derive(Debug, Deserialize)]
+struct OrderResponse {
+    order: OrderInfo,
+    #[serde(default)]
+    trades: Vec<UserTrade>,
+}
+
+/// `raw` user channels push single items, aggregated ones push arrays.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+    fn into_vec(self) -> Vec<T> {
+        match self {
+            OneOrMany::One(item) => vec![item],
+            OneOrMany::Many(items) => items,
+        }
+    }
+}
+
+fn to_side(direction: Direction) -> Result<Side, String> {
+    match direction {
+        Direction::Buy => Ok(Side::Buy),
+        Direction::Sell => Ok(Side::Sell),
+        Direction::Zero => Err("Deribit order has no direction".to_string()),
+    }
+}
+
+fn to_update(order: OrderInfo) -> Result<OrderUpdate, String> {
+    Ok(OrderUpdate {
+        exchange: ExchangeType::Deribit,
+        side: to_side(order.direction)?,
+        order_id: order.order_id,
+        instrument_name: order.instrument_name,
+        state: order.order_state,
+        amount: order.amount,
+        filled_amount: order.filled_amount,
+        price: order.price,
+        average_price: order.average_price,
+        label: Some(order.label).filter(|label| !label.is_empty()),
+        updated: Duration::from_millis(order.last_update_timestamp),
+    })
+}
+
+fn to_fill(trade: UserTrade) -> Result<Fill, String> {
+    Ok(Fill {
+        exchange: ExchangeType::Deribit,
+        side: to_side(trade.direction)?,
+        trade_id: trade.trade_id,
+        order_id: trade.order_id,
+        instrument_name: trade.instrument_name,
+        price: trade.price,
+        amount: trade.amount,
+        fee: trade.fee,
+        fee_currency: trade.fee_currency,
+        timestamp: Duration::from_millis(trade.timestamp),
+    })
+}
+
+fn order_params(request: &OrderRequest) -> Value {
+    let mut params = json!({
+        "instrument_name": Deribit::to_instrument_name(request.instrument),
+        "amount": request.amount,
+        "time_in_force": match request.time_in_force {
+            TimeInForce::GoodTilCancelled => "good_til_cancelled",
+            TimeInForce::ImmediateOrCancel => "immediate_or_cancel",
+            TimeInForce::FillOrKill => "fill_or_kill",
+        },
+        "post_only": request.post_only,
+        "reduce_only": request.reduce_only,
+    });
+
+    match request.order_type {
+        OrderType::Limit { price } => {
+            params["type"] = json!("limit");
+            params["price"] = json!(price);
+        }
+        OrderType::Market => params["type"] = json!("market"),
+    }
+    if let Some(label) = &request.label {
+        params["label"] = json!(label);
+    }
+
+    params
+}
+
+impl Deribit {
+    /// Subscribe to updates of all our orders and fills, optionally
+    /// asking Deribit to cancel our orders if this connection drops.
+    pub async fn track_orders(
+        &self,
+        cancel_on_disconnect: bool,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+        if cancel_on_disconnect {
+            self.enable_cancel_on_disconnect().await?;
+        }
+
+        let subscribed = self
+            .subscribe(vec![
+                "user.orders.any.any.raw".to_string(),
+                "user.trades.any.any.raw".to_string(),
+            ])
+            .await?;
+        log::info!("Subscribed to Deribit order channels {:?}", subscribed);
+        Ok(subscribed)
+    }
+
+    /// Have Deribit cancel all our orders when this connection closes.
+    pub async fn enable_cancel_on_disconnect(
+        &self,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let _: Value = self
+            .request(
+                "private/enable_cancel_on_disconnect",
+                json!({ "scope": "connection" }),
+            )
+            .await?;
+        self.cancel_on_disconnect.store(true, Ordering::Relaxed);
+        log::info!("Enabled cancel on disconnect for Deribit.");
+        Ok(())
+    }
+
+    /// Orders sent whose state is not yet known because no response
+    /// arrived.
+    pub async fn pending_orders(&self) -> Vec<OrderRequest> {
+        self.orders.lock().await.pending().cloned().collect()
+    }
+
+    /// Latest known state of order `order_id`.
+    pub async fn tracked_order(&self, order_id: &str) -> Option<OrderUpdate> {
+        self.orders.lock().await.order(order_id).cloned()
+    }
+
+    /// Fills received so far for order `order_id`.
+    pub async fn tracked_fills(&self, order_id: &str) -> Vec<Fill> {
+        self.orders.lock().await.fills(order_id).to_vec()
+    }
+
+    async fn record_update(&self, update: OrderUpdate) {
+        if let Some(event) = self.orders.lock().await.apply_update(update) {
+            let _ = self.order_events.send(event);
+        }
+    }
+
+    async fn record_fill(&self, fill: Fill) {
+        if let Some(event) = self.orders.lock().await.apply_fill(fill) {
+            let _ = self.order_events.send(event);
+        }
+    }
+
+    async fn send_order(&self, method: &str, params: Value) -> OrderResult {
+        let response: OrderResponse = self
+            .request_with_timeout(method, params, ORDER_RESPONSE_TIMEOUT)
+            .await?;
+
+        let order = to_update(response.order)?;
+        let fills = response
+            .trades
+            .into_iter()
+            .map(to_fill)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.record_update(order.clone()).await;
+        for fill in &fills {
+            self.record_fill(fill.clone()).await;
+        }
+
+        Ok(OrderAck { order, fills })
+    }
+
+    /// The order placed with `label`, if Deribit knows of one.
+    async fn order_by_label(
+        &self,
+        label: &str,
+    ) -> Result<Option<OrderUpdate>, Box<dyn std::error::Error + Send + Sync>> {
+        let orders: Vec<OrderInfo> = self
+            .request(
+                "private/get_order_state_by_label",
+                json!({ "currency": "any", "label": label }),
+            )
+            .await?;
+
+        let Some(order) = orders
+            .into_iter()
+            .max_by_key(|order| order.last_update_timestamp)
+        else {
+            return Ok(None);
+        };
+        let update = to_update(order)?;
+        self.record_update(update.clone()).await;
+        Ok(Some(update))
+    }
+
+    pub(super) async fn handle_order_notification(
+        &self,
+        channel: &str,
+        text: &str,
+    ) -> Result<(), String> {
+        if channel.starts_with("user.orders.") {
+            let notification = serde_json::from_str::<
+                Notification<SubscriptionParams<OneOrMany<OrderInfo>>>,
+            >(text)
+            .map_err(|err| format!("Failed to parse {}: {}", channel, err))?;
+
+            for order in notification.params.data.into_vec() {
+                self.record_update(to_update(order)?).await;
+            }
+        } else {
+            let notification = serde_json::from_str::<
+                Notification<SubscriptionParams<OneOrMany<UserTrade>>>,
+            >(text)
+            .map_err(|err| format!("Failed to parse {}: {}", channel, err))?;
+
+            for trade in notification.params.data.into_vec() {
+                self.record_fill(to_fill(trade)?).await;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl OrderGateway for Deribit {
+    async fn place_order(&self, mut request: OrderRequest) -> OrderResult {
+        let method = match request.side {
+            Side::Buy => "private/buy",
+            Side::Sell => "private/sell",
+        };
+        let label = request.label.get_or_insert_with(generated_label).clone();
+
+        let err = match self.send_order(method, order_params(&request)).await {
+            Ok(ack) => return Ok(ack),
+            Err(err) => err,
+        };
+
+        if err.is::<RpcError>() {
+            log::warn!("Deribit rejected {:?}: {}", request, err);
+            let _ = self.order_events.send(OrderEvent::Rejected {
+                exchange: ExchangeType::Deribit,
+                request,
+                reason: err.to_string(),
+            });
+            return Err(err);
+        }
+
+        // The order may have reached Deribit regardless, so look for it.
+        log::warn!("No answer from Deribit to {:?}: {}", request, err);
+        match self.order_by_label(&label).await {
+            Ok(Some(order)) => {
+                return Ok(OrderAck {
+                    order,
+                    fills: Vec::new(),
+                });
+            }
+            Ok(None) => {}
+            Err(lookup) => log::warn!("Failed to look up Deribit order {}: {}", label, lookup),
+        }
+
+        self.orders.lock().await.add_pending(request.clone());
+        let _ = self.order_events.send(OrderEvent::Unknown {
+            exchange: ExchangeType::Deribit,
+            request: request.clone(),
+            reason: err.to_string(),
+        });
+        Err(Box::new(OrderStateUnknown {
+            request,
+            reason: err.to_string(),
+        }))
+    }
+
+    async fn edit_order(&self, order_id: &str, amount: f64, price: f64) -> OrderResult {
+        self.send_order(
+            "private/edit",
+            json!({ "order_id": order_id, "amount": amount, "price": price }),
+        )
+        .await
+    }
+
+    async fn cancel_order(
+        &self,
+        order_id: &str,
+    ) -> Result<OrderUpdate, Box<dyn std::error::Error + Send + Sync>> {
+        let order: OrderInfo = self
+            .request_with_timeout(
+                "private/cancel",
+                json!({ "order_id": order_id }),
+                ORDER_RESPONSE_TIMEOUT,
+            )
+            .await?;
+
+        let update = to_update(order)?;
+        self.record_update(update.clone()).await;
+        Ok(update)
+    }
+
+    async fn cancel_all(&self) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let cancelled: u64 = self
+            .request_with_timeout("private/cancel_all", json!({}), ORDER_RESPONSE_TIMEOUT)
+            .await?;
+        log::info!("Cancelled {} Deribit orders.", cancelled);
+        Ok(cancelled)
+    }
+
+    fn order_events(&self) -> broadcast::Receiver<OrderEvent> {
+        self.order_events.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{Value, json};
+
+    use crate::book_management::traded_instruments::Instrument;
+    use crate::exchange_connectivity::mock_server::{
+        MockServer, connect_deribit, deribit_fixture, deribit_subscription,
+    };
+    use crate::exchange_connectivity::orders::{
+        OrderEvent, OrderGateway, OrderRequest, OrderState, OrderStateUnknown, Side,
+    };
+
+    fn order_json(state: &str, filled_amount: f64, updated: u64) -> Value {
+        json!({
+            "order_id": "ORD-1", "instrument_name": "BTC_USDT", "direction": "buy",
+            "price": 42000.0, "amount": 0.5, "filled_amount": filled_amount,
+            "average_price": 42000.0, "order_state": state, "order_type": "limit",
+            "label": "", "creation_timestamp": 1, "last_update_timestamp": updated,
+        })
+    }
+
+    #[tokio::test]
+    async fn tracks_limit_order_to_fill() {
+        let server = MockServer::start(deribit_fixture).await;
+        let deribit = connect_deribit(&server).await;
+        let mut events = deribit.order_events();
+
+        deribit.track_orders(true).await.unwrap();
+        let cancel_on_disconnect = server.wait_for("private/enable_cancel_on_disconnect").await;
+        assert_eq!(cancel_on_disconnect["params"]["scope"], "connection");
+
+        let ack = deribit
+            .place_order(
+                OrderRequest::limit(Instrument::BtcUsdt, Side::Buy, 0.5, 42000.0)
+                    .post_only()
+                    .with_label("test"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(ack.order.state, OrderState::Open);
+        assert!(ack.fills.is_empty());
+
+        let sent = server.wait_for("private/buy").await;
+        assert_eq!(sent["params"]["instrument_name"], "BTC_USDT");
+        assert_eq!(sent["params"]["type"], "limit");
+        assert_eq!(sent["params"]["post_only"], true);
+        assert_eq!(sent["params"]["label"], "test");
+
+        let trade = json!({
+            "trade_id": "T-1", "order_id": "ORD-1", "instrument_name": "BTC_USDT",
+            "direction": "buy", "price": 42000.0, "amount": 0.5, "fee": 0.0,
+            "fee_currency": "BTC", "timestamp": 5,
+        });
+        server.push(deribit_subscription(
+            "user.orders.any.any.raw",
+            order_json("filled", 0.5, 5),
+        ));
+        server.push(deribit_subscription(
+            "user.trades.any.any.raw",
+            json!([trade]),
+        ));
+        // Repeats of what we already know produce no events.
+        server.push(deribit_subscription(
+            "user.orders.any.any.raw",
+            order_json("filled", 0.5, 5),
+        ));
+        server.push(deribit_subscription(
+            "user.trades.any.any.raw",
+            json!([trade]),
+        ));
+
+        match events.recv().await.unwrap() {
+            OrderEvent::Updated(update) => assert_eq!(update.state, OrderState::Open),
+            event => panic!("Unexpected event {:?}", event),
+        }
+        match events.recv().await.unwrap() {
+            OrderEvent::Updated(update) => assert_eq!(update.state, OrderState::Filled),
+            event => panic!("Unexpected event {:?}", event),
+        }
+        match events.recv().await.unwrap() {
+            OrderEvent::Filled(fill) => assert_eq!(fill.trade_id, "T-1"),
+            event => panic!("Unexpected event {:?}", event),
+        }
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(events.try_recv().is_err());
+        assert_eq!(deribit.tracked_fills("ORD-1").await.len(), 1);
+        assert_eq!(
+            deribit.tracked_order("ORD-1").await.unwrap().filled_amount,
+            0.5
+        );
+    }
+
+    #[tokio::test]
+    async fn edits_cancels_and_reports_rejections() {
+        let server = MockServer::start(|request: &Value| match request["method"].as_str() {
+            Some("private/sell") => vec![json!({
+                "jsonrpc": "2.0", "id": request["id"],
+                "error": { "code": 10009, "message": "not_enough_funds" },
+            })],
+            _ => deribit_fixture(request),
+        })
+        .await;
+        let deribit = connect_deribit(&server).await;
+        let mut events = deribit.order_events();
+
+        let market = deribit
+            .place_order(OrderRequest::market(Instrument::BtcUsdt, Side::Buy, 0.1))
+            .await
+            .unwrap();
+        assert_eq!(market.order.state, OrderState::Filled);
+        assert_eq!(market.order.price, None);
+        assert_eq!(market.fills.len(), 1);
+
+        let edited = deribit.edit_order("ORD-2", 0.2, 41000.0).await.unwrap();
+        assert_eq!(edited.order.price, Some(41000.0));
+
+        let cancelled = deribit.cancel_order("ORD-2").await.unwrap();
+        assert_eq!(cancelled.state, OrderState::Cancelled);
+        assert_eq!(deribit.cancel_all().await.unwrap(), 3);
+
+        let rejected = deribit
+            .place_order(OrderRequest::limit(
+                Instrument::BtcUsdt,
+                Side::Sell,
+                1.0,
+                1.0,
+            ))
+            .await;
+        assert!(
+            rejected
+                .unwrap_err()
+                .to_string()
+                .contains("not_enough_funds")
+        );
+
+        let mut saw_rejection = false;
+        while let Ok(event) = events.try_recv() {
+            if let OrderEvent::Rejected { reason, .. } = event {
+                saw_rejection = reason.contains("not_enough_funds");
+            }
+        }
+        assert!(saw_rejection);
+    }
+
+    #[tokio::test]
+    async fn unanswered_orders_are_looked_up_or_left_pending() {
+        let server = MockServer::start(|request: &Value| match request["method"].as_str() {
+            Some("private/buy") => Vec::new(),
+            Some("private/get_order_state_by_label") => {
+                let orders = match request["params"]["label"].as_str() {
+                    Some("found") => vec![order_json("open", 0.0, 2)],
+                    _ => Vec::new(),
+                };
+                vec![json!({ "jsonrpc": "2.0", "id": request["id"], "result": orders })]
+            }
+            _ => deribit_fixture(request),
+        })
+        .await;
+        let deribit = connect_deribit(&server).await;
+        let mut events = deribit.order_events();
+
+        let order = |label| {
+            OrderRequest::limit(Instrument::BtcUsdt, Side::Buy, 0.5, 42000.0).with_label(label)
+        };
+        let (found, lost) = tokio::join!(
+            deribit.place_order(order("found")),
+            deribit.place_order(order("lost")),
+        );
+        assert_eq!(found.unwrap().order.order_id, "ORD-1");
+        let lost = lost.unwrap_err();
+        let unknown = lost.downcast_ref::<OrderStateUnknown>().unwrap();
+        assert_eq!(unknown.request.label.as_deref(), Some("lost"));
+        assert_eq!(deribit.pending_orders().await.len(), 1);
+
+        // The order turns out to be live after all.
+        let mut update = order_json("open", 0.0, 3);
+        update["order_id"] = json!("ORD-2");
+        update["label"] = json!("lost");
+        server.push(deribit_subscription("user.orders.any.any.raw", update));
+
+        let mut saw_unknown = false;
+        loop {
+            match events.recv().await.unwrap() {
+                OrderEvent::Unknown { request, .. } => {
+                    saw_unknown = request.label.as_deref() == Some("lost")
+                }
+                OrderEvent::Updated(update) if update.order_id == "ORD-2" => break,
+                OrderEvent::Rejected { .. } => panic!("Timeouts are not rejections"),
+                _ => {}
+            }
+        }
+        assert!(saw_unknown);
+        assert!(deribit.pending_orders().await.is_empty());
+    }
+}
@@ -0,0 +1,258 @@
+//! Zero-copy fast path for decoding order book frames.
+//!
+//! Instead of building a `serde_json::Value` tree (or even a typed
+//! model), the frame is scanned in place: the `bids`/`asks` keys are
+//! located with `memchr`'s SIMD-accelerated substring search and each
+//! `[price, quantity]` pair is parsed straight from the borrowed text
+//! into book levels. Both JSON numbers (Deribit) and decimal strings
+//! (Binance) are accepted.
+//!
+//! The fast path only handles well-formed books. It returns `None` for
+//! anything else (error responses, escaped strings, malformed levels),
+//! in which case callers fall back to the typed decoder in
+//! [`levels`](super::levels), which reports exactly what was wrong.
+use memchr::memmem;
+
+use crate::book_management::{Ask, Bid, Order, traded_instruments::Instrument};
+use crate::exchange_connectivity::ExchangeType;
+
+struct Cursor<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn after_key(text: &'a str, key: &str) -> Option<Self> {
+        let bytes = text.as_bytes();
+        let mut from = 0;
+
+        // Keys are searched for as `"key"` followed by a colon, which
+        // cannot match inside an (escaped) string value.
+        while let Some(found) = memmem::find(&bytes[from..], key.as_bytes()) {
+            let start = from + found;
+            let end = start + key.len();
+            from = end;
+
+            if start == 0 || bytes[start - 1] != b'"' || bytes.get(end) != Some(&b'"') {
+                continue;
+            }
+
+            let mut cursor = Cursor { text, pos: end + 1 };
+            cursor.skip_ws();
+            if cursor.eat(b':') {
+                cursor.skip_ws();
+                return Some(cursor);
+            }
+        }
+
+        None
+    }
+
+    #[inline]
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    #[inline]
+    fn skip_ws(&mut self) {
+        while let Some(b' ' | b'\n' | b'\r' | b'\t') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    #[inline]
+    fn eat(&mut self, byte: u8) -> bool {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    #[inline]
+    fn number(&mut self) -> Option<f64> {
+        let bytes = self.text.as_bytes();
+
+        let (start, end) = if self.eat(b'"') {
+            let start = self.pos;
+            let end = start + memchr::memchr(b'"', &bytes[start..])?;
+            self.pos = end + 1;
+            (start, end)
+        } else {
+            let start = self.pos;
+            while let Some(b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E') = self.peek() {
+                self.pos += 1;
+            }
+            (start, self.pos)
+        };
+
+        // Slice boundaries always sit next to ASCII bytes, so this is
+        // a valid `str` slice; anything exotic (escapes) fails to parse.
+        let value: f64 = self.text.get(start..end)?.parse().ok()?;
+        value.is_finite().then_some(value)
+    }
+
+    /// Parse one `[price, quantity]` pair.
+    #[inline]
+    fn level(&mut self) -> Option<(f64, f64)> {
+        if !self.eat(b'[') {
+            return None;
+        }
+        self.skip_ws();
+        let price = self.number()?;
+        self.skip_ws();
+        if !self.eat(b',') {
+            return None;
+        }
+        self.skip_ws();
+        let quantity = self.number()?;
+        self.skip_ws();
+        if !self.eat(b']') || quantity < 0.0 {
+            return None;
+        }
+
+        Some((price, quantity))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let start = self.pos;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+        self.text.get(start..self.pos)?.parse().ok()
+    }
+}
+
+/// Append the levels stored under `key` (e.g. `"bids"`) to `out`.
+///
+/// `out` is only extended on success, so a reused buffer is left as it
+/// was if the frame has to go through the slow path instead.
+pub fn parse_levels_into<T: Order>(
+    text: &str,
+    key: &str,
+    exchange: ExchangeType,
+    instrument: Instrument,
+    out: &mut Vec<T>,
+) -> Option<()> {
+    let mut cursor = Cursor::after_key(text, key)?;
+    let initial_len = out.len();
+
+    let parsed = (|| {
+        if !cursor.eat(b'[') {
+            return None;
+        }
+        cursor.skip_ws();
+        if cursor.eat(b']') {
+            return Some(());
+        }
+
+        loop {
+            let (price, quantity) = cursor.level()?;
+            out.push(T::new(instrument, exchange, quantity, price));
+
+            cursor.skip_ws();
+            if cursor.eat(b']') {
+                return Some(());
+            }
+            if !cursor.eat(b',') {
+                return None;
+            }
+            cursor.skip_ws();
+        }
+    })();
+
+    if parsed.is_none() {
+        out.truncate(initial_len);
+    }
+    parsed
+}
+
+/// Parse a book frame into reusable bid and ask buffers.
+pub fn parse_book_into(
+    text: &str,
+    exchange: ExchangeType,
+    instrument: Instrument,
+    bids: &mut Vec<Bid>,
+    asks: &mut Vec<Ask>,
+) -> Option<()> {
+    let initial_bids = bids.len();
+    parse_levels_into(text, "bids", exchange, instrument, bids)?;
+
+    if parse_levels_into(text, "asks", exchange, instrument, asks).is_none() {
+        bids.truncate(initial_bids);
+        return None;
+    }
+
+    Some(())
+}
+
+/// Parse a book frame into freshly allocated bid and ask vectors.
+pub fn parse_book(
+    text: &str,
+    exchange: ExchangeType,
+    instrument: Instrument,
+) -> Option<(Vec<Bid>, Vec<Ask>)> {
+    let mut bids = Vec::new();
+    let mut asks = Vec::new();
+    parse_book_into(text, exchange, instrument, &mut bids, &mut asks)?;
+
+    Some((bids, asks))
+}
+
+/// Read the first unsigned integer stored under `key`.
+pub fn find_u64(text: &str, key: &str) -> Option<u64> {
+    Cursor::after_key(text, key)?.u64()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find_u64, parse_book};
+    use crate::book_management::{Order, traded_instruments::Instrument};
+    use crate::exchange_connectivity::ExchangeType;
+
+    #[test]
+    fn parses_deribit_numbers() {
+        let text = r#"{"jsonrpc":"2.0","id":10001,"result":{"timestamp":1700000000123,
+            "stats":{"volume":12.5},"state":"open","bids":[[42000.5,1.25],[41999,3e-1]],
+            "asks":[ ],"instrument_name":"BTC_USDT"}}"#;
+
+        let (bids, asks) = parse_book(text, ExchangeType::Deribit, Instrument::BtcUsdt).unwrap();
+
+        assert_eq!(bids.len(), 2);
+        assert_eq!(bids[0].price(), 42000.5);
+        assert_eq!(bids[1].quantity(), 0.3);
+        assert!(asks.is_empty());
+        assert_eq!(find_u64(text, "timestamp"), Some(1700000000123));
+    }
+
+    #[test]
+    fn parses_binance_decimal_strings() {
+        let text = r#"{"id":"10001","status":200,"result":{"lastUpdateId":1027024,
+            "bids":[["4.00000000","431.00000000"]],"asks":[["4.00000200", "12.00000000"]]}}"#;
+
+        let (bids, asks) = parse_book(text, ExchangeType::Binance, Instrument::BtcUsdt).unwrap();
+
+        assert_eq!(bids[0].quantity(), 431.0);
+        assert_eq!(asks[0].price(), 4.000002);
+    }
+
+    #[test]
+    fn rejects_anything_unusual() {
+        let malformed = [
+            r#"{"id":1,"error":{"code":1,"message":"\"bids\": []"}}"#,
+            r#"{"result":{"bids":[[1,2]],"asks":[[1]]}}"#,
+            r#"{"result":{"bids":[[1,2,3]],"asks":[]}}"#,
+            r#"{"result":{"bids":[[1,-2]],"asks":[]}}"#,
+            r#"{"result":{"bids":[[1,2]]"#,
+        ];
+
+        for text in malformed {
+            assert!(
+                parse_book(text, ExchangeType::Deribit, Instrument::BtcUsdt).is_none(),
+                "{}",
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! Typed decoding of `[price, quantity]` book levels shared by every
+//! exchange connector.
+//!
+//! Levels are decoded one at a time so that a single malformed entry is
+//! counted instead of failing (or silently shrinking) the whole book.
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+
+use crate::book_management::{Order, traded_instruments::Instrument};
+use crate::exchange_connectivity::ExchangeType;
+
+/// A numeric field sent either as a JSON number (Deribit) or as a
+/// decimal string (Binance).
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Decimal {
+    Value(f64),
+    Invalid,
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DecimalVisitor;
+
+        impl<'de> Visitor<'de> for DecimalVisitor {
+            type Value = Decimal;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a number or a decimal string")
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Decimal, E> {
+                Ok(Decimal::Value(v))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Decimal, E> {
+                Ok(Decimal::Value(v as f64))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Decimal, E> {
+                Ok(Decimal::Value(v as f64))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Decimal, E> {
+                Ok(v.parse().map_or(Decimal::Invalid, Decimal::Value))
+            }
+
+            fn visit_bool<E: de::Error>(self, _: bool) -> Result<Decimal, E> {
+                Ok(Decimal::Invalid)
+            }
+
+            fn visit_unit<E: de::Error>(self) -> Result<Decimal, E> {
+                Ok(Decimal::Invalid)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Decimal, A::Error> {
+                while seq.next_element::<IgnoredAny>()?.is_some() {}
+                Ok(Decimal::Invalid)
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Decimal, A::Error> {
+                while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+                Ok(Decimal::Invalid)
+            }
+        }
+
+        deserializer.deserialize_any(DecimalVisitor)
+    }
+}
+
+/// One entry of a `bids`/`asks` array as sent by an exchange.
+///
+/// Anything other than a two element array of finite, non-negative
+/// numbers is kept as [`RawLevel::Malformed`] so it can be reported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RawLevel {
+    Valid { price: f64, quantity: f64 },
+    Malformed,
+}
+
+impl RawLevel {
+    fn from_fields(fields: &[Decimal]) -> Self {
+        match fields {
+            [Decimal::Value(price), Decimal::Value(quantity)]
+                if price.is_finite() && quantity.is_finite() && *quantity >= 0.0 =>
+            {
+                RawLevel::Valid {
+                    price: *price,
+                    quantity: *quantity,
+                }
+            }
+            _ => RawLevel::Malformed,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for RawLevel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RawLevelVisitor;
+
+        impl<'de> Visitor<'de> for RawLevelVisitor {
+            type Value = RawLevel;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a [price, quantity] pair")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<RawLevel, A::Error> {
+                let mut fields = [Decimal::Invalid; 3];
+                let mut len = 0;
+
+                while let Some(field) = seq.next_element::<Decimal>()? {
+                    if len < fields.len() {
+                        fields[len] = field;
+                    }
+                    len += 1;
+                }
+
+                Ok(RawLevel::from_fields(&fields[..len.min(fields.len())]))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<RawLevel, A::Error> {
+                while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+                Ok(RawLevel::Malformed)
+            }
+
+            fn visit_str<E: de::Error>(self, _: &str) -> Result<RawLevel, E> {
+                Ok(RawLevel::Malformed)
+            }
+
+            fn visit_f64<E: de::Error>(self, _: f64) -> Result<RawLevel, E> {
+                Ok(RawLevel::Malformed)
+            }
+
+            fn visit_u64<E: de::Error>(self, _: u64) -> Result<RawLevel, E> {
+                Ok(RawLevel::Malformed)
+            }
+
+            fn visit_i64<E: de::Error>(self, _: i64) -> Result<RawLevel, E> {
+                Ok(RawLevel::Malformed)
+            }
+
+            fn visit_bool<E: de::Error>(self, _: bool) -> Result<RawLevel, E> {
+                Ok(RawLevel::Malformed)
+            }
+
+            fn visit_unit<E: de::Error>(self) -> Result<RawLevel, E> {
+                Ok(RawLevel::Malformed)
+            }
+        }
+
+        deserializer.deserialize_any(RawLevelVisitor)
+    }
+}
+
+/// Orders decoded from one side of a book, alongside how many levels
+/// had to be discarded.
+#[derive(Debug)]
+pub struct ParsedOrders<T: Order> {
+    pub orders: Vec<T>,
+    pub malformed: usize,
+}
+
+/// Convert raw levels into orders of type `T`, counting malformed levels.
+pub fn into_orders<T: Order>(
+    levels: &[RawLevel],
+    exchange: ExchangeType,
+    instrument: Instrument,
+) -> ParsedOrders<T> {
+    let mut orders = Vec::with_capacity(levels.len());
+    let mut malformed = 0;
+
+    for level in levels {
+        match *level {
+            RawLevel::Valid { price, quantity } => {
+                orders.push(T::new(instrument, exchange, quantity, price))
+            }
+            RawLevel::Malformed => malformed += 1,
+        }
+    }
+
+    ParsedOrders { orders, malformed }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RawLevel, into_orders};
+    use crate::book_management::{Bid, Order, traded_instruments::Instrument};
+    use crate::exchange_connectivity::ExchangeType;
+
+    #[test]
+    fn decodes_numbers_and_decimal_strings() {
+        let levels: Vec<RawLevel> =
+            serde_json::from_str(r#"[[101.5, 2], ["100.25", "0.5"]]"#).unwrap();
+
+        assert_eq!(
+            levels,
+            vec![
+                RawLevel::Valid {
+                    price: 101.5,
+                    quantity: 2.0
+                },
+                RawLevel::Valid {
+                    price: 100.25,
+                    quantity: 0.5
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_levels_are_counted() {
+        let levels: Vec<RawLevel> = serde_json::from_str(
+            r#"[[1, 1], [1], [1, 2, 3], ["x", "1"], {"p": 1}, null, [[1], 2], [1, -2]]"#,
+        )
+        .unwrap();
+
+        let parsed = into_orders::<Bid>(&levels, ExchangeType::Deribit, Instrument::BtcUsdt);
+
+        assert_eq!(parsed.orders.len(), 1);
+        assert_eq!(parsed.orders[0].price(), 1.0);
+        assert_eq!(parsed.malformed, 7);
+    }
+}
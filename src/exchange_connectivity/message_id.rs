@@ -0,0 +1,335 @@
+//! Request id allocation shared by every exchange connection.
+//!
+//! Ids for requests are handed out from [`REQUEST_ID_START`] up to
+//! [`REQUEST_ID_END`] and then wrap, skipping any id still in flight.
+//! Fixed control messages (auth, heartbeats, ...) use [`ControlId`]s,
+//! which all sit below the request range so the two can never collide.
+//!
+//! The allocator also holds the response slot of every in-flight
+//! request, so a response is only accepted while its request is still
+//! waiting for it. Late responses to abandoned requests are dropped
+//! instead of being picked up by whoever reuses the id.
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::{Serialize, Serializer};
+
+/// First id handed out to requests.
+pub const REQUEST_ID_START: u64 = 10_000;
+
+/// Request ids wrap back to [`REQUEST_ID_START`] once they reach this.
+pub const REQUEST_ID_END: u64 = 1_000_000;
+
+/// Ids reserved for fixed control messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlId {
+    /// Deribit `public/auth`, both initial and refresh.
+    Auth,
+    /// Deribit `public/set_heartbeat`.
+    SetHeartbeat,
+    /// Deribit `public/test` sent in reply to a heartbeat.
+    HeartbeatTest,
+    /// Binance `ping`.
+    Ping,
+}
+
+impl ControlId {
+    const ALL: [ControlId; 4] = [
+        ControlId::Auth,
+        ControlId::SetHeartbeat,
+        ControlId::HeartbeatTest,
+        ControlId::Ping,
+    ];
+
+    pub const fn id(self) -> u64 {
+        match self {
+            ControlId::Auth => 9929,
+            ControlId::SetHeartbeat => 9098,
+            ControlId::HeartbeatTest => 8212,
+            ControlId::Ping => 1000,
+        }
+    }
+
+    pub fn from_id(id: u64) -> Option<Self> {
+        Self::ALL.into_iter().find(|control| control.id() == id)
+    }
+}
+
+const _: () = {
+    let mut i = 0;
+    while i < ControlId::ALL.len() {
+        assert!(ControlId::ALL[i].id() < REQUEST_ID_START);
+        i += 1;
+    }
+};
+
+/// A request id as it appears on the wire.
+///
+/// Deribit uses JSON-RPC integers, while Binance accepts strings as well
+/// as signed 64-bit integers and echoes back whichever it was sent. The
+/// original form is kept so it can be echoed back unchanged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WireId {
+    Int(i64),
+    Text(String),
+}
+
+impl WireId {
+    /// The id as one of our (non-negative) numeric ids, if it is one.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            WireId::Int(id) => u64::try_from(*id).ok(),
+            WireId::Text(id) => id.parse().ok(),
+        }
+    }
+}
+
+impl fmt::Display for WireId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireId::Int(id) => write!(f, "{}", id),
+            WireId::Text(id) => write!(f, "{}", id),
+        }
+    }
+}
+
+impl Serialize for WireId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            WireId::Int(id) => serializer.serialize_i64(*id),
+            WireId::Text(id) => serializer.serialize_str(id),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for WireId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct WireIdVisitor;
+
+        impl Visitor<'_> for WireIdVisitor {
+            type Value = WireId;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a string or 64-bit signed integer id")
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<WireId, E> {
+                Ok(WireId::Int(v))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<WireId, E> {
+                i64::try_from(v)
+                    .map(WireId::Int)
+                    .map_err(|_| E::custom(format!("id {} does not fit in an i64", v)))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<WireId, E> {
+                Ok(WireId::Text(v.to_string()))
+            }
+        }
+
+        deserializer.deserialize_any(WireIdVisitor)
+    }
+}
+
+/// Allocates unique request ids for one connection and holds the
+/// responses of requests that are still in flight.
+#[derive(Debug)]
+pub struct MessageIdAllocator<T> {
+    next: AtomicU64,
+    in_flight: Mutex<HashMap<u64, Option<T>>>,
+}
+
+impl<T> Default for MessageIdAllocator<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> MessageIdAllocator<T> {
+    pub fn new() -> Self {
+        Self {
+            next: AtomicU64::new(REQUEST_ID_START),
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn next_candidate(&self) -> u64 {
+        let advance = |id: u64| {
+            if id + 1 >= REQUEST_ID_END {
+                REQUEST_ID_START
+            } else {
+                id + 1
+            }
+        };
+
+        // `fetch_update` retries on contention, so concurrent callers
+        // always see distinct candidates.
+        match self
+            .next
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |id| Some(advance(id)))
+        {
+            Ok(id) | Err(id) => id,
+        }
+    }
+
+    /// Reserve an id that no other in-flight request is using. The id is
+    /// released when the returned [`RequestId`] is dropped.
+    pub fn allocate(&self) -> Result<RequestId<'_, T>, String> {
+        for _ in REQUEST_ID_START..REQUEST_ID_END {
+            let id = self.next_candidate();
+            if let Entry::Vacant(slot) = self.in_flight.lock().unwrap().entry(id) {
+                slot.insert(None);
+                return Ok(RequestId {
+                    id,
+                    allocator: self,
+                });
+            }
+        }
+
+        Err("Every request id is in flight.".to_string())
+    }
+
+    pub fn is_in_flight(&self, id: u64) -> bool {
+        self.in_flight.lock().unwrap().contains_key(&id)
+    }
+
+    pub fn in_flight_count(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+
+    /// Store the response to request `id`. The response is handed back
+    /// if no request with that id is waiting for it.
+    pub fn deliver(&self, id: u64, response: T) -> Result<(), T> {
+        match self.in_flight.lock().unwrap().get_mut(&id) {
+            Some(slot) => {
+                *slot = Some(response);
+                Ok(())
+            }
+            None => Err(response),
+        }
+    }
+}
+
+/// An id reserved for one request, released when dropped.
+#[derive(Debug)]
+pub struct RequestId<'a, T> {
+    id: u64,
+    allocator: &'a MessageIdAllocator<T>,
+}
+
+impl<T> RequestId<'_, T> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The id as a JSON string, the form we send to Binance.
+    pub fn wire_text(&self) -> WireId {
+        WireId::Text(self.id.to_string())
+    }
+
+    /// Take the response to this request if it has arrived.
+    pub fn try_take(&self) -> Option<T> {
+        self.allocator
+            .in_flight
+            .lock()
+            .unwrap()
+            .get_mut(&self.id)
+            .and_then(Option::take)
+    }
+}
+
+impl<T> Drop for RequestId<'_, T> {
+    fn drop(&mut self) {
+        self.allocator.in_flight.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::sync::atomic::Ordering;
+    use std::sync::{Arc, Mutex};
+
+    use super::{ControlId, MessageIdAllocator, REQUEST_ID_END, REQUEST_ID_START, WireId};
+
+    #[test]
+    fn concurrent_allocations_are_unique() {
+        let allocator = Arc::new(MessageIdAllocator::<()>::new());
+        let seen = Arc::new(Mutex::new(HashSet::new()));
+
+        let handles = (0..8)
+            .map(|_| {
+                let allocator = Arc::clone(&allocator);
+                let seen = Arc::clone(&seen);
+                std::thread::spawn(move || {
+                    let held = (0..500)
+                        .map(|_| allocator.allocate().unwrap())
+                        .collect::<Vec<_>>();
+                    let mut seen = seen.lock().unwrap();
+                    for request in &held {
+                        assert!(seen.insert(request.id()));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(seen.lock().unwrap().len(), 4000);
+        assert_eq!(allocator.in_flight_count(), 0);
+    }
+
+    #[test]
+    fn wrapping_skips_in_flight_ids() {
+        let allocator = MessageIdAllocator::<()>::new();
+        let held = allocator.allocate().unwrap();
+        assert_eq!(held.id(), REQUEST_ID_START);
+
+        allocator.next.store(REQUEST_ID_END - 1, Ordering::Relaxed);
+        assert_eq!(allocator.allocate().unwrap().id(), REQUEST_ID_END - 1);
+        assert_eq!(allocator.allocate().unwrap().id(), REQUEST_ID_START + 1);
+    }
+
+    #[test]
+    fn responses_only_reach_waiting_requests() {
+        let allocator = MessageIdAllocator::new();
+        let request = allocator.allocate().unwrap();
+        let id = request.id();
+
+        assert_eq!(allocator.deliver(id, "first"), Ok(()));
+        assert_eq!(request.try_take(), Some("first"));
+        assert_eq!(request.try_take(), None);
+
+        drop(request);
+        assert_eq!(allocator.deliver(id, "late"), Err("late"));
+        assert!(!allocator.is_in_flight(id));
+    }
+
+    #[test]
+    fn control_ids_are_reserved() {
+        for control in ControlId::ALL {
+            assert!(control.id() < REQUEST_ID_START);
+            assert_eq!(ControlId::from_id(control.id()), Some(control));
+        }
+        assert_eq!(ControlId::from_id(REQUEST_ID_START), None);
+    }
+
+    #[test]
+    fn wire_ids_keep_their_form() {
+        let text: WireId = serde_json::from_str(r#""10001""#).unwrap();
+        let int: WireId = serde_json::from_str("10001").unwrap();
+        let negative: WireId = serde_json::from_str("-5").unwrap();
+
+        assert_eq!(text.as_u64(), Some(10001));
+        assert_eq!(int.as_u64(), Some(10001));
+        assert_eq!(negative.as_u64(), None);
+        assert_eq!(serde_json::to_string(&text).unwrap(), r#""10001""#);
+        assert_eq!(serde_json::to_string(&negative).unwrap(), "-5");
+    }
+}
@@ -0,0 +1,281 @@
+//! Local WebSocket server standing in for an exchange in tests.
+//!
+//! Every text frame is parsed as JSON, recorded, and passed to a
+//! handler which returns the frames to send back. Notifications can be
+//! pushed to every connected client at any time.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio_tungstenite::{accept_async, tungstenite::protocol::Message};
+
+use super::deribit::Deribit;
+
+type Handler = dyn Fn(&Value) -> Vec<Value> + Send + Sync;
+
+pub struct MockServer {
+    pub url: String,
+    received: Arc<Mutex<Vec<Value>>>,
+    closes: Arc<AtomicUsize>,
+    push: broadcast::Sender<Value>,
+}
+
+impl MockServer {
+    pub async fn start(handler: impl Fn(&Value) -> Vec<Value> + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let closes = Arc::new(AtomicUsize::new(0));
+        let (push, _) = broadcast::channel::<Value>(64);
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let server_received = Arc::clone(&received);
+        let server_closes = Arc::clone(&closes);
+        let server_push = push.clone();
+        tokio::spawn(async move {
+            while let Ok((tcp, _)) = listener.accept().await {
+                let Ok(ws) = accept_async(tcp).await else {
+                    continue;
+                };
+
+                let handler = Arc::clone(&handler);
+                let received = Arc::clone(&server_received);
+                let closes = Arc::clone(&server_closes);
+                let mut pushed = server_push.subscribe();
+                tokio::spawn(async move {
+                    let (mut sink, mut stream) = ws.split();
+                    loop {
+                        tokio::select! {
+                            frame = stream.next() => {
+                                let Some(Ok(Message::Text(text))) = frame else {
+                                    match frame {
+                                        Some(Ok(Message::Close(_))) => {
+                                            closes.fetch_add(1, Ordering::Relaxed);
+                                            break;
+                                        }
+                                        Some(Ok(_)) => continue,
+                                        _ => break,
+                                    }
+                                };
+                                let Ok(request) = serde_json::from_str::<Value>(&text) else {
+                                    continue;
+                                };
+                                received.lock().unwrap().push(request.clone());
+                                for reply in handler(&request) {
+                                    if sink.send(reply.to_string().into()).await.is_err() {
+                                        return;
+                                    }
+                                }
+                            }
+                            Ok(notification) = pushed.recv() => {
+                                if sink.send(notification.to_string().into()).await.is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        MockServer {
+            url,
+            received,
+            closes,
+            push,
+        }
+    }
+
+    /// Send a frame to every connected client.
+    pub fn push(&self, notification: Value) {
+        let _ = self.push.send(notification);
+    }
+
+    /// Every request received so far with the given `method`.
+    pub fn requests(&self, method: &str) -> Vec<Value> {
+        self.received
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|request| request["method"] == method)
+            .cloned()
+            .collect()
+    }
+
+    /// Poll until a request with `method` has been received.
+    pub async fn wait_for(&self, method: &str) -> Value {
+        for _ in 0..50 {
+            if let Some(request) = self.requests(method).pop() {
+                return request;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("Mock server never received {}", method);
+    }
+
+    /// Poll until a client has sent a Close frame.
+    pub async fn wait_for_close(&self) {
+        for _ in 0..50 {
+            if self.closes.load(Ordering::Relaxed) > 0 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("Mock server never received a Close frame");
+    }
+}
+
+/// Connect a [`Deribit`] to `server` and run its manager until it has
+/// sent its authentication request.
+pub async fn connect_deribit(server: &MockServer) -> Arc<Deribit> {
+    let (deribit, _) = Deribit::connect_to(&server.url, "id".into(), "secret".into(), None)
+        .await
+        .expect("Failed to connect to mock server");
+    let deribit = Arc::new(deribit);
+
+    let deribit_clone = Arc::clone(&deribit);
+    deribit
+        .tasks
+        .spawn(async move { deribit_clone.ws_manager().await });
+    server.wait_for("public/auth").await;
+
+    deribit
+}
+
+/// Wrap `result` in a JSON-RPC response to `request`.
+pub fn rpc_result(request: &Value, result: Value) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": request["id"],
+        "result": result,
+        "usIn": 0,
+        "usOut": 0,
+    })
+}
+
+/// A JSON-RPC subscription notification as pushed by Deribit.
+pub fn deribit_subscription(channel: &str, data: Value) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "subscription",
+        "params": {
+            "channel": channel,
+            "data": data,
+        },
+    })
+}
+
+/// Canned replies to the Deribit methods used by the connector.
+pub fn deribit_fixture(request: &Value) -> Vec<Value> {
+    let result = match request["method"].as_str().unwrap_or_default() {
+        "public/auth" => json!({
+            "access_token": "access",
+            "refresh_token": "refresh",
+            "expires_in": 900,
+            "token_type": "bearer",
+            "scope": "connection",
+        }),
+        "public/set_heartbeat" | "public/test" => json!("ok"),
+        "public/get_time" => json!(super::clock::now().as_millis() as u64),
+        "public/get_order_book" => json!({
+            "timestamp": super::clock::now().as_millis() as u64,
+            "instrument_name": request["params"]["instrument_name"],
+            "bids": [[100.0, 1.0], [99.5, 2.0]],
+            "asks": [[100.5, 1.5], [101.0, 3.0]],
+        }),
+        "private/subscribe" => request["params"]["channels"].clone(),
+        "private/get_account_summary" => json!({
+            "currency": request["params"]["currency"],
+            "balance": 2.5,
+            "equity": 2.6,
+            "available_funds": 2.0,
+            "margin_balance": 2.6,
+            "initial_margin": 0.4,
+            "maintenance_margin": 0.2,
+            "total_pl": 0.1,
+        }),
+        "private/get_positions" => json!([{
+            "instrument_name": "BTC-PERPETUAL",
+            "kind": "future",
+            "direction": "buy",
+            "size": 1000.0,
+            "average_price": 42000.0,
+            "mark_price": 42100.0,
+            "floating_profit_loss": 0.0001,
+        }]),
+        "private/get_open_orders_by_currency" => json!([{
+            "order_id": "ETH-1",
+            "instrument_name": "BTC_USDT",
+            "direction": "sell",
+            "price": 45000.0,
+            "amount": 0.1,
+            "filled_amount": 0.0,
+            "order_state": "open",
+            "order_type": "limit",
+            "label": "",
+            "creation_timestamp": 1,
+            "last_update_timestamp": 1,
+        }]),
+        "private/enable_cancel_on_disconnect" => json!("ok"),
+        "private/buy" | "private/sell" | "private/edit" => deribit_order_response(request),
+        "private/cancel" => deribit_order(request, "cancelled", 0.0, 3),
+        "private/cancel_all" => json!(3),
+        _ => return Vec::new(),
+    };
+
+    vec![rpc_result(request, result)]
+}
+
+/// Market orders fill in full straight away, anything else rests.
+fn deribit_order_response(request: &Value) -> Value {
+    let params = &request["params"];
+    if params["type"] == "market" {
+        let amount = params["amount"].as_f64().unwrap_or_default();
+        let order = deribit_order(request, "filled", amount, 1);
+        let trade = json!({
+            "trade_id": format!("T-{}", request["id"]),
+            "order_id": order["order_id"],
+            "instrument_name": order["instrument_name"],
+            "direction": order["direction"],
+            "price": 42000.0,
+            "amount": amount,
+            "fee": 0.0,
+            "fee_currency": "BTC",
+            "timestamp": 1,
+        });
+        json!({ "order": order, "trades": [trade] })
+    } else if request["method"] == "private/edit" {
+        json!({ "order": deribit_order(request, "open", 0.0, 2), "trades": [] })
+    } else {
+        json!({ "order": deribit_order(request, "open", 0.0, 1), "trades": [] })
+    }
+}
+
+fn deribit_order(request: &Value, state: &str, filled_amount: f64, updated: u64) -> Value {
+    let params = &request["params"];
+    let direction = if request["method"] == "private/sell" {
+        "sell"
+    } else {
+        "buy"
+    };
+    let market = params["type"] == "market";
+
+    json!({
+        "order_id": params["order_id"].as_str().unwrap_or("ORD-1"),
+        "instrument_name": params["instrument_name"].as_str().unwrap_or("BTC_USDT"),
+        "direction": direction,
+        "price": if market { json!("market_price") } else { json!(params["price"].as_f64().unwrap_or(42000.0)) },
+        "amount": params["amount"].as_f64().unwrap_or(0.1),
+        "filled_amount": filled_amount,
+        "average_price": if filled_amount > 0.0 { 42000.0 } else { 0.0 },
+        "order_state": state,
+        "order_type": if market { "market" } else { "limit" },
+        "label": params["label"].as_str().unwrap_or_default(),
+        "creation_timestamp": 1,
+        "last_update_timestamp": updated,
+    })
+}
@@ -1,39 +1,66 @@
 mod binance;
+pub mod clock;
 mod deribit;
+pub mod fast_parse;
+pub mod levels;
+pub mod message_id;
+#[cfg(test)]
+pub(crate) mod mock_server;
+pub mod orders;
+pub mod pool;
+pub mod proxy;
+pub mod shutdown;
+pub mod watchdog;
 
-use std::sync::atomic::AtomicBool;
 use std::time::Duration;
 use std::{env, sync::Arc};
 
 use binance::Binance;
-use deribit::Deribit;
+use clock::{ClockSync, FeedLatency, UpdateTimestamps};
+pub use deribit::Deribit;
+pub use deribit::account::{
+    AccountChanges, AccountState, AccountSummary, Direction, OrderInfo, Position, UserTrade,
+};
 use dotenv::dotenv;
+use pool::{ExchangePool, PoolConfig};
+use proxy::ProxyConfig;
+use shutdown::CancellationToken;
 use std::error::Error;
-use tokio::task::spawn;
+use tokio_tungstenite::tungstenite::Utf8Bytes;
+use watchdog::{ConnectionHealth, ConnectionMonitor};
 
 use crate::book_management::{Ask, Bid, traded_instruments::Instrument};
 
+/// Bids, asks and timestamps returned by a book request.
+pub type BidsAsksResult =
+    Result<(Vec<Bid>, Vec<Ask>, UpdateTimestamps), Box<dyn Error + Send + Sync>>;
+
+/// A response frame alongside the local time it was read off the socket.
+#[derive(Debug, Clone)]
+pub(crate) struct ReceivedFrame {
+    pub text: Utf8Bytes,
+    pub received: Duration,
+}
+
 pub trait ConnectedExchangeForBook {
     /// For a given request, pull (up to) `depth` bids and asks for
     /// some specific instrument.
     ///
     /// Returns a vector of bids and asks in the book alongside
-    /// the time at which a response was recieved in time
-    /// after the UNIX epoch (Deribit standard).
+    /// the exchange, receive and normalised timestamps of the
+    /// response, as durations after the UNIX epoch.
     fn pull_bids_asks(
         &self,
         depth: u32,
         instrument: Instrument,
-    ) -> impl std::future::Future<
-        Output = Result<(Vec<Bid>, Vec<Ask>, Duration), Box<dyn Error + Send + Sync>>,
-    > + Send;
+    ) -> impl std::future::Future<Output = BidsAsksResult> + Send;
 
     fn to_instrument_name(instrument: Instrument) -> String
     where
         Self: Sized;
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum ExchangeType {
     Deribit,
     Binance,
@@ -43,71 +70,199 @@ pub enum ExchangeType {
 pub enum Exchange {
     Deribit(Arc<Deribit>),
     Binance(Arc<Binance>),
+    /// Several connections to one venue with instruments sharded
+    /// across them.
+    Pool(Arc<ExchangePool>),
 }
 
 impl Clone for Exchange {
     fn clone(&self) -> Self {
         match self {
-            Exchange::Binance(binance) => Exchange::Binance(Arc::clone(&binance)),
-            Exchange::Deribit(deribit) => Exchange::Deribit(Arc::clone(&deribit)),
+            Exchange::Binance(binance) => Exchange::Binance(Arc::clone(binance)),
+            Exchange::Deribit(deribit) => Exchange::Deribit(Arc::clone(deribit)),
+            Exchange::Pool(pool) => Exchange::Pool(Arc::clone(pool)),
         }
     }
 }
 
 impl Exchange {
-    pub async fn pull_bids_asks(
+    pub async fn pull_bids_asks(&self, depth: u32, instrument: Instrument) -> BidsAsksResult {
+        match self {
+            Exchange::Pool(pool) => pool.pull_bids_asks(depth, instrument).await,
+            connection => connection.pull_from_connection(depth, instrument).await,
+        }
+    }
+
+    /// [`pull_bids_asks`](Self::pull_bids_asks) for a single connection.
+    /// Pools call this on their connections, which are never pools.
+    pub(crate) async fn pull_from_connection(
         &self,
         depth: u32,
         instrument: Instrument,
-    ) -> Result<(Vec<Bid>, Vec<Ask>, Duration), Box<dyn Error + Send + Sync>> {
+    ) -> BidsAsksResult {
         match self {
             Exchange::Deribit(deribit) => deribit.pull_bids_asks(depth, instrument).await,
             Exchange::Binance(binance) => binance.pull_bids_asks(depth, instrument).await,
+            Exchange::Pool(_) => Err("Connection pools cannot be nested".into()),
+        }
+    }
+
+    pub fn exchange_type(&self) -> ExchangeType {
+        match self {
+            Exchange::Deribit(_) => ExchangeType::Deribit,
+            Exchange::Binance(_) => ExchangeType::Binance,
+            Exchange::Pool(pool) => pool.exchange_type(),
+        }
+    }
+
+    /// Clock offset and round-trip tracking for this connection, or for
+    /// the first connection of a pool.
+    pub fn clock(&self) -> &ClockSync {
+        match self {
+            Exchange::Deribit(deribit) => &deribit.clock,
+            Exchange::Binance(binance) => &binance.clock,
+            Exchange::Pool(pool) => pool.connections()[0].clock(),
+        }
+    }
+
+    /// Liveness tracking for this connection. A pool reports its first
+    /// healthy connection, or its first connection if none are.
+    pub fn monitor(&self) -> &ConnectionMonitor {
+        match self {
+            Exchange::Deribit(deribit) => &deribit.monitor,
+            Exchange::Binance(binance) => &binance.monitor,
+            Exchange::Pool(pool) => pool
+                .connections()
+                .iter()
+                .map(Exchange::monitor)
+                .find(|monitor| monitor.health() == ConnectionHealth::Healthy)
+                .unwrap_or_else(|| pool.connections()[0].monitor()),
+        }
+    }
+
+    pub fn connection_health(&self) -> ConnectionHealth {
+        self.monitor().health()
+    }
+
+    /// One-way feed latency of this venue, once it has been measured.
+    pub fn feed_latency(&self) -> Option<FeedLatency> {
+        self.clock().feed_latency()
+    }
+
+    /// Number of book levels this connection has discarded as malformed.
+    pub fn malformed_levels(&self) -> u64 {
+        match self {
+            Exchange::Deribit(deribit) => deribit.malformed_levels(),
+            Exchange::Binance(binance) => binance.malformed_levels(),
+            Exchange::Pool(pool) => pool
+                .connections()
+                .iter()
+                .map(Exchange::malformed_levels)
+                .sum(),
         }
     }
 
+    /// Connect to `exchange` and spawn the tasks serving the connection.
+    ///
+    /// Cancelling the returned token stops those tasks; [`shutdown`]
+    /// also closes the socket and waits for them.
+    ///
+    /// [`shutdown`]: Exchange::shutdown
     pub async fn connect(
         exchange: ExchangeType,
         keys: &ExchangeKeys,
-    ) -> Option<(Exchange, Arc<AtomicBool>)> {
+    ) -> Option<(Exchange, CancellationToken)> {
         match exchange {
             ExchangeType::Binance => {
-                let (binance, keep_alive) = {
-                    let (binance, keep_alive) = Binance::connect().await?;
-                    (Arc::new(binance), keep_alive)
+                let (binance, shutdown) = {
+                    let (binance, shutdown) = Binance::connect(keys.binance_proxy.clone()).await?;
+                    (Arc::new(binance), shutdown)
                 };
 
                 let binance_clone = Arc::clone(&binance);
-                spawn(async move {
+                binance.tasks.spawn(async move {
                     binance_clone.ws_manager().await;
                 });
 
-                Some((Exchange::Binance(binance), keep_alive))
+                let binance_clone = Arc::clone(&binance);
+                binance.tasks.spawn(async move {
+                    binance_clone.clock_sync_task().await;
+                });
+
+                let binance_clone = Arc::clone(&binance);
+                binance.tasks.spawn(async move {
+                    binance_clone.watchdog_task().await;
+                });
+
+                Some((Exchange::Binance(binance), shutdown))
             }
             ExchangeType::Deribit => {
-                let (deribit, keep_alive) = {
-                    let (deribit, keep_alive) = Deribit::connect(
+                let (deribit, shutdown) = {
+                    let (deribit, shutdown) = Deribit::connect(
                         keys.deribit_client_id.to_string(),
                         keys.deribit_api_key.to_string(),
+                        keys.deribit_proxy.clone(),
                     )
                     .await?;
-                    (Arc::new(deribit), keep_alive)
+                    (Arc::new(deribit), shutdown)
                 };
 
                 let deribit_clone = Arc::clone(&deribit);
-                spawn(async move {
+                deribit.tasks.spawn(async move {
                     deribit_clone.ws_manager().await;
                 });
 
-                Some((Exchange::Deribit(deribit), keep_alive))
+                let deribit_clone = Arc::clone(&deribit);
+                deribit.tasks.spawn(async move {
+                    deribit_clone.clock_sync_task().await;
+                });
+
+                let deribit_clone = Arc::clone(&deribit);
+                deribit.tasks.spawn(async move {
+                    deribit_clone.watchdog_task().await;
+                });
+
+                Some((Exchange::Deribit(deribit), shutdown))
             }
         }
     }
+
+    /// Stop the connection's tasks, close its socket and wait for the
+    /// tasks to finish.
+    pub async fn shutdown(&self) {
+        match self {
+            Exchange::Pool(pool) => pool.shutdown().await,
+            connection => connection.shutdown_connection().await,
+        }
+    }
+
+    /// [`shutdown`](Self::shutdown) for a single connection.
+    pub(crate) async fn shutdown_connection(&self) {
+        match self {
+            Exchange::Deribit(deribit) => deribit.shutdown().await,
+            Exchange::Binance(binance) => binance.shutdown().await,
+            Exchange::Pool(_) => log::error!("Connection pools cannot be nested"),
+        }
+    }
+
+    /// Open a pool of connections to `exchange` as configured by `config`.
+    pub async fn connect_pool(
+        exchange: ExchangeType,
+        keys: &ExchangeKeys,
+        config: &PoolConfig,
+    ) -> Option<Exchange> {
+        let pool = ExchangePool::connect(exchange, keys, config).await?;
+        Some(Exchange::Pool(Arc::new(pool)))
+    }
 }
 
 pub struct ExchangeKeys {
     pub deribit_client_id: String,
     pub deribit_api_key: String,
+    /// Proxy for Deribit connections, from `DERIBIT_PROXY`.
+    pub deribit_proxy: Option<ProxyConfig>,
+    /// Proxy for Binance connections, from `BINANCE_PROXY`.
+    pub binance_proxy: Option<ProxyConfig>,
 }
 
 impl ExchangeKeys {
@@ -122,9 +277,17 @@ impl ExchangeKeys {
             "You must create a .env file with your Deribit API key. Read the README.md for more info.",
         );
 
+        let deribit_proxy = ProxyConfig::from_env("DERIBIT_PROXY")
+            .unwrap_or_else(|err| panic!("DERIBIT_PROXY is not a valid proxy URL: {}", err));
+
+        let binance_proxy = ProxyConfig::from_env("BINANCE_PROXY")
+            .unwrap_or_else(|err| panic!("BINANCE_PROXY is not a valid proxy URL: {}", err));
+
         ExchangeKeys {
-            deribit_client_id: deribit_client_id,
-            deribit_api_key: deribit_api_key,
+            deribit_client_id,
+            deribit_api_key,
+            deribit_proxy,
+            binance_proxy,
         }
     }
 }
@@ -0,0 +1,424 @@
+//! Order entry shared by every venue that can trade.
+//!
+//! A venue implementing [`OrderGateway`] accepts [`OrderRequest`]s and
+//! reports what happens to them as [`OrderEvent`]s. Order state and
+//! fills can arrive more than once (in the response to a request and
+//! again on the venue's user channels), so they are funnelled through an
+//! [`OrderTracker`] which only lets changes through.
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::sync::broadcast;
+
+use crate::book_management::traded_instruments::Instrument;
+use crate::exchange_connectivity::ExchangeType;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OrderType {
+    Limit { price: f64 },
+    Market,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimeInForce {
+    #[default]
+    GoodTilCancelled,
+    ImmediateOrCancel,
+    FillOrKill,
+}
+
+/// An order we want to place.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderRequest {
+    pub instrument: Instrument,
+    pub side: Side,
+    /// Amount in the base currency.
+    pub amount: f64,
+    pub order_type: OrderType,
+    pub time_in_force: TimeInForce,
+    pub post_only: bool,
+    pub reduce_only: bool,
+    /// Free-form tag echoed back on every update of the order.
+    pub label: Option<String>,
+}
+
+impl OrderRequest {
+    pub fn limit(instrument: Instrument, side: Side, amount: f64, price: f64) -> Self {
+        Self::new(instrument, side, amount, OrderType::Limit { price })
+    }
+
+    pub fn market(instrument: Instrument, side: Side, amount: f64) -> Self {
+        Self::new(instrument, side, amount, OrderType::Market)
+    }
+
+    fn new(instrument: Instrument, side: Side, amount: f64, order_type: OrderType) -> Self {
+        OrderRequest {
+            instrument,
+            side,
+            amount,
+            order_type,
+            time_in_force: TimeInForce::default(),
+            post_only: false,
+            reduce_only: false,
+            label: None,
+        }
+    }
+
+    pub fn with_time_in_force(mut self, time_in_force: TimeInForce) -> Self {
+        self.time_in_force = time_in_force;
+        self
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn post_only(mut self) -> Self {
+        self.post_only = true;
+        self
+    }
+
+    pub fn reduce_only(mut self) -> Self {
+        self.reduce_only = true;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderState {
+    Open,
+    Filled,
+    Rejected,
+    Cancelled,
+    Untriggered,
+    Triggered,
+}
+
+impl OrderState {
+    /// Whether the order can still trade.
+    pub fn is_open(self) -> bool {
+        matches!(self, OrderState::Open | OrderState::Untriggered)
+    }
+
+    /// Whether the order is done and can never change state again.
+    pub fn is_terminal(self) -> bool {
+        matches!(
+            self,
+            OrderState::Filled | OrderState::Rejected | OrderState::Cancelled
+        )
+    }
+}
+
+/// The latest known state of one of our orders.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderUpdate {
+    pub exchange: ExchangeType,
+    pub order_id: String,
+    pub instrument_name: String,
+    pub side: Side,
+    pub state: OrderState,
+    pub amount: f64,
+    pub filled_amount: f64,
+    /// Limit price, or `None` for market orders.
+    pub price: Option<f64>,
+    pub average_price: f64,
+    pub label: Option<String>,
+    /// Exchange time of this update.
+    pub updated: Duration,
+}
+
+/// One execution against one of our orders.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fill {
+    pub exchange: ExchangeType,
+    pub trade_id: String,
+    pub order_id: String,
+    pub instrument_name: String,
+    pub side: Side,
+    pub price: f64,
+    pub amount: f64,
+    pub fee: f64,
+    pub fee_currency: String,
+    /// Exchange time of the execution.
+    pub timestamp: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OrderEvent {
+    /// An order was accepted or changed state.
+    Updated(OrderUpdate),
+    Filled(Fill),
+    /// The venue refused an order outright.
+    Rejected {
+        exchange: ExchangeType,
+        request: OrderRequest,
+        reason: String,
+    },
+    /// No answer to an order came back, so it may or may not be live.
+    /// Later updates carrying its label settle it.
+    Unknown {
+        exchange: ExchangeType,
+        request: OrderRequest,
+        reason: String,
+    },
+}
+
+/// An order's state straight after a request, with any fills it got.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderAck {
+    pub order: OrderUpdate,
+    pub fills: Vec<Fill>,
+}
+
+/// An `Err` holding [`OrderStateUnknown`] means the order may still be
+/// live; any other `Err` means it was not placed.
+pub type OrderResult = Result<OrderAck, Box<dyn Error + Send + Sync>>;
+
+/// Placing `request` got no answer, so whether it is live is unknown.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderStateUnknown {
+    pub request: OrderRequest,
+    pub reason: String,
+}
+
+impl fmt::Display for OrderStateUnknown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "State of {:?} is unknown: {}", self.request, self.reason)
+    }
+}
+
+impl Error for OrderStateUnknown {}
+
+/// A venue we can send orders to.
+pub trait OrderGateway {
+    fn place_order(&self, request: OrderRequest) -> impl Future<Output = OrderResult> + Send;
+
+    /// Change the amount and price of a resting order.
+    fn edit_order(
+        &self,
+        order_id: &str,
+        amount: f64,
+        price: f64,
+    ) -> impl Future<Output = OrderResult> + Send;
+
+    fn cancel_order(
+        &self,
+        order_id: &str,
+    ) -> impl Future<Output = Result<OrderUpdate, Box<dyn Error + Send + Sync>>> + Send;
+
+    /// Cancel every open order, returning how many were cancelled.
+    fn cancel_all(&self) -> impl Future<Output = Result<u64, Box<dyn Error + Send + Sync>>> + Send;
+
+    /// Every order and fill event from now on.
+    fn order_events(&self) -> broadcast::Receiver<OrderEvent>;
+}
+
+/// Lifecycle of our orders, built from possibly duplicated updates.
+#[derive(Debug, Default)]
+pub struct OrderTracker {
+    orders: BTreeMap<String, OrderUpdate>,
+    fills: BTreeMap<String, Vec<Fill>>,
+    seen_trades: HashSet<String>,
+    /// Orders whose state is unknown, keyed by label.
+    pending: BTreeMap<String, OrderRequest>,
+}
+
+impl OrderTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `update`, returning an event if it tells us anything new.
+    /// Updates older than the one already held are ignored.
+    pub fn apply_update(&mut self, update: OrderUpdate) -> Option<OrderEvent> {
+        if let Some(known) = self.orders.get(&update.order_id)
+            && (*known == update || !Self::supersedes(&update, known))
+        {
+            return None;
+        }
+
+        if let Some(label) = &update.label {
+            self.pending.remove(label);
+        }
+        self.orders.insert(update.order_id.clone(), update.clone());
+        Some(OrderEvent::Updated(update))
+    }
+
+    /// Whether `update` is newer than `known`. Timestamps are only to the
+    /// millisecond, so a request's response and the matching channel
+    /// update can share one; between those the one with more filled, or
+    /// failing that the one that ended the order, is newer. A finished
+    /// order never becomes live again.
+    fn supersedes(update: &OrderUpdate, known: &OrderUpdate) -> bool {
+        if known.state.is_terminal() && !update.state.is_terminal() {
+            return false;
+        }
+
+        match update.updated.cmp(&known.updated) {
+            Ordering::Greater => true,
+            Ordering::Less => false,
+            Ordering::Equal => match update.filled_amount.total_cmp(&known.filled_amount) {
+                Ordering::Greater => true,
+                Ordering::Less => false,
+                Ordering::Equal => update.state.is_terminal() || !known.state.is_terminal(),
+            },
+        }
+    }
+
+    /// Note that `request` may be live until an update with its label
+    /// arrives. Requests without a label cannot be matched and are not
+    /// held.
+    pub fn add_pending(&mut self, request: OrderRequest) {
+        if let Some(label) = request.label.clone() {
+            self.pending.insert(label, request);
+        }
+    }
+
+    /// Orders sent whose state is not yet known.
+    pub fn pending(&self) -> impl Iterator<Item = &OrderRequest> {
+        self.pending.values()
+    }
+
+    /// Record `fill`, returning an event the first time it is seen.
+    pub fn apply_fill(&mut self, fill: Fill) -> Option<OrderEvent> {
+        if !self.seen_trades.insert(fill.trade_id.clone()) {
+            return None;
+        }
+
+        self.fills
+            .entry(fill.order_id.clone())
+            .or_default()
+            .push(fill.clone());
+        Some(OrderEvent::Filled(fill))
+    }
+
+    pub fn order(&self, order_id: &str) -> Option<&OrderUpdate> {
+        self.orders.get(order_id)
+    }
+
+    pub fn fills(&self, order_id: &str) -> &[Fill] {
+        self.fills.get(order_id).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn open_orders(&self) -> impl Iterator<Item = &OrderUpdate> {
+        self.orders.values().filter(|order| order.state.is_open())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{Fill, OrderEvent, OrderState, OrderTracker, OrderUpdate, Side};
+    use crate::exchange_connectivity::ExchangeType;
+
+    fn update(state: OrderState, filled_amount: f64, updated: u64) -> OrderUpdate {
+        OrderUpdate {
+            exchange: ExchangeType::Deribit,
+            order_id: "1".into(),
+            instrument_name: "BTC_USDT".into(),
+            side: Side::Buy,
+            state,
+            amount: 1.0,
+            filled_amount,
+            price: Some(100.0),
+            average_price: 0.0,
+            label: None,
+            updated: Duration::from_millis(updated),
+        }
+    }
+
+    #[test]
+    fn only_new_information_becomes_an_event() {
+        let mut tracker = OrderTracker::new();
+
+        assert!(
+            tracker
+                .apply_update(update(OrderState::Open, 0.0, 1))
+                .is_some()
+        );
+        assert!(
+            tracker
+                .apply_update(update(OrderState::Open, 0.0, 1))
+                .is_none()
+        );
+        assert!(
+            tracker
+                .apply_update(update(OrderState::Filled, 1.0, 3))
+                .is_some()
+        );
+        assert!(
+            tracker
+                .apply_update(update(OrderState::Open, 0.5, 2))
+                .is_none()
+        );
+        assert_eq!(tracker.order("1").unwrap().state, OrderState::Filled);
+        assert_eq!(tracker.open_orders().count(), 0);
+
+        // Same millisecond: the older, less filled state loses either way
+        // round, and a finished order stays finished.
+        assert!(
+            tracker
+                .apply_update(update(OrderState::Open, 0.5, 3))
+                .is_none()
+        );
+        assert!(
+            tracker
+                .apply_update(update(OrderState::Open, 1.0, 4))
+                .is_none()
+        );
+        assert_eq!(tracker.order("1").unwrap().state, OrderState::Filled);
+
+        let mut tracker = OrderTracker::new();
+        tracker.apply_update(update(OrderState::Open, 0.0, 5));
+        assert!(
+            tracker
+                .apply_update(update(OrderState::Open, 0.4, 5))
+                .is_some()
+        );
+        assert!(
+            tracker
+                .apply_update(update(OrderState::Cancelled, 0.4, 5))
+                .is_some()
+        );
+        assert!(
+            tracker
+                .apply_update(update(OrderState::Open, 0.4, 5))
+                .is_none()
+        );
+
+        let mut tracker = OrderTracker::new();
+
+        let fill = Fill {
+            exchange: ExchangeType::Deribit,
+            trade_id: "t1".into(),
+            order_id: "1".into(),
+            instrument_name: "BTC_USDT".into(),
+            side: Side::Buy,
+            price: 100.0,
+            amount: 1.0,
+            fee: 0.0,
+            fee_currency: "BTC".into(),
+            timestamp: Duration::from_millis(3),
+        };
+        assert_eq!(
+            tracker.apply_fill(fill.clone()),
+            Some(OrderEvent::Filled(fill.clone()))
+        );
+        assert!(tracker.apply_fill(fill).is_none());
+        assert_eq!(tracker.fills("1").len(), 1);
+    }
+}
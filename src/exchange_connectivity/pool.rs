@@ -0,0 +1,448 @@
+//! Several connections to one exchange, sharing its instruments.
+//!
+//! A single socket serialises every request behind one sink and one
+//! stream, so a heavy instrument or a slow handler holds up the rest. An
+//! [`ExchangePool`] opens several connections and gives each instrument
+//! its own shard: book requests for an instrument only go to the
+//! connections it is assigned to. With `redundancy` above one, each
+//! request is sent on several connections and the first answer wins.
+//!
+//! Assignments are kept by a [`ShardMap`], which moves instruments off
+//! connections the watchdog reports as down and evens the load out again
+//! once they have reconnected.
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use futures_util::future::{BoxFuture, select_ok};
+
+use crate::book_management::traded_instruments::Instrument;
+use crate::exchange_connectivity::watchdog::ConnectionHealth;
+use crate::exchange_connectivity::{BidsAsksResult, Exchange, ExchangeKeys, ExchangeType};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// Number of connections to open.
+    pub connections: usize,
+    /// Most instruments a single connection serves.
+    pub max_instruments_per_connection: usize,
+    /// Connections each instrument is requested on; the first answer
+    /// is used and the rest are dropped.
+    pub redundancy: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            connections: 1,
+            max_instruments_per_connection: 16,
+            redundancy: 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Shard {
+    up: bool,
+    instruments: BTreeSet<Instrument>,
+}
+
+/// Which connections serve which instruments.
+#[derive(Debug)]
+pub struct ShardMap {
+    shards: Vec<Shard>,
+    assignments: BTreeMap<Instrument, Vec<usize>>,
+    max_per_connection: usize,
+    redundancy: usize,
+}
+
+impl ShardMap {
+    pub fn new(config: &PoolConfig) -> Self {
+        ShardMap {
+            shards: (0..config.connections)
+                .map(|_| Shard {
+                    up: true,
+                    instruments: BTreeSet::new(),
+                })
+                .collect(),
+            assignments: BTreeMap::new(),
+            max_per_connection: config.max_instruments_per_connection,
+            redundancy: config.redundancy.clamp(1, config.connections.max(1)),
+        }
+    }
+
+    /// Connections serving `instrument`, assigning it to the least loaded
+    /// connections with room if it has none yet.
+    pub fn assign(&mut self, instrument: Instrument) -> Result<Vec<usize>, String> {
+        if let Some(connections) = self.assignments.get(&instrument)
+            && !connections.is_empty()
+        {
+            return Ok(connections.clone());
+        }
+
+        self.assignments.insert(instrument, Vec::new());
+        self.fill(instrument);
+
+        let connections = &self.assignments[&instrument];
+        if connections.is_empty() {
+            self.assignments.remove(&instrument);
+            return Err(format!(
+                "No connection has room for {} (at most {} instruments each)",
+                instrument, self.max_per_connection
+            ));
+        }
+        Ok(connections.clone())
+    }
+
+    pub fn connections_for(&self, instrument: Instrument) -> &[usize] {
+        self.assignments.get(&instrument).map_or(&[], Vec::as_slice)
+    }
+
+    /// Number of instruments served by connection `index`.
+    pub fn load(&self, index: usize) -> usize {
+        self.shards[index].instruments.len()
+    }
+
+    pub fn is_up(&self, index: usize) -> bool {
+        self.shards[index].up
+    }
+
+    /// Record whether connection `index` can serve requests, returning
+    /// whether that changed.
+    pub fn set_up(&mut self, index: usize, up: bool) -> bool {
+        let changed = self.shards[index].up != up;
+        self.shards[index].up = up;
+        changed
+    }
+
+    /// Move instruments off connections that are down and spread them
+    /// evenly over the rest, returning how many assignments changed.
+    pub fn rebalance(&mut self) -> usize {
+        let mut moved = 0;
+
+        for index in 0..self.shards.len() {
+            if self.shards[index].up {
+                continue;
+            }
+            for instrument in std::mem::take(&mut self.shards[index].instruments) {
+                if let Some(connections) = self.assignments.get_mut(&instrument) {
+                    connections.retain(|connection| *connection != index);
+                }
+                moved += 1;
+            }
+        }
+
+        let instruments = self.assignments.keys().copied().collect::<Vec<_>>();
+        for instrument in &instruments {
+            self.fill(*instrument);
+        }
+
+        // Even out connections which came back empty.
+        loop {
+            let up = (0..self.shards.len())
+                .filter(|index| self.shards[*index].up)
+                .collect::<Vec<_>>();
+            let (Some(&busiest), Some(&idlest)) = (
+                up.iter()
+                    .max_by_key(|index| (self.load(**index), usize::MAX - **index)),
+                up.iter().min_by_key(|index| (self.load(**index), **index)),
+            ) else {
+                break;
+            };
+            if self.load(busiest) <= self.load(idlest) + 1 {
+                break;
+            }
+
+            let Some(instrument) = self.shards[busiest]
+                .instruments
+                .iter()
+                .find(|instrument| !self.shards[idlest].instruments.contains(instrument))
+                .copied()
+            else {
+                break;
+            };
+
+            self.shards[busiest].instruments.remove(&instrument);
+            self.shards[idlest].instruments.insert(instrument);
+            for connection in self.assignments.get_mut(&instrument).into_iter().flatten() {
+                if *connection == busiest {
+                    *connection = idlest;
+                }
+            }
+            moved += 1;
+        }
+
+        moved
+    }
+
+    /// Top `instrument` up to `redundancy` connections from those which
+    /// are up and have room, least loaded first.
+    fn fill(&mut self, instrument: Instrument) {
+        let assigned = self.assignments.entry(instrument).or_default();
+        while assigned.len() < self.redundancy {
+            let candidate = (0..self.shards.len())
+                .filter(|index| {
+                    let shard = &self.shards[*index];
+                    shard.up
+                        && shard.instruments.len() < self.max_per_connection
+                        && !assigned.contains(index)
+                })
+                .min_by_key(|index| (self.shards[*index].instruments.len(), *index));
+
+            let Some(index) = candidate else {
+                break;
+            };
+            assigned.push(index);
+            self.shards[index].instruments.insert(instrument);
+        }
+    }
+}
+
+/// Several connections to one exchange with instruments sharded across
+/// them.
+#[derive(Debug)]
+pub struct ExchangePool {
+    exchange: ExchangeType,
+    connections: Vec<Exchange>,
+    shards: Mutex<ShardMap>,
+    /// Reconnect count of each connection when last checked.
+    reconnects: Mutex<Vec<u64>>,
+    duplicates_dropped: AtomicU64,
+}
+
+impl ExchangePool {
+    /// Open `config.connections` connections to `exchange`. If any fails
+    /// to connect, those already open are shut down again.
+    pub async fn connect(
+        exchange: ExchangeType,
+        keys: &ExchangeKeys,
+        config: &PoolConfig,
+    ) -> Option<Self> {
+        let mut connections = Vec::new();
+
+        for _ in 0..config.connections.max(1) {
+            match Exchange::connect(exchange, keys).await {
+                Some((connection, _)) => connections.push(connection),
+                None => {
+                    for connection in &connections {
+                        connection.shutdown_connection().await;
+                    }
+                    return None;
+                }
+            }
+        }
+
+        Some(Self::from_connections(exchange, connections, config))
+    }
+
+    /// Pool already open `connections` to `exchange`.
+    pub fn from_connections(
+        exchange: ExchangeType,
+        connections: Vec<Exchange>,
+        config: &PoolConfig,
+    ) -> Self {
+        assert!(
+            !connections.is_empty(),
+            "A pool needs at least one connection"
+        );
+        let config = PoolConfig {
+            connections: connections.len(),
+            ..*config
+        };
+
+        ExchangePool {
+            exchange,
+            shards: Mutex::new(ShardMap::new(&config)),
+            reconnects: Mutex::new(
+                connections
+                    .iter()
+                    .map(|c| c.monitor().reconnects())
+                    .collect(),
+            ),
+            connections,
+            duplicates_dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn exchange_type(&self) -> ExchangeType {
+        self.exchange
+    }
+
+    pub fn connections(&self) -> &[Exchange] {
+        &self.connections
+    }
+
+    /// Connections currently serving `instrument`.
+    pub fn connections_for(&self, instrument: Instrument) -> Vec<usize> {
+        self.refresh_shards();
+        self.shards
+            .lock()
+            .unwrap()
+            .connections_for(instrument)
+            .to_vec()
+    }
+
+    /// Redundant answers discarded because another connection was first.
+    pub fn duplicates_dropped(&self) -> u64 {
+        self.duplicates_dropped.load(Ordering::Relaxed)
+    }
+
+    /// Book levels for `instrument` from the connections serving it,
+    /// taking whichever answers first.
+    pub async fn pull_bids_asks(&self, depth: u32, instrument: Instrument) -> BidsAsksResult {
+        self.refresh_shards();
+        let assigned = self.shards.lock().unwrap().assign(instrument)?;
+
+        if let [index] = assigned.as_slice() {
+            return self.connections[*index]
+                .pull_from_connection(depth, instrument)
+                .await;
+        }
+
+        let requests = assigned
+            .iter()
+            .map(|index| -> BoxFuture<'_, BidsAsksResult> {
+                Box::pin(self.connections[*index].pull_from_connection(depth, instrument))
+            })
+            .collect::<Vec<_>>();
+        let (levels, pending) = select_ok(requests).await?;
+
+        // Dropping the slower requests discards their answers.
+        self.duplicates_dropped
+            .fetch_add(pending.len() as u64, Ordering::Relaxed);
+        Ok(levels)
+    }
+
+    /// Shut every connection in the pool down.
+    pub async fn shutdown(&self) {
+        for connection in &self.connections {
+            connection.shutdown_connection().await;
+        }
+    }
+
+    /// Bring the shard map in line with the watchdog's view of each
+    /// connection, rebalancing if any went down or reconnected.
+    fn refresh_shards(&self) {
+        let mut shards = self.shards.lock().unwrap();
+        let mut reconnects = self.reconnects.lock().unwrap();
+        let mut changed = false;
+
+        for (index, connection) in self.connections.iter().enumerate() {
+            let up = matches!(
+                connection.connection_health(),
+                ConnectionHealth::Healthy | ConnectionHealth::Probing
+            );
+            changed |= shards.set_up(index, up);
+
+            let count = connection.monitor().reconnects();
+            changed |= reconnects[index] != count;
+            reconnects[index] = count;
+        }
+
+        if changed {
+            let moved = shards.rebalance();
+            log::info!(
+                "Rebalanced {:?} connection pool, {} assignments moved.",
+                self.exchange,
+                moved
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use serde_json::Value;
+
+    use super::{ExchangePool, PoolConfig, ShardMap};
+    use crate::book_management::traded_instruments::Instrument;
+    use crate::exchange_connectivity::mock_server::{MockServer, connect_deribit, deribit_fixture};
+    use crate::exchange_connectivity::{Exchange, ExchangeType};
+
+    #[test]
+    fn spreads_instruments_within_caps_and_rebalances() {
+        let config = PoolConfig {
+            connections: 3,
+            max_instruments_per_connection: 1,
+            redundancy: 1,
+        };
+        let mut shards = ShardMap::new(&config);
+
+        assert_eq!(shards.assign(Instrument::BtcUsdt).unwrap(), [0]);
+        assert_eq!(shards.assign(Instrument::EthUsdc).unwrap(), [1]);
+        assert_eq!(shards.assign(Instrument::BtcUsdt).unwrap(), [0]);
+
+        // Connection 1 drops: its instrument moves to the spare.
+        shards.set_up(1, false);
+        assert_eq!(shards.rebalance(), 1);
+        assert_eq!(shards.connections_for(Instrument::EthUsdc), [2]);
+
+        // Every live connection is at its cap.
+        assert!(shards.assign(Instrument::EthBtc).is_err());
+        shards.set_up(1, true);
+        shards.rebalance();
+        assert_eq!(shards.assign(Instrument::EthBtc).unwrap(), [1]);
+    }
+
+    #[test]
+    fn redundant_assignments_use_distinct_connections() {
+        let config = PoolConfig {
+            connections: 3,
+            max_instruments_per_connection: 4,
+            redundancy: 2,
+        };
+        let mut shards = ShardMap::new(&config);
+
+        assert_eq!(shards.assign(Instrument::BtcUsdt).unwrap(), [0, 1]);
+        assert_eq!(shards.assign(Instrument::EthUsdc).unwrap(), [2, 0]);
+        assert_eq!(shards.assign(Instrument::EthBtc).unwrap(), [1, 2]);
+
+        shards.set_up(0, false);
+        shards.rebalance();
+        for instrument in [Instrument::BtcUsdt, Instrument::EthUsdc] {
+            assert!(!shards.connections_for(instrument).contains(&0));
+        }
+
+        // Once back, the emptied connection takes load off the others.
+        shards.set_up(0, true);
+        assert!(shards.rebalance() > 0);
+        assert!(shards.load(0) >= 1);
+        assert!((0..3).all(|index| shards.load(index) <= 2));
+    }
+
+    #[tokio::test]
+    async fn first_answer_wins_and_stale_connections_are_skipped() {
+        let answering = MockServer::start(deribit_fixture).await;
+        let silent = MockServer::start(|request: &Value| match request["method"].as_str() {
+            Some("public/get_order_book") => Vec::new(),
+            _ => deribit_fixture(request),
+        })
+        .await;
+
+        let first = connect_deribit(&silent).await;
+        let second = connect_deribit(&answering).await;
+        let pool = ExchangePool::from_connections(
+            ExchangeType::Deribit,
+            vec![
+                Exchange::Deribit(Arc::clone(&first)),
+                Exchange::Deribit(Arc::clone(&second)),
+            ],
+            &PoolConfig {
+                connections: 2,
+                max_instruments_per_connection: 4,
+                redundancy: 2,
+            },
+        );
+
+        let (bids, asks, _) = pool.pull_bids_asks(10, Instrument::BtcUsdt).await.unwrap();
+        assert_eq!((bids.len(), asks.len()), (2, 2));
+        assert_eq!(pool.duplicates_dropped(), 1);
+
+        first.monitor.mark_stale();
+        assert_eq!(pool.connections_for(Instrument::BtcUsdt), [1]);
+        pool.pull_bids_asks(10, Instrument::BtcUsdt).await.unwrap();
+        assert_eq!(silent.requests("public/get_order_book").len(), 1);
+    }
+}
@@ -0,0 +1,188 @@
+//! Structured shutdown for long-running tasks.
+//!
+//! Every task a connection (or the GUI) spawns is handed a clone of a
+//! [`CancellationToken`] and registered in a [`TaskGroup`]. Shutting the
+//! group down cancels the token, which every task watches between units
+//! of work, and then waits for each task to return.
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+/// Longest a task group waits for a single task before aborting it.
+const JOIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    notify: Notify,
+    children: Mutex<Vec<CancellationToken>>,
+}
+
+/// A cheaply cloneable signal that work should stop.
+///
+/// Cancelling a token also cancels every token created from it with
+/// [`child_token`](Self::child_token).
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    state: Arc<TokenState>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token cancelled along with this one, but which can also be
+    /// cancelled on its own.
+    pub fn child_token(&self) -> CancellationToken {
+        let child = CancellationToken::new();
+        let mut children = self.state.children.lock().unwrap();
+        if self.is_cancelled() {
+            child.cancel();
+        } else {
+            children.push(child.clone());
+        }
+        child
+    }
+
+    pub fn cancel(&self) {
+        if self.state.cancelled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        self.state.notify.notify_waiters();
+
+        let children = std::mem::take(&mut *self.state.children.lock().unwrap());
+        for child in children {
+            child.cancel();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Resolves once the token is cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.state.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Sleep for `duration`, returning `false` early if cancelled first.
+    pub async fn sleep(&self, duration: Duration) -> bool {
+        tokio::select! {
+            _ = self.cancelled() => false,
+            _ = tokio::time::sleep(duration) => true,
+        }
+    }
+}
+
+/// Tasks sharing a [`CancellationToken`], joined together on shutdown.
+#[derive(Debug, Default)]
+pub struct TaskGroup {
+    token: CancellationToken,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl TaskGroup {
+    pub fn new(token: CancellationToken) -> Self {
+        TaskGroup {
+            token,
+            handles: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The token every task in the group should watch.
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    pub fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        let handle = tokio::spawn(task);
+        self.handles.lock().unwrap().push(handle);
+    }
+
+    /// Number of spawned tasks not yet joined.
+    pub fn len(&self) -> usize {
+        self.handles.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Cancel the group's token and wait for every task to finish.
+    /// Tasks which ignore the token for too long are aborted.
+    pub async fn shutdown(&self) {
+        self.token.cancel();
+        self.join().await;
+    }
+
+    /// Wait for every task spawned so far, including any spawned while
+    /// waiting.
+    pub async fn join(&self) {
+        loop {
+            let handles = std::mem::take(&mut *self.handles.lock().unwrap());
+            if handles.is_empty() {
+                return;
+            }
+
+            for mut handle in handles {
+                match tokio::time::timeout(JOIN_TIMEOUT, &mut handle).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) if err.is_cancelled() => {}
+                    Ok(Err(err)) => log::error!("Task panicked before shutdown: {}", err),
+                    Err(_) => {
+                        log::warn!("Task ignored shutdown for {:?}, aborting.", JOIN_TIMEOUT);
+                        handle.abort();
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    use super::{CancellationToken, TaskGroup};
+
+    #[tokio::test]
+    async fn cancelling_a_parent_stops_every_task() {
+        let parent = CancellationToken::new();
+        let group = TaskGroup::new(parent.child_token());
+        let stopped = Arc::new(AtomicU32::new(0));
+
+        for _ in 0..3 {
+            let token = group.token().clone();
+            let stopped = Arc::clone(&stopped);
+            group.spawn(async move {
+                while token.sleep(Duration::from_millis(10)).await {}
+                stopped.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        assert_eq!(group.len(), 3);
+
+        parent.cancel();
+        assert!(group.token().is_cancelled());
+        group.join().await;
+
+        assert_eq!(stopped.load(Ordering::Relaxed), 3);
+        assert!(group.is_empty());
+
+        // Children of a cancelled token start out cancelled.
+        assert!(parent.child_token().is_cancelled());
+        parent.child_token().cancelled().await;
+    }
+}
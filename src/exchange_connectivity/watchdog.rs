@@ -0,0 +1,292 @@
+//! Detection of silently stalled connections.
+//!
+//! Every connection records when it last read a frame and when it last
+//! saw a heartbeat (a Deribit `heartbeat`, a Binance ping, or an answered
+//! probe) in a [`ConnectionMonitor`]. A watchdog task checks the monitor
+//! periodically: once the connection has been quiet for too long it sends
+//! an active probe, and if nothing comes back before the probe times out
+//! the connection is marked stale and reconnected.
+use std::error::Error;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::exchange_connectivity::clock;
+use crate::exchange_connectivity::shutdown::CancellationToken;
+
+/// How a connection is doing, as far as the watchdog can tell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionHealth {
+    Healthy,
+    /// Quiet for too long; a probe is waiting for an answer.
+    Probing,
+    /// The probe went unanswered or the socket closed.
+    Stale,
+    Reconnecting,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WatchdogConfig {
+    /// How often the monitor is checked.
+    pub check_interval: Duration,
+    /// Inbound silence after which the connection is probed.
+    pub probe_after: Duration,
+    /// Time since the last heartbeat after which the connection is
+    /// probed, for venues that promise regular heartbeats.
+    pub heartbeat_timeout: Option<Duration>,
+    /// How long a probe may go unanswered before reconnecting.
+    pub probe_timeout: Duration,
+}
+
+impl WatchdogConfig {
+    /// Thresholds for a venue heartbeating every `interval`.
+    pub fn for_heartbeat(interval: Duration) -> Self {
+        WatchdogConfig {
+            check_interval: Duration::from_secs(1),
+            probe_after: interval * 3 / 2,
+            heartbeat_timeout: Some(interval * 5 / 2),
+            probe_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// What the watchdog should do after a check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchdogAction {
+    Wait,
+    Probe,
+    Reconnect,
+}
+
+#[derive(Debug)]
+struct MonitorState {
+    health: ConnectionHealth,
+    last_inbound: Duration,
+    last_heartbeat: Duration,
+    probe_sent: Option<Duration>,
+    reconnects: u64,
+}
+
+/// Liveness tracking for one connection.
+#[derive(Debug)]
+pub struct ConnectionMonitor {
+    state: Mutex<MonitorState>,
+}
+
+impl Default for ConnectionMonitor {
+    fn default() -> Self {
+        Self::new(clock::now())
+    }
+}
+
+impl ConnectionMonitor {
+    /// A healthy connection established at `now`.
+    pub fn new(now: Duration) -> Self {
+        ConnectionMonitor {
+            state: Mutex::new(MonitorState {
+                health: ConnectionHealth::Healthy,
+                last_inbound: now,
+                last_heartbeat: now,
+                probe_sent: None,
+                reconnects: 0,
+            }),
+        }
+    }
+
+    pub fn record_inbound(&self, at: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.last_inbound = state.last_inbound.max(at);
+    }
+
+    pub fn record_heartbeat(&self, at: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.last_inbound = state.last_inbound.max(at);
+        state.last_heartbeat = state.last_heartbeat.max(at);
+    }
+
+    pub fn record_probe(&self, at: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.probe_sent = Some(at);
+        state.health = ConnectionHealth::Probing;
+    }
+
+    /// The socket closed or failed under us.
+    pub fn mark_stale(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.probe_sent = None;
+        state.health = ConnectionHealth::Stale;
+    }
+
+    pub fn mark_reconnecting(&self) {
+        self.state.lock().unwrap().health = ConnectionHealth::Reconnecting;
+    }
+
+    /// A fresh connection replaced the stale one at `now`.
+    pub fn record_reconnect(&self, now: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.health = ConnectionHealth::Healthy;
+        state.last_inbound = now;
+        state.last_heartbeat = now;
+        state.probe_sent = None;
+        state.reconnects += 1;
+    }
+
+    pub fn health(&self) -> ConnectionHealth {
+        self.state.lock().unwrap().health
+    }
+
+    pub fn last_inbound(&self) -> Duration {
+        self.state.lock().unwrap().last_inbound
+    }
+
+    pub fn last_heartbeat(&self) -> Duration {
+        self.state.lock().unwrap().last_heartbeat
+    }
+
+    /// Number of times the connection has been replaced.
+    pub fn reconnects(&self) -> u64 {
+        self.state.lock().unwrap().reconnects
+    }
+
+    /// Work out what to do at `now`.
+    pub fn check(&self, now: Duration, config: &WatchdogConfig) -> WatchdogAction {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(sent) = state.probe_sent {
+            if state.last_inbound >= sent {
+                // Anything read after the probe means the line is alive.
+                state.probe_sent = None;
+                state.last_heartbeat = state.last_inbound;
+                state.health = ConnectionHealth::Healthy;
+            } else if now.saturating_sub(sent) >= config.probe_timeout {
+                state.probe_sent = None;
+                state.health = ConnectionHealth::Stale;
+                return WatchdogAction::Reconnect;
+            } else {
+                return WatchdogAction::Wait;
+            }
+        }
+
+        if matches!(
+            state.health,
+            ConnectionHealth::Stale | ConnectionHealth::Reconnecting
+        ) {
+            return WatchdogAction::Reconnect;
+        }
+
+        let quiet = now.saturating_sub(state.last_inbound) >= config.probe_after;
+        let no_heartbeat = config
+            .heartbeat_timeout
+            .is_some_and(|timeout| now.saturating_sub(state.last_heartbeat) >= timeout);
+        if quiet || no_heartbeat {
+            WatchdogAction::Probe
+        } else {
+            WatchdogAction::Wait
+        }
+    }
+}
+
+/// A connection the watchdog can probe and replace.
+pub(crate) trait Watched {
+    const NAME: &'static str;
+
+    fn monitor(&self) -> &ConnectionMonitor;
+
+    /// Send something the venue must answer.
+    fn probe(&self) -> impl Future<Output = Result<(), Box<dyn Error + Send + Sync>>> + Send;
+
+    /// Replace the socket and restore the session on the new one.
+    fn reconnect(&self) -> impl Future<Output = Result<(), Box<dyn Error + Send + Sync>>> + Send;
+}
+
+/// Check `connection` every `config.check_interval` until `shutdown` is
+/// cancelled, probing and reconnecting as needed.
+pub(crate) async fn run_watchdog<W: Watched>(
+    connection: &W,
+    shutdown: &CancellationToken,
+    config: WatchdogConfig,
+) {
+    while shutdown.sleep(config.check_interval).await {
+        match connection.monitor().check(clock::now(), &config) {
+            WatchdogAction::Wait => {}
+            WatchdogAction::Probe => {
+                log::warn!(
+                    "{} connection quiet since {:?}, probing.",
+                    W::NAME,
+                    connection.monitor().last_inbound()
+                );
+                connection.monitor().record_probe(clock::now());
+                if let Err(err) = connection.probe().await {
+                    log::error!("Failed to probe {} connection: {}", W::NAME, err);
+                    connection.monitor().mark_stale();
+                }
+            }
+            WatchdogAction::Reconnect => {
+                log::warn!("{} connection is stale, reconnecting.", W::NAME);
+                connection.monitor().mark_reconnecting();
+                match connection.reconnect().await {
+                    Ok(()) => {
+                        connection.monitor().record_reconnect(clock::now());
+                        log::info!("Reconnected to {}.", W::NAME);
+                    }
+                    Err(err) => {
+                        log::error!("Failed to reconnect to {}: {}", W::NAME, err);
+                        connection.monitor().mark_stale();
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{ConnectionHealth, ConnectionMonitor, WatchdogAction, WatchdogConfig};
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn probes_then_reconnects_a_silent_connection() {
+        let config = WatchdogConfig::for_heartbeat(secs(30));
+        let monitor = ConnectionMonitor::new(secs(0));
+
+        assert_eq!(monitor.check(secs(44), &config), WatchdogAction::Wait);
+        assert_eq!(monitor.check(secs(45), &config), WatchdogAction::Probe);
+
+        monitor.record_probe(secs(45));
+        assert_eq!(monitor.health(), ConnectionHealth::Probing);
+        assert_eq!(monitor.check(secs(50), &config), WatchdogAction::Wait);
+        assert_eq!(monitor.check(secs(55), &config), WatchdogAction::Reconnect);
+        assert_eq!(monitor.health(), ConnectionHealth::Stale);
+
+        monitor.record_reconnect(secs(56));
+        assert_eq!(monitor.health(), ConnectionHealth::Healthy);
+        assert_eq!(monitor.reconnects(), 1);
+        assert_eq!(monitor.check(secs(57), &config), WatchdogAction::Wait);
+    }
+
+    #[test]
+    fn answered_probes_and_missing_heartbeats() {
+        let config = WatchdogConfig::for_heartbeat(secs(30));
+        let monitor = ConnectionMonitor::new(secs(0));
+
+        // Data keeps flowing but heartbeats stopped.
+        for t in (10..=75).step_by(10) {
+            monitor.record_inbound(secs(t));
+        }
+        assert_eq!(monitor.check(secs(75), &config), WatchdogAction::Probe);
+
+        monitor.record_probe(secs(75));
+        monitor.record_inbound(secs(76));
+        assert_eq!(monitor.check(secs(77), &config), WatchdogAction::Wait);
+        assert_eq!(monitor.health(), ConnectionHealth::Healthy);
+        assert_eq!(monitor.last_heartbeat(), secs(76));
+
+        monitor.mark_stale();
+        assert_eq!(monitor.check(secs(78), &config), WatchdogAction::Reconnect);
+    }
+}
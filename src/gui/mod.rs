@@ -1,36 +1,65 @@
 use crate::book_management::AggregatedOrderBook;
+use crate::book_management::analytics::Imbalance;
 use crate::book_management::multibook::Multibook;
+use crate::book_management::snapshot::BookSnapshot;
+use crate::book_management::venue_book::VenueFreshness;
+use crate::exchange_connectivity::ExchangeType;
+use crate::exchange_connectivity::clock::{self, FeedLatency};
+use crate::exchange_connectivity::shutdown::TaskGroup;
 use chrono::TimeZone;
 use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::time::Duration;
 
 use std::sync::Mutex;
-use tokio::task;
 
 pub struct MyApp {
     books: Multibook,
     book_properties: BTreeMap<u32, Arc<AppBookProperties>>,
 }
 
+/// What is shown of one book snapshot. Built from a single snapshot and
+/// replaced as a whole, so a frame never mixes two updates.
+struct BookView {
+    pretty_output: String,
+    curr_time: Duration,
+    imbalance: Option<f64>,
+    venue_imbalances: BTreeMap<ExchangeType, f64>,
+    venue_freshness: BTreeMap<ExchangeType, VenueFreshness>,
+}
+
+impl BookView {
+    fn new(snapshot: &BookSnapshot, stale_after: Duration) -> Self {
+        let imbalance = Imbalance::default();
+        BookView {
+            pretty_output: snapshot
+                .pretty_print()
+                .unwrap_or_else(|e| format!("Error: {e}")),
+            curr_time: snapshot.last_time(),
+            imbalance: snapshot.imbalance(&imbalance),
+            venue_imbalances: snapshot.venue_imbalances(&imbalance),
+            venue_freshness: snapshot.venue_freshness(clock::now(), stale_after),
+        }
+    }
+}
+
 struct AppBookProperties {
-    pretty_output: Arc<Mutex<Option<String>>>,
-    curr_time: Arc<Mutex<Duration>>,
-    imbalance: Arc<Mutex<f64>>,
+    view: Arc<Mutex<Option<BookView>>>,
+    feed_latency: Arc<Mutex<BTreeMap<ExchangeType, FeedLatency>>>,
 }
 
 impl Default for AppBookProperties {
     fn default() -> Self {
         AppBookProperties {
-            pretty_output: Arc::new(Mutex::new(None)),
-            curr_time: Arc::new(Mutex::new(Duration::from_secs(0))),
-            imbalance: Arc::new(Mutex::new(1.0)),
+            view: Arc::new(Mutex::new(None)),
+            feed_latency: Arc::new(Mutex::new(BTreeMap::new())),
         }
     }
 }
 
 impl MyApp {
-    pub fn new(books: impl Iterator<Item = Arc<AggregatedOrderBook>>) -> Self {
+    /// Display `books`, refreshing each in a task spawned on `tasks`.
+    pub fn new(books: impl Iterator<Item = Arc<AggregatedOrderBook>>, tasks: &TaskGroup) -> Self {
         let mut book_properties = BTreeMap::new();
         let mut app = Self {
             books: {
@@ -43,19 +72,19 @@ impl MyApp {
 
                 multibook
             },
-            book_properties: book_properties,
+            book_properties,
         };
 
-        app.create_refresh_tasks();
+        app.create_refresh_tasks(tasks);
 
         app
     }
 
-    fn create_refresh_tasks(&mut self) {
+    fn create_refresh_tasks(&mut self, tasks: &TaskGroup) {
         let key_book = self.books.subscribed.iter();
 
         for (key, book) in key_book {
-            let key = key.clone();
+            let key = *key;
             let book = Arc::clone(book);
             let property = self.book_properties.get(&key);
 
@@ -64,9 +93,10 @@ impl MyApp {
             }
 
             let property = Arc::clone(property.unwrap());
+            let shutdown = tasks.token().clone();
 
-            task::spawn(async move {
-                loop {
+            tasks.spawn(async move {
+                while !shutdown.is_cancelled() {
                     if let Err(err) = book.update_state().await {
                         log::error!(
                             "Error during book state update in refresh app task: {}",
@@ -74,16 +104,11 @@ impl MyApp {
                         );
                     }
 
-                    let output = book
-                        .pretty_print()
-                        .await
-                        .unwrap_or_else(|e| format!("Error: {e}"));
-
-                    *property.pretty_output.lock().unwrap() = Some(output);
-                    *property.curr_time.lock().unwrap() = book.last_time().await;
-                    *property.imbalance.lock().unwrap() = book.imbalance().await;
+                    let view = BookView::new(&book.snapshot(), book.stale_after());
+                    *property.view.lock().unwrap() = Some(view);
+                    *property.feed_latency.lock().unwrap() = book.feed_latency();
 
-                    tokio::time::sleep(Duration::from_millis(300)).await;
+                    shutdown.sleep(Duration::from_millis(300)).await;
                 }
             });
         }
@@ -94,29 +119,70 @@ impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.horizontal(|ui| {
-                for (_, property) in &self.book_properties {
+                for property in self.book_properties.values() {
                     let property = Arc::clone(property);
 
                     ui.vertical(|ui| {
                         ui.heading("market-aggregator");
-                        let curr_time = *property.curr_time.lock().unwrap();
-                        ui.horizontal(|ui| {
-                            ui.label(format!("Last msg recv: {:#?}", {
-                                let secs = curr_time.as_secs() as i64;
-                                let nsecs = curr_time.subsec_nanos() as u32;
-
-                                let datetime = chrono::Utc.timestamp_opt(secs, nsecs).unwrap();
+                        let view = property.view.lock().unwrap();
+
+                        if let Some(view) = &*view {
+                            ui.horizontal(|ui| {
+                                ui.label(format!("Last msg recv: {:#?}", {
+                                    let secs = view.curr_time.as_secs() as i64;
+                                    let nsecs = view.curr_time.subsec_nanos();
+
+                                    let datetime = chrono::Utc.timestamp_opt(secs, nsecs).unwrap();
+
+                                    datetime.format("%Y-%m-%d %H:%M:%S%.3f %Z").to_string()
+                                }));
+                                let venues = view
+                                    .venue_imbalances
+                                    .iter()
+                                    .map(|(exchange, imbalance)| {
+                                        format!("{:?} {:+.3}", exchange, imbalance)
+                                    })
+                                    .collect::<Vec<_>>()
+                                    .join(", ");
+                                ui.label(format!(
+                                    "Imbalance: {} ({})",
+                                    view.imbalance.map_or("n/a".to_string(), |imbalance| {
+                                        format!("{:+.3}", imbalance)
+                                    }),
+                                    venues
+                                ))
+                            });
+                        }
 
-                                datetime.format("%Y-%m-%d %H:%M:%S%.3f %Z").to_string()
-                            }));
+                        ui.label(format!(
+                            "Feed latency: {}",
+                            property
+                                .feed_latency
+                                .lock()
+                                .unwrap()
+                                .iter()
+                                .map(|(exchange, latency)| format!(
+                                    "{:?} {:.1}ms",
+                                    exchange,
+                                    latency.average.as_secs_f64() * 1000.0
+                                ))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ));
+
+                        if let Some(view) = &*view {
                             ui.label(format!(
-                                "Imbalance l/r: {}",
-                                property.imbalance.lock().unwrap()
-                            ))
-                        });
-
-                        if let Some(output) = &*property.pretty_output.lock().unwrap() {
-                            ui.monospace(output);
+                                "Venues: {}",
+                                view.venue_freshness
+                                    .iter()
+                                    .map(|(exchange, freshness)| format!(
+                                        "{:?} {:?}",
+                                        exchange, freshness
+                                    ))
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            ));
+                            ui.monospace(&view.pretty_output);
                         } else {
                             ui.label("Loading...");
                         }
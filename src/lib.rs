@@ -1,7 +1,9 @@
-#![feature(let_chains)]
+pub mod aggregator;
 pub mod book_management;
 pub mod exchange_connectivity;
 pub mod gui;
+pub mod order_routing;
+pub mod paper_trading;
 pub mod time_series_array;
 
 use colored::Colorize;
@@ -57,6 +59,6 @@ mod test {
         }
 
         // uncomment and run cargo test if binance api doesnt work.
-        // panic!("{}", aggregated.pretty_print().await.unwrap());
+        // panic!("{}", aggregated.pretty_print().unwrap());
     }
 }
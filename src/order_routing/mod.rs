@@ -0,0 +1,501 @@
+//! Smart order routing across the venues of an aggregated book.
+//!
+//! A parent order is split into at most one child order per venue so that
+//! the all-in cost of the whole order (price plus taker fees) is as low as
+//! possible, with fees taken from a [`FeeSchedule`] where it covers the
+//! venue and from its [`VenueRules`] otherwise. Plans are worked out
+//! against a snapshot of the consolidated book, so they can be inspected
+//! without trading, and each venue's child orders can then be sent through
+//! that venue's [`OrderGateway`].
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::book_management::fees::{FeeSchedule, Liquidity};
+use crate::book_management::traded_instruments::Instrument;
+use crate::book_management::{AggregatedOrderBook, Ask, Bid, Order};
+use crate::exchange_connectivity::ExchangeType;
+use crate::exchange_connectivity::orders::{
+    OrderGateway, OrderRequest, OrderResult, Side, TimeInForce,
+};
+
+/// Trading rules of one venue.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct VenueRules {
+    /// Taker fee as a fraction of notional, e.g. `0.0005` for 5bps. Only
+    /// used if the router's fee schedule has no tiers for the venue.
+    pub taker_fee: f64,
+    /// Smallest order the venue accepts, in the base currency.
+    pub min_size: f64,
+}
+
+/// The part of a parent order routed to one venue.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChildOrder {
+    pub exchange: ExchangeType,
+    pub instrument: Instrument,
+    pub side: Side,
+    pub amount: f64,
+    /// Worst price the child has to reach to fill in full.
+    pub limit_price: f64,
+    /// Expected average fill price, before fees.
+    pub average_price: f64,
+    /// Expected taker fee, in the quote currency.
+    pub fee: f64,
+}
+
+impl ChildOrder {
+    /// The child as an immediate-or-cancel limit order at its worst price.
+    pub fn to_request(&self) -> OrderRequest {
+        OrderRequest::limit(self.instrument, self.side, self.amount, self.limit_price)
+            .with_time_in_force(TimeInForce::ImmediateOrCancel)
+    }
+
+    pub fn notional(&self) -> f64 {
+        self.amount * self.average_price
+    }
+}
+
+/// How a parent order would be split given the book at planning time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoutePlan {
+    pub instrument: Instrument,
+    pub side: Side,
+    pub size: f64,
+    pub limit: f64,
+    pub children: Vec<ChildOrder>,
+}
+
+impl RoutePlan {
+    pub fn filled(&self) -> f64 {
+        self.children.iter().map(|child| child.amount).sum()
+    }
+
+    /// Size the book could not take within the limit.
+    pub fn unfilled(&self) -> f64 {
+        (self.size - self.filled()).max(0.0)
+    }
+
+    pub fn notional(&self) -> f64 {
+        self.children.iter().map(ChildOrder::notional).sum()
+    }
+
+    pub fn fees(&self) -> f64 {
+        self.children.iter().map(|child| child.fee).sum()
+    }
+
+    /// Average price per unit once fees are included, if anything fills.
+    pub fn all_in_price(&self) -> Option<f64> {
+        let filled = self.filled();
+        if filled <= 0.0 {
+            return None;
+        }
+
+        let all_in = match self.side {
+            Side::Buy => self.notional() + self.fees(),
+            Side::Sell => self.notional() - self.fees(),
+        };
+        Some(all_in / filled)
+    }
+
+    pub fn children_for(&self, exchange: ExchangeType) -> impl Iterator<Item = &ChildOrder> {
+        self.children
+            .iter()
+            .filter(move |child| child.exchange == exchange)
+    }
+}
+
+/// A book level with the per-unit cost of taking it, fees included.
+struct Level {
+    exchange: ExchangeType,
+    price: f64,
+    quantity: f64,
+    all_in: f64,
+}
+
+fn total_quantity(fills: &[(f64, f64)]) -> f64 {
+    fills.iter().map(|(_, quantity)| quantity).sum()
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SmartOrderRouter {
+    venues: BTreeMap<ExchangeType, VenueRules>,
+    fees: FeeSchedule,
+}
+
+impl SmartOrderRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_venue(mut self, exchange: ExchangeType, rules: VenueRules) -> Self {
+        self.venues.insert(exchange, rules);
+        self
+    }
+
+    /// Tiered fees, taking precedence over each venue's flat taker fee.
+    pub fn with_fees(mut self, fees: FeeSchedule) -> Self {
+        self.fees = fees;
+        self
+    }
+
+    /// Rules of `exchange`, no fees and no minimum if none were given.
+    pub fn rules(&self, exchange: ExchangeType) -> VenueRules {
+        self.venues.get(&exchange).copied().unwrap_or_default()
+    }
+
+    /// Taker fee paid on `instrument` at `exchange`.
+    pub fn taker_fee(&self, exchange: ExchangeType, instrument: Instrument) -> f64 {
+        match self.fees.tier(exchange, instrument) {
+            Some(tier) => tier.rate(Liquidity::Taker),
+            None => self.rules(exchange).taker_fee,
+        }
+    }
+
+    /// Plan a parent order against the current state of `book`.
+    pub fn plan(&self, book: &AggregatedOrderBook, side: Side, size: f64, limit: f64) -> RoutePlan {
+        let (bids, asks) = book.levels();
+        self.plan_against(book.instrument(), &bids, &asks, side, size, limit)
+    }
+
+    /// Plan a parent order against the given levels. Buys take asks up
+    /// to `limit` and sells take bids down to it.
+    ///
+    /// Levels are taken cheapest all-in first. A venue whose share would
+    /// fall below its minimum size is dropped and its share re-routed.
+    pub fn plan_against(
+        &self,
+        instrument: Instrument,
+        bids: &[Bid],
+        asks: &[Ask],
+        side: Side,
+        size: f64,
+        limit: f64,
+    ) -> RoutePlan {
+        let levels = match side {
+            Side::Buy => self.levels(instrument, asks, side, limit),
+            Side::Sell => self.levels(instrument, bids, side, limit),
+        };
+
+        let mut excluded = BTreeSet::new();
+        let taken = loop {
+            let taken = Self::allocate(&levels, size, &excluded);
+            let too_small = taken
+                .iter()
+                .map(|(exchange, fills)| (*exchange, total_quantity(fills)))
+                .filter(|(exchange, amount)| *amount < self.rules(*exchange).min_size)
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+
+            match too_small {
+                Some((exchange, _)) => {
+                    excluded.insert(exchange);
+                }
+                None => break taken,
+            }
+        };
+
+        let children = taken
+            .into_iter()
+            .map(|(exchange, fills)| {
+                let amount = total_quantity(&fills);
+                let notional: f64 = fills.iter().map(|(price, qty)| price * qty).sum();
+                let worst = fills.iter().map(|(price, _)| *price);
+                let limit_price = match side {
+                    Side::Buy => worst.fold(f64::MIN, f64::max),
+                    Side::Sell => worst.fold(f64::MAX, f64::min),
+                };
+
+                ChildOrder {
+                    exchange,
+                    instrument,
+                    side,
+                    amount,
+                    limit_price,
+                    average_price: notional / amount,
+                    fee: notional * self.taker_fee(exchange, instrument),
+                }
+            })
+            .collect();
+
+        RoutePlan {
+            instrument,
+            side,
+            size,
+            limit,
+            children,
+        }
+    }
+
+    fn levels<T: Order>(
+        &self,
+        instrument: Instrument,
+        orders: &[T],
+        side: Side,
+        limit: f64,
+    ) -> Vec<Level> {
+        let mut levels = orders
+            .iter()
+            .filter(|order| match side {
+                Side::Buy => order.price() <= limit,
+                Side::Sell => order.price() >= limit,
+            })
+            .filter(|order| order.quantity() > 0.0)
+            .map(|order| {
+                let fee = self.taker_fee(order.exchange(), instrument);
+                Level {
+                    exchange: order.exchange(),
+                    price: order.price(),
+                    quantity: order.quantity(),
+                    all_in: match side {
+                        Side::Buy => order.price() * (1.0 + fee),
+                        Side::Sell => order.price() * (1.0 - fee),
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+
+        match side {
+            Side::Buy => levels.sort_by(|a, b| a.all_in.total_cmp(&b.all_in)),
+            Side::Sell => levels.sort_by(|a, b| b.all_in.total_cmp(&a.all_in)),
+        }
+        levels
+    }
+
+    /// Take `size` from `levels` in order, skipping `excluded` venues.
+    /// Returns the (price, quantity) taken on each venue.
+    fn allocate(
+        levels: &[Level],
+        size: f64,
+        excluded: &BTreeSet<ExchangeType>,
+    ) -> BTreeMap<ExchangeType, Vec<(f64, f64)>> {
+        let mut remaining = size;
+        let mut taken: BTreeMap<ExchangeType, Vec<(f64, f64)>> = BTreeMap::new();
+
+        for level in levels {
+            if remaining <= 0.0 {
+                break;
+            }
+            if excluded.contains(&level.exchange) {
+                continue;
+            }
+
+            let quantity = level.quantity.min(remaining);
+            taken
+                .entry(level.exchange)
+                .or_default()
+                .push((level.price, quantity));
+            remaining -= quantity;
+        }
+
+        taken
+    }
+
+    /// Send the child orders of `plan` routed to `exchange` through
+    /// `gateway`, returning the result of each.
+    pub async fn execute<G: OrderGateway>(
+        plan: &RoutePlan,
+        exchange: ExchangeType,
+        gateway: &G,
+    ) -> Vec<OrderResult> {
+        let mut results = Vec::new();
+        for child in plan.children_for(exchange) {
+            log::info!("Routing {:?} to {:?}", child, exchange);
+            results.push(gateway.place_order(child.to_request()).await);
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use tokio::sync::broadcast;
+
+    use super::{SmartOrderRouter, VenueRules};
+    use crate::book_management::fees::{FeeSchedule, FeeTier};
+    use crate::book_management::traded_instruments::Instrument;
+    use crate::book_management::{Ask, Bid, Order};
+    use crate::exchange_connectivity::ExchangeType;
+    use crate::exchange_connectivity::orders::{
+        OrderAck, OrderEvent, OrderGateway, OrderRequest, OrderResult, OrderState, OrderType,
+        OrderUpdate, Side, TimeInForce,
+    };
+
+    fn ask(exchange: ExchangeType, price: f64, quantity: f64) -> Ask {
+        Ask::new(Instrument::BtcUsdt, exchange, quantity, price)
+    }
+
+    fn bid(exchange: ExchangeType, price: f64, quantity: f64) -> Bid {
+        Bid::new(Instrument::BtcUsdt, exchange, quantity, price)
+    }
+
+    #[test]
+    fn fees_decide_between_venues() {
+        let asks = [
+            ask(ExchangeType::Deribit, 100.0, 1.0),
+            ask(ExchangeType::Binance, 100.02, 1.0),
+            ask(ExchangeType::Deribit, 100.5, 1.0),
+        ];
+        let router = SmartOrderRouter::new()
+            .with_venue(
+                ExchangeType::Deribit,
+                VenueRules {
+                    taker_fee: 0.0005,
+                    min_size: 0.0,
+                },
+            )
+            .with_venue(
+                ExchangeType::Binance,
+                VenueRules {
+                    taker_fee: 0.0001,
+                    min_size: 0.0,
+                },
+            );
+
+        let plan = router.plan_against(Instrument::BtcUsdt, &[], &asks, Side::Buy, 1.5, 101.0);
+
+        // Binance is cheaper all-in despite the higher price.
+        let binance = plan.children_for(ExchangeType::Binance).next().unwrap();
+        let deribit = plan.children_for(ExchangeType::Deribit).next().unwrap();
+        assert_eq!(binance.amount, 1.0);
+        assert_eq!(deribit.amount, 0.5);
+        assert_eq!(deribit.limit_price, 100.0);
+        assert!((deribit.fee - 0.025).abs() < 1e-9);
+        assert_eq!(plan.unfilled(), 0.0);
+
+        let all_in = plan.all_in_price().unwrap();
+        assert!((all_in - (150.02 + 0.035002) / 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fee_schedule_overrides_flat_fees() {
+        let asks = [
+            ask(ExchangeType::Deribit, 100.0, 1.0),
+            ask(ExchangeType::Binance, 100.02, 1.0),
+        ];
+        let router = SmartOrderRouter::new()
+            .with_venue(
+                ExchangeType::Deribit,
+                VenueRules {
+                    taker_fee: 0.0,
+                    min_size: 0.0,
+                },
+            )
+            .with_fees(
+                FeeSchedule::new()
+                    .with_venue(ExchangeType::Deribit, [FeeTier::new(0.0, 0.0, 0.0005)])
+                    .with_venue(ExchangeType::Binance, [FeeTier::new(0.0, 0.0, 0.0001)]),
+            );
+
+        assert_eq!(
+            router.taker_fee(ExchangeType::Deribit, Instrument::BtcUsdt),
+            0.0005
+        );
+        let plan = router.plan_against(Instrument::BtcUsdt, &[], &asks, Side::Buy, 1.0, 101.0);
+        assert_eq!(plan.children.len(), 1);
+        assert_eq!(plan.children[0].exchange, ExchangeType::Binance);
+    }
+
+    #[test]
+    fn small_shares_are_rerouted() {
+        let bids = [
+            bid(ExchangeType::Binance, 101.0, 0.05),
+            bid(ExchangeType::Deribit, 100.0, 2.0),
+            bid(ExchangeType::Deribit, 99.0, 2.0),
+        ];
+        let router = SmartOrderRouter::new().with_venue(
+            ExchangeType::Binance,
+            VenueRules {
+                taker_fee: 0.0,
+                min_size: 0.1,
+            },
+        );
+
+        let plan = router.plan_against(Instrument::BtcUsdt, &bids, &[], Side::Sell, 3.0, 99.5);
+
+        assert_eq!(plan.children.len(), 1);
+        assert_eq!(plan.children[0].exchange, ExchangeType::Deribit);
+        assert_eq!(plan.children[0].amount, 2.0);
+        assert_eq!(plan.children[0].limit_price, 100.0);
+        assert_eq!(plan.unfilled(), 1.0);
+    }
+
+    struct RecordingGateway {
+        sent: Mutex<Vec<OrderRequest>>,
+        events: broadcast::Sender<OrderEvent>,
+    }
+
+    impl OrderGateway for RecordingGateway {
+        async fn place_order(&self, request: OrderRequest) -> OrderResult {
+            let OrderType::Limit { price } = request.order_type else {
+                return Err("expected a limit order".into());
+            };
+            let order = OrderUpdate {
+                exchange: ExchangeType::Deribit,
+                order_id: "1".into(),
+                instrument_name: request.instrument.to_string(),
+                side: request.side,
+                state: OrderState::Open,
+                amount: request.amount,
+                filled_amount: 0.0,
+                price: Some(price),
+                average_price: 0.0,
+                label: None,
+                updated: Duration::ZERO,
+            };
+            self.sent.lock().unwrap().push(request);
+            Ok(OrderAck {
+                order,
+                fills: Vec::new(),
+            })
+        }
+
+        async fn edit_order(&self, _: &str, _: f64, _: f64) -> OrderResult {
+            Err("unsupported".into())
+        }
+
+        async fn cancel_order(
+            &self,
+            _: &str,
+        ) -> Result<OrderUpdate, Box<dyn std::error::Error + Send + Sync>> {
+            Err("unsupported".into())
+        }
+
+        async fn cancel_all(&self) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+            Ok(0)
+        }
+
+        fn order_events(&self) -> broadcast::Receiver<OrderEvent> {
+            self.events.subscribe()
+        }
+    }
+
+    #[tokio::test]
+    async fn executes_one_venue_of_a_plan() {
+        let asks = [
+            ask(ExchangeType::Deribit, 100.0, 1.0),
+            ask(ExchangeType::Binance, 100.0, 1.0),
+            ask(ExchangeType::Deribit, 100.5, 1.0),
+        ];
+        let plan = SmartOrderRouter::new().plan_against(
+            Instrument::BtcUsdt,
+            &[],
+            &asks,
+            Side::Buy,
+            2.5,
+            101.0,
+        );
+        let gateway = RecordingGateway {
+            sent: Mutex::new(Vec::new()),
+            events: broadcast::channel(1).0,
+        };
+
+        let results = SmartOrderRouter::execute(&plan, ExchangeType::Deribit, &gateway).await;
+
+        assert_eq!(results.len(), 1);
+        let sent = gateway.sent.lock().unwrap();
+        assert_eq!(sent[0].amount, 1.5);
+        assert_eq!(sent[0].order_type, OrderType::Limit { price: 100.5 });
+        assert_eq!(sent[0].time_in_force, TimeInForce::ImmediateOrCancel);
+    }
+}
@@ -0,0 +1,778 @@
+//! Paper trading against the aggregated book.
+//!
+//! A [`PaperExchange`] stands in for one venue. It accepts the same
+//! [`OrderRequest`]s as a real [`OrderGateway`] and fills them against that
+//! venue's levels of live or replayed [`AggregatedOrderBook`] state.
+//! Marketable orders walk the book as takers. Resting limit orders join
+//! the back of the queue at their price and fill as makers once the
+//! volume ahead of them has traded away, or straight away if the market
+//! trades through them. Orders only reach the book after the configured
+//! latency.
+//!
+//! The simulator does not remove the liquidity it takes from later book
+//! updates, so it is only realistic for sizes small next to the book.
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+
+use crate::book_management::traded_instruments::Instrument;
+use crate::book_management::{AggregatedOrderBook, Ask, Bid, Order};
+use crate::exchange_connectivity::ExchangeType;
+use crate::exchange_connectivity::clock;
+use crate::exchange_connectivity::orders::{
+    Fill, OrderAck, OrderEvent, OrderGateway, OrderRequest, OrderResult, OrderState, OrderTracker,
+    OrderType, OrderUpdate, Side, TimeInForce,
+};
+
+/// Order events buffered for each subscriber before the slowest lags.
+const EVENT_CAPACITY: usize = 1024;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SimulatorConfig {
+    /// Delay between sending an order (or edit) and it reaching the book.
+    pub latency: Duration,
+    /// Fee on resting fills as a fraction of notional.
+    pub maker_fee: f64,
+    /// Fee on aggressive fills as a fraction of notional.
+    pub taker_fee: f64,
+}
+
+/// Net position in one instrument, with its running PnL in the quote
+/// currency.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PaperPosition {
+    /// Signed base-currency position, negative when short.
+    pub quantity: f64,
+    /// Average entry price of the open position.
+    pub average_price: f64,
+    pub realised_pnl: f64,
+    pub fees: f64,
+}
+
+impl PaperPosition {
+    fn apply(&mut self, side: Side, price: f64, amount: f64, fee: f64) {
+        let signed = match side {
+            Side::Buy => amount,
+            Side::Sell => -amount,
+        };
+        self.fees += fee;
+
+        if self.quantity == 0.0 || self.quantity.signum() == signed.signum() {
+            let size = self.quantity.abs() + amount;
+            self.average_price = (self.average_price * self.quantity.abs() + price * amount) / size;
+            self.quantity += signed;
+            return;
+        }
+
+        let closed = amount.min(self.quantity.abs());
+        self.realised_pnl += closed * (price - self.average_price) * self.quantity.signum();
+        let previous = self.quantity;
+        self.quantity += signed;
+
+        if self.quantity == 0.0 {
+            self.average_price = 0.0;
+        } else if self.quantity.signum() != previous.signum() {
+            // Flipped through flat: what is left was opened at this price.
+            self.average_price = price;
+        }
+    }
+
+    pub fn unrealised_pnl(&self, mark: f64) -> f64 {
+        self.quantity * (mark - self.average_price)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pnl {
+    pub realised: f64,
+    pub unrealised: f64,
+    pub fees: f64,
+}
+
+impl Pnl {
+    pub fn total(&self) -> f64 {
+        self.realised + self.unrealised - self.fees
+    }
+}
+
+/// Where a resting order sits in the queue at its price.
+#[derive(Clone, Copy, Debug)]
+struct QueueSlot {
+    /// Displayed quantity still ahead of us.
+    ahead: f64,
+    /// Quantity displayed at our price at the last update.
+    displayed: f64,
+}
+
+#[derive(Debug)]
+struct PaperOrder {
+    id: String,
+    request: OrderRequest,
+    filled: f64,
+    notional: f64,
+    state: OrderState,
+    /// When the order (or its last edit) reaches the book.
+    active_at: Duration,
+    /// `None` until the order has reached the book.
+    queue: Option<QueueSlot>,
+    updated: Duration,
+}
+
+impl PaperOrder {
+    fn remaining(&self) -> f64 {
+        (self.request.amount - self.filled).max(0.0)
+    }
+
+    fn limit_price(&self) -> Option<f64> {
+        match self.request.order_type {
+            OrderType::Limit { price } => Some(price),
+            OrderType::Market => None,
+        }
+    }
+
+    /// Whether a counterparty quoting `price` would trade with us.
+    fn crosses(&self, price: f64) -> bool {
+        match (self.limit_price(), self.request.side) {
+            (None, _) => true,
+            (Some(limit), Side::Buy) => price <= limit,
+            (Some(limit), Side::Sell) => price >= limit,
+        }
+    }
+}
+
+/// One venue's levels of a book, best first, as (price, quantity).
+#[derive(Debug, Default)]
+struct VenueBook {
+    bids: Vec<(f64, f64)>,
+    asks: Vec<(f64, f64)>,
+}
+
+impl VenueBook {
+    fn mid(&self) -> Option<f64> {
+        Some((self.bids.first()?.0 + self.asks.first()?.0) / 2.0)
+    }
+
+    /// Levels an order on `side` would trade against.
+    fn opposite(&self, side: Side) -> &[(f64, f64)] {
+        match side {
+            Side::Buy => &self.asks,
+            Side::Sell => &self.bids,
+        }
+    }
+
+    /// Quantity displayed at `price` on `side`.
+    fn displayed(&self, side: Side, price: f64) -> f64 {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        levels
+            .iter()
+            .filter(|(level, _)| *level == price)
+            .map(|(_, quantity)| quantity)
+            .sum()
+    }
+}
+
+#[derive(Debug, Default)]
+struct SimState {
+    now: Duration,
+    books: HashMap<Instrument, VenueBook>,
+    orders: BTreeMap<String, PaperOrder>,
+    positions: BTreeMap<Instrument, PaperPosition>,
+    tracker: OrderTracker,
+    next_order: u64,
+    next_trade: u64,
+    events: Vec<OrderEvent>,
+}
+
+impl SimState {
+    fn update(&mut self, order: &PaperOrder, venue: ExchangeType) {
+        let update = OrderUpdate {
+            exchange: venue,
+            order_id: order.id.clone(),
+            instrument_name: order.request.instrument.to_string(),
+            side: order.request.side,
+            state: order.state,
+            amount: order.request.amount,
+            filled_amount: order.filled,
+            price: order.limit_price(),
+            average_price: if order.filled > 0.0 {
+                order.notional / order.filled
+            } else {
+                0.0
+            },
+            label: order.request.label.clone(),
+            updated: order.updated,
+        };
+
+        if let Some(event) = self.tracker.apply_update(update) {
+            self.events.push(event);
+        }
+    }
+
+    fn fill(
+        &mut self,
+        order: &mut PaperOrder,
+        venue: ExchangeType,
+        price: f64,
+        amount: f64,
+        fee_rate: f64,
+    ) {
+        let fee = price * amount * fee_rate;
+        order.filled += amount;
+        order.notional += price * amount;
+        order.updated = self.now;
+
+        let instrument = order.request.instrument;
+        self.positions
+            .entry(instrument)
+            .or_default()
+            .apply(order.request.side, price, amount, fee);
+
+        self.next_trade += 1;
+        let fill = Fill {
+            exchange: venue,
+            trade_id: format!("PAPER-T-{}", self.next_trade),
+            order_id: order.id.clone(),
+            instrument_name: instrument.to_string(),
+            side: order.request.side,
+            price,
+            amount,
+            fee,
+            fee_currency: instrument.quote_currency().to_string(),
+            timestamp: self.now,
+        };
+        if let Some(event) = self.tracker.apply_fill(fill) {
+            self.events.push(event);
+        }
+    }
+
+    /// Match every open order that has reached the book.
+    fn advance(&mut self, now: Duration, venue: ExchangeType, config: &SimulatorConfig) {
+        self.now = self.now.max(now);
+
+        let ids = self
+            .orders
+            .values()
+            .filter(|order| order.state.is_open() && order.active_at <= self.now)
+            .map(|order| order.id.clone())
+            .collect::<Vec<_>>();
+
+        for id in ids {
+            let Some(mut order) = self.orders.remove(&id) else {
+                continue;
+            };
+            if self.books.contains_key(&order.request.instrument) {
+                match order.queue {
+                    None => self.arrive(&mut order, venue, config),
+                    Some(slot) => self.rest(&mut order, slot, venue, config),
+                }
+                self.update(&order, venue);
+            }
+            self.orders.insert(id, order);
+        }
+    }
+
+    /// Handle an order reaching the book: take what it can, then rest or
+    /// cancel the remainder.
+    fn arrive(&mut self, order: &mut PaperOrder, venue: ExchangeType, config: &SimulatorConfig) {
+        let book = &self.books[&order.request.instrument];
+        let takeable = book
+            .opposite(order.request.side)
+            .iter()
+            .copied()
+            .take_while(|(price, _)| order.crosses(*price))
+            .collect::<Vec<_>>();
+        let available: f64 = takeable.iter().map(|(_, quantity)| quantity).sum();
+
+        order.updated = self.now;
+        if order.request.post_only && !takeable.is_empty() {
+            order.state = OrderState::Rejected;
+            return;
+        }
+        if order.request.time_in_force == TimeInForce::FillOrKill && available < order.remaining() {
+            order.state = OrderState::Cancelled;
+            return;
+        }
+
+        for (price, quantity) in takeable {
+            let amount = quantity.min(order.remaining());
+            if amount <= 0.0 {
+                break;
+            }
+            self.fill(order, venue, price, amount, config.taker_fee);
+        }
+
+        if order.remaining() <= 0.0 {
+            order.state = OrderState::Filled;
+        } else if order.request.order_type == OrderType::Market
+            || order.request.time_in_force != TimeInForce::GoodTilCancelled
+        {
+            order.state = OrderState::Cancelled;
+        } else if let Some(price) = order.limit_price() {
+            let displayed =
+                self.books[&order.request.instrument].displayed(order.request.side, price);
+            order.queue = Some(QueueSlot {
+                ahead: displayed,
+                displayed,
+            });
+        }
+    }
+
+    /// Move a resting order up its queue and fill what has traded past it.
+    fn rest(
+        &mut self,
+        order: &mut PaperOrder,
+        mut slot: QueueSlot,
+        venue: ExchangeType,
+        config: &SimulatorConfig,
+    ) {
+        let Some(price) = order.limit_price() else {
+            return;
+        };
+        let book = &self.books[&order.request.instrument];
+        let traded_through = book
+            .opposite(order.request.side)
+            .first()
+            .is_some_and(|(best, _)| order.crosses(*best));
+        let displayed = book.displayed(order.request.side, price);
+
+        let amount = if traded_through {
+            order.remaining()
+        } else {
+            // Volume leaving our level is assumed to have traded, and once
+            // the queue ahead is gone the rest trades with us.
+            slot.ahead -= (slot.displayed - displayed).max(0.0);
+            slot.displayed = displayed;
+            let amount = (-slot.ahead).max(0.0).min(order.remaining());
+            slot.ahead = slot.ahead.max(0.0);
+            amount
+        };
+
+        order.queue = Some(slot);
+        if amount > 0.0 {
+            self.fill(order, venue, price, amount, config.maker_fee);
+        }
+        if order.remaining() <= 0.0 {
+            order.state = OrderState::Filled;
+        }
+    }
+}
+
+/// A simulated venue trading against real book state.
+#[derive(Debug)]
+pub struct PaperExchange {
+    venue: ExchangeType,
+    config: SimulatorConfig,
+    state: Mutex<SimState>,
+    events: broadcast::Sender<OrderEvent>,
+}
+
+impl PaperExchange {
+    /// Simulate trading on `venue`, matching against its levels only.
+    pub fn new(venue: ExchangeType, config: SimulatorConfig) -> Self {
+        PaperExchange {
+            venue,
+            config,
+            state: Mutex::new(SimState::default()),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+
+    pub fn venue(&self) -> ExchangeType {
+        self.venue
+    }
+
+    /// Run `f` on the simulator state and publish the events it raised.
+    fn with_state<R>(&self, f: impl FnOnce(&mut SimState) -> R) -> R {
+        let (result, events) = {
+            let mut state = self.state.lock().unwrap();
+            let result = f(&mut state);
+            (result, std::mem::take(&mut state.events))
+        };
+
+        for event in events {
+            let _ = self.events.send(event);
+        }
+        result
+    }
+
+    /// Accept `request` at `now`. It reaches the book after the
+    /// configured latency. Returns the order id.
+    pub fn submit(&self, request: OrderRequest, now: Duration) -> String {
+        self.with_state(|state| {
+            state.now = state.now.max(now);
+            state.next_order += 1;
+            let order = PaperOrder {
+                id: format!("PAPER-{}", state.next_order),
+                request,
+                filled: 0.0,
+                notional: 0.0,
+                state: OrderState::Open,
+                active_at: now + self.config.latency,
+                queue: None,
+                updated: now,
+            };
+            let id = order.id.clone();
+
+            state.update(&order, self.venue);
+            state.orders.insert(id.clone(), order);
+            state.advance(now, self.venue, &self.config);
+            id
+        })
+    }
+
+    /// Change the amount and price of an open limit order. The order
+    /// loses its place in the queue.
+    pub fn edit(
+        &self,
+        order_id: &str,
+        amount: f64,
+        price: f64,
+        now: Duration,
+    ) -> Result<(), String> {
+        self.with_state(|state| {
+            let order = state
+                .orders
+                .get_mut(order_id)
+                .filter(|order| order.state.is_open())
+                .ok_or_else(|| format!("No open paper order {}", order_id))?;
+            if order.request.order_type == OrderType::Market {
+                return Err(format!("Paper order {} is a market order", order_id));
+            }
+
+            order.request.amount = amount;
+            order.request.order_type = OrderType::Limit { price };
+            order.active_at = now + self.config.latency;
+            order.queue = None;
+            order.updated = now;
+
+            let order = state.orders.remove(order_id).unwrap();
+            state.update(&order, self.venue);
+            state.orders.insert(order.id.clone(), order);
+            state.advance(now, self.venue, &self.config);
+            Ok(())
+        })
+    }
+
+    pub fn cancel(&self, order_id: &str, now: Duration) -> Result<OrderUpdate, String> {
+        self.with_state(|state| {
+            let mut order = state
+                .orders
+                .remove(order_id)
+                .ok_or_else(|| format!("No paper order {}", order_id))?;
+            if order.state.is_open() {
+                order.state = OrderState::Cancelled;
+                order.updated = now;
+            }
+
+            state.update(&order, self.venue);
+            state.orders.insert(order.id.clone(), order);
+            Ok(state.tracker.order(order_id).cloned().unwrap())
+        })
+    }
+
+    /// Feed the book of `instrument` at time `now` and match against it.
+    /// Levels from other venues are ignored.
+    pub fn on_book(&self, instrument: Instrument, bids: &[Bid], asks: &[Ask], now: Duration) {
+        let levels = |orders: &mut dyn Iterator<Item = (ExchangeType, f64, f64)>| {
+            orders
+                .filter(|(exchange, _, _)| *exchange == self.venue)
+                .map(|(_, price, quantity)| (price, quantity))
+                .collect()
+        };
+        let book = VenueBook {
+            bids: levels(&mut bids.iter().map(|b| (b.exchange(), b.price(), b.quantity()))),
+            asks: levels(&mut asks.iter().map(|a| (a.exchange(), a.price(), a.quantity()))),
+        };
+
+        self.with_state(|state| {
+            state.books.insert(instrument, book);
+            state.advance(now, self.venue, &self.config);
+        });
+    }
+
+    /// Match against the current state of a live aggregated book.
+    pub fn on_aggregated_book(&self, book: &AggregatedOrderBook) {
+        let (bids, asks) = book.levels();
+        self.on_book(book.instrument(), &bids, &asks, clock::now());
+    }
+
+    /// Let time pass without a book update.
+    pub fn advance(&self, now: Duration) {
+        self.with_state(|state| state.advance(now, self.venue, &self.config));
+    }
+
+    pub fn order(&self, order_id: &str) -> Option<OrderUpdate> {
+        self.state.lock().unwrap().tracker.order(order_id).cloned()
+    }
+
+    pub fn fills(&self, order_id: &str) -> Vec<Fill> {
+        self.state.lock().unwrap().tracker.fills(order_id).to_vec()
+    }
+
+    pub fn open_orders(&self) -> Vec<OrderUpdate> {
+        self.state
+            .lock()
+            .unwrap()
+            .tracker
+            .open_orders()
+            .cloned()
+            .collect()
+    }
+
+    pub fn positions(&self) -> BTreeMap<Instrument, PaperPosition> {
+        self.state.lock().unwrap().positions.clone()
+    }
+
+    /// PnL in `instrument`, marking open positions to the venue's mid.
+    pub fn pnl(&self, instrument: Instrument) -> Pnl {
+        let state = self.state.lock().unwrap();
+        let position = state
+            .positions
+            .get(&instrument)
+            .copied()
+            .unwrap_or_default();
+        let mark = state.books.get(&instrument).and_then(VenueBook::mid);
+
+        Pnl {
+            realised: position.realised_pnl,
+            unrealised: mark.map_or(0.0, |mark| position.unrealised_pnl(mark)),
+            fees: position.fees,
+        }
+    }
+
+    fn ack(&self, order_id: &str) -> OrderResult {
+        let order = self
+            .order(order_id)
+            .ok_or_else(|| format!("No paper order {}", order_id))?;
+        Ok(OrderAck {
+            order,
+            fills: self.fills(order_id),
+        })
+    }
+}
+
+impl OrderGateway for PaperExchange {
+    async fn place_order(&self, request: OrderRequest) -> OrderResult {
+        let id = self.submit(request, clock::now());
+        tokio::time::sleep(self.config.latency).await;
+        self.advance(clock::now());
+        self.ack(&id)
+    }
+
+    async fn edit_order(&self, order_id: &str, amount: f64, price: f64) -> OrderResult {
+        self.edit(order_id, amount, price, clock::now())?;
+        tokio::time::sleep(self.config.latency).await;
+        self.advance(clock::now());
+        self.ack(order_id)
+    }
+
+    async fn cancel_order(
+        &self,
+        order_id: &str,
+    ) -> Result<OrderUpdate, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.cancel(order_id, clock::now())?)
+    }
+
+    async fn cancel_all(&self) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let open = self.open_orders();
+        for order in &open {
+            self.cancel(&order.order_id, clock::now())?;
+        }
+        Ok(open.len() as u64)
+    }
+
+    fn order_events(&self) -> broadcast::Receiver<OrderEvent> {
+        self.events.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{PaperExchange, PaperPosition, SimulatorConfig};
+    use crate::book_management::traded_instruments::Instrument;
+    use crate::book_management::{Ask, Bid, Order};
+    use crate::exchange_connectivity::ExchangeType;
+    use crate::exchange_connectivity::orders::{
+        OrderEvent, OrderGateway, OrderRequest, OrderState, Side, TimeInForce,
+    };
+
+    const VENUE: ExchangeType = ExchangeType::Deribit;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn book(bids: &[(f64, f64)], asks: &[(f64, f64)]) -> (Vec<Bid>, Vec<Ask>) {
+        (
+            bids.iter()
+                .map(|(p, q)| Bid::new(Instrument::BtcUsdt, VENUE, *q, *p))
+                .chain([Bid::new(
+                    Instrument::BtcUsdt,
+                    ExchangeType::Binance,
+                    50.0,
+                    1000.0,
+                )])
+                .collect(),
+            asks.iter()
+                .map(|(p, q)| Ask::new(Instrument::BtcUsdt, VENUE, *q, *p))
+                .collect(),
+        )
+    }
+
+    fn feed(exchange: &PaperExchange, bids: &[(f64, f64)], asks: &[(f64, f64)], now: u64) {
+        let (bids, asks) = book(bids, asks);
+        exchange.on_book(Instrument::BtcUsdt, &bids, &asks, ms(now));
+    }
+
+    #[test]
+    fn market_orders_walk_the_book_after_latency() {
+        let exchange = PaperExchange::new(
+            VENUE,
+            SimulatorConfig {
+                latency: ms(10),
+                maker_fee: 0.0,
+                taker_fee: 0.001,
+            },
+        );
+        feed(&exchange, &[(99.0, 1.0)], &[(100.0, 1.0), (101.0, 2.0)], 0);
+
+        let id = exchange.submit(
+            OrderRequest::market(Instrument::BtcUsdt, Side::Buy, 2.0),
+            ms(0),
+        );
+        assert_eq!(exchange.order(&id).unwrap().filled_amount, 0.0);
+
+        // The book moved before the order arrived.
+        feed(&exchange, &[(99.0, 1.0)], &[(100.5, 1.0), (101.0, 2.0)], 10);
+
+        let order = exchange.order(&id).unwrap();
+        assert_eq!(order.state, OrderState::Filled);
+        assert_eq!(order.average_price, (100.5 + 101.0) / 2.0);
+        let fills = exchange.fills(&id);
+        assert_eq!(fills.len(), 2);
+        assert!((fills[0].fee - 0.1005).abs() < 1e-9);
+        assert_eq!(fills[0].fee_currency, "USDT");
+
+        let position = exchange.positions()[&Instrument::BtcUsdt];
+        assert_eq!(position.quantity, 2.0);
+        let pnl = exchange.pnl(Instrument::BtcUsdt);
+        assert!((pnl.unrealised - 2.0 * (99.75 - 100.75)).abs() < 1e-9);
+        assert!((pnl.total() - (pnl.unrealised - 0.2015)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn resting_orders_wait_for_their_queue() {
+        let exchange = PaperExchange::new(VENUE, SimulatorConfig::default());
+        feed(&exchange, &[(99.0, 3.0)], &[(100.0, 1.0)], 0);
+
+        let id = exchange.submit(
+            OrderRequest::limit(Instrument::BtcUsdt, Side::Buy, 2.0, 99.0),
+            ms(0),
+        );
+        assert_eq!(exchange.order(&id).unwrap().state, OrderState::Open);
+
+        // 2 of the 3 ahead of us trade, then someone joins behind us.
+        feed(&exchange, &[(99.0, 1.0)], &[(100.0, 1.0)], 1);
+        feed(&exchange, &[(99.0, 4.0)], &[(100.0, 1.0)], 2);
+        assert_eq!(exchange.order(&id).unwrap().filled_amount, 0.0);
+
+        // 1.5 leaves the level: 1 was ahead of us, 0.5 fills us.
+        feed(&exchange, &[(99.0, 2.5)], &[(100.0, 1.0)], 3);
+        assert_eq!(exchange.order(&id).unwrap().filled_amount, 0.5);
+
+        // The market trades through our price and fills the rest.
+        feed(&exchange, &[(98.0, 1.0)], &[(99.0, 1.0)], 4);
+        let order = exchange.order(&id).unwrap();
+        assert_eq!(order.state, OrderState::Filled);
+        assert_eq!(order.average_price, 99.0);
+    }
+
+    #[test]
+    fn immediate_orders_do_not_rest() {
+        let exchange = PaperExchange::new(VENUE, SimulatorConfig::default());
+        feed(&exchange, &[(99.0, 1.0)], &[(100.0, 1.0)], 0);
+
+        let fok = exchange.submit(
+            OrderRequest::limit(Instrument::BtcUsdt, Side::Buy, 2.0, 100.0)
+                .with_time_in_force(TimeInForce::FillOrKill),
+            ms(0),
+        );
+        assert_eq!(exchange.order(&fok).unwrap().state, OrderState::Cancelled);
+        assert!(exchange.fills(&fok).is_empty());
+
+        let ioc = exchange.submit(
+            OrderRequest::limit(Instrument::BtcUsdt, Side::Buy, 2.0, 100.0)
+                .with_time_in_force(TimeInForce::ImmediateOrCancel),
+            ms(0),
+        );
+        let order = exchange.order(&ioc).unwrap();
+        assert_eq!(order.state, OrderState::Cancelled);
+        assert_eq!(order.filled_amount, 1.0);
+
+        let post_only = exchange.submit(
+            OrderRequest::limit(Instrument::BtcUsdt, Side::Sell, 1.0, 99.0).post_only(),
+            ms(0),
+        );
+        assert_eq!(
+            exchange.order(&post_only).unwrap().state,
+            OrderState::Rejected
+        );
+    }
+
+    #[test]
+    fn positions_realise_pnl_when_reduced() {
+        let mut position = PaperPosition::default();
+        position.apply(Side::Buy, 100.0, 2.0, 0.0);
+        position.apply(Side::Buy, 110.0, 2.0, 0.0);
+        assert_eq!(position.average_price, 105.0);
+
+        position.apply(Side::Sell, 120.0, 3.0, 1.0);
+        assert_eq!(position.quantity, 1.0);
+        assert_eq!(position.realised_pnl, 45.0);
+
+        position.apply(Side::Sell, 90.0, 2.0, 0.0);
+        assert_eq!(position.quantity, -1.0);
+        assert_eq!(position.realised_pnl, 30.0);
+        assert_eq!(position.average_price, 90.0);
+        assert_eq!(position.fees, 1.0);
+    }
+
+    #[tokio::test]
+    async fn works_as_an_order_gateway() {
+        let exchange = PaperExchange::new(VENUE, SimulatorConfig::default());
+        let mut events = exchange.order_events();
+        feed(&exchange, &[(99.0, 1.0)], &[(100.0, 1.0)], 0);
+
+        let ack = exchange
+            .place_order(OrderRequest::limit(
+                Instrument::BtcUsdt,
+                Side::Buy,
+                1.0,
+                98.0,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(ack.order.state, OrderState::Open);
+
+        let edited = exchange
+            .edit_order(&ack.order.order_id, 0.5, 100.0)
+            .await
+            .unwrap();
+        assert_eq!(edited.order.state, OrderState::Filled);
+        assert_eq!(edited.fills.len(), 1);
+        assert_eq!(exchange.cancel_all().await.unwrap(), 0);
+
+        let mut filled = false;
+        while let Ok(event) = events.try_recv() {
+            filled |= matches!(event, OrderEvent::Filled(_));
+        }
+        assert!(filled);
+    }
+}
@@ -4,6 +4,7 @@ use std::{collections::VecDeque, fmt::Debug};
 /// to hold ordered data in a map-like K/V fashion.
 ///
 /// Capacity is bounded below by `1` and defaulted to 100K.
+#[derive(Debug)]
 pub struct TimeSeriesArray<K, V>
 where
     K: Copy + Ord + Debug,
@@ -18,6 +19,16 @@ pub enum TimeSeriesError {
     KeyExists,
 }
 
+impl<K, V> Default for TimeSeriesArray<K, V>
+where
+    K: Copy + Ord + Debug,
+    V: Clone + Debug,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<K, V> TimeSeriesArray<K, V>
 where
     K: Copy + Ord + Debug,
@@ -46,10 +57,10 @@ where
             self.remove_element();
         }
 
-        if let Some(most_recent) = self.keys.back() {
-            if key < *most_recent {
-                return self.insert_not_ordered(key, val);
-            }
+        if let Some(most_recent) = self.keys.back()
+            && key < *most_recent
+        {
+            return self.insert_not_ordered(key, val);
         }
 
         self.keys.push_back(key);
@@ -59,7 +70,7 @@ where
 
     /// Returns the value corresponding to an exact key, or None.
     pub fn find_key(&self, key: &K) -> Option<&V> {
-        match self.keys.binary_search(&key) {
+        match self.keys.binary_search(key) {
             Ok(i) => Some(&self.values[i]),
             Err(_) => None,
         }
@@ -68,7 +79,7 @@ where
     /// Returns the last value equal to or less than some key, or
     /// none if there is no such key present satisfying.
     pub fn last_value_for_key(&self, key: &K) -> Option<(K, &V)> {
-        match self.get_index_last_value_for_key(&key) {
+        match self.get_index_last_value_for_key(key) {
             None => None,
             Some(i) => Some((self.keys[i], &self.values[i])),
         }
@@ -93,7 +104,7 @@ where
 
     #[inline]
     fn get_index_last_value_for_key(&self, key: &K) -> Option<usize> {
-        match self.keys.binary_search(&key) {
+        match self.keys.binary_search(key) {
             Ok(i) => Some(i),
             Err(i) => match i {
                 0 => None,
@@ -105,7 +116,7 @@ where
     #[inline]
     fn insert_not_ordered(&mut self, key: K, val: &V) -> Result<(), TimeSeriesError> {
         match self.keys.binary_search(&key) {
-            Ok(_) => return Err(TimeSeriesError::KeyExists),
+            Ok(_) => Err(TimeSeriesError::KeyExists),
             Err(pos) => {
                 self.keys.insert(pos, key);
                 self.values.insert(pos, val.clone());
@@ -152,7 +163,7 @@ mod tests {
     fn create_test_array(vec: &Vec<(std::time::Duration, u64)>) -> TimeSeriesArray<Duration, u64> {
         let mut arr = TimeSeriesArray::new();
         for (time, val) in vec {
-            match arr.insert(time.clone(), val) {
+            match arr.insert(*time, val) {
                 Ok(_) => (),
                 Err(_) => panic!("didnt expect test insertion failure"),
             }
@@ -209,12 +220,9 @@ mod tests {
 
         // expect [50-99]
         let iter = arr.range_query(Duration::from_secs(49), Duration::from_secs(1000));
-        let mut expected = 50;
-
-        for (time, val) in iter {
+        for (expected, (time, val)) in (50..).zip(iter) {
             assert_eq!(*time, Duration::from_secs(expected));
             assert_eq!(*val, expected + 1);
-            expected += 1;
         }
     }
 }
@@ -1,11 +1,9 @@
-use std::{
-    sync::{Arc, atomic::Ordering},
-    time::Duration,
-};
+use std::time::Duration;
 
 use market_aggregator::{
-    book_management::{AggregatedOrderBook, traded_instruments::Instrument},
-    exchange_connectivity::{Exchange, ExchangeKeys, ExchangeType},
+    aggregator::MarketAggregator,
+    book_management::traded_instruments::Instrument,
+    exchange_connectivity::{ExchangeKeys, ExchangeType},
     gui::MyApp,
 };
 
@@ -16,30 +14,21 @@ async fn create_book_aggregation_update() {
 
     // market_aggregator::logging_config();
 
-    let (deribit, deribit_keep_alive) = Exchange::connect(ExchangeType::Deribit, &keys)
-        .await
-        .unwrap();
-
-    // let (binance, binance_keep_alive) = Exchange::connect(ExchangeType::Binance, &keys)
-    //     .await
-    //     .unwrap();
-
-    let exchanges = Arc::new(vec![deribit]);
-
-    let book_collection = vec![
-        Arc::new(AggregatedOrderBook::new(Instrument::BtcUsdt, &exchanges)),
-        Arc::new(AggregatedOrderBook::new(Instrument::EthUsdc, &exchanges)),
-        Arc::new(AggregatedOrderBook::new(Instrument::EthBtc, &exchanges)),
-    ];
+    // Add ExchangeType::Binance here if the binance api works for you.
+    let aggregator = MarketAggregator::connect(
+        &[ExchangeType::Deribit],
+        &[Instrument::BtcUsdt, Instrument::EthUsdc, Instrument::EthBtc],
+        &keys,
+    )
+    .await
+    .unwrap();
 
     println!("before");
-    let _my_app = MyApp::new(book_collection.into_iter());
+    let _my_app = MyApp::new(aggregator.books().iter().cloned(), aggregator.tasks());
     println!("after");
 
     // it refreshes automatically in this time
     tokio::time::sleep(Duration::from_millis(100)).await;
 
-    deribit_keep_alive.store(false, Ordering::Relaxed);
-    // binance_keep_alive.store(false, Ordering::Relaxed);
-    return;
+    aggregator.shutdown().await;
 }